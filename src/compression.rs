@@ -0,0 +1,67 @@
+use std::io::Write;
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+/// Ratio of the length of `text` to its length compressed with zlib at the default level.
+///
+/// This is the measure OpenAI's Whisper uses to catch a decoder stuck repeating itself:
+/// ordinary speech stays well below 2.4, a loop goes far above it.
+/// The text is compressed with the pure Rust backend of `flate2`. For ordinary text that gives
+/// the same length as Python's `zlib.compress`, highly repetitive text comes out somewhat longer
+/// than with zlib but still far above the threshold.
+///
+/// # Examples
+/// ```
+/// # use whisper_rs::compression_ratio;
+/// assert!(compression_ratio(b" And so my fellow Americans, ask not what your country can do for you.") < 2.4);
+/// assert!(compression_ratio(" Thank you.".repeat(20).as_bytes()) > 2.4);
+/// ```
+pub fn compression_ratio(text: &[u8]) -> f32 {
+    text.len() as f32 / zlib_len(text) as f32
+}
+
+/// Length of `text` compressed with zlib at the default level, header and checksum included.
+fn zlib_len(text: &[u8]) -> usize {
+    let mut encoder = ZlibEncoder::new(
+        Vec::with_capacity(text.len() / 2 + 16),
+        Compression::default(),
+    );
+    // writing to a Vec cannot fail
+    encoder
+        .write_all(text)
+        .and_then(|()| encoder.finish())
+        .map_or(text.len() + 11, |compressed| compressed.len())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_zlib_len_matches_zlib() {
+        // lengths from Python's zlib.compress
+        for (text, expected) in [
+            (&b""[..], 8),
+            (b"a", 9),
+            (b"hello hello hello hello", 16),
+            (b"the cat sat on the mat with the hat", 35),
+            (b" And so my fellow Americans, ask not what your country can do for you, ask what you can do for your country.", 79),
+        ] {
+            assert_eq!(zlib_len(text), expected, "{:?}", String::from_utf8_lossy(text));
+        }
+    }
+
+    #[test]
+    fn test_compression_ratio_flags_loops() {
+        let normal = compression_ratio(b" And so my fellow Americans, ask not what your country can do for you, ask what you can do for your country.");
+        assert!(normal < 1.5);
+        for looping in [
+            " Thank you.".repeat(12),
+            " Thank you.".repeat(30),
+            "abc".repeat(107),
+        ] {
+            assert!(compression_ratio(looping.as_bytes()) > 2.4, "{looping:?}");
+        }
+    }
+}
@@ -34,6 +34,8 @@ pub enum WhisperError {
     },
     /// A null byte was detected in a user-provided string.
     NullByteInString { idx: usize },
+    /// A user-provided regular expression is not valid, the problem starts at byte `idx`.
+    InvalidRegex { idx: usize },
     /// Whisper returned a null pointer.
     NullPointer,
     /// Generic whisper error. Varies depending on the function.
@@ -48,6 +50,28 @@ pub enum WhisperError {
     InputOutputLengthMismatch { input_len: usize, output_len: usize },
     /// Input slice was not an even number of samples.
     HalfSampleMissing(usize),
+    /// The language id is not one whisper.cpp knows about.
+    InvalidLanguageId(c_int),
+    /// [`DtwMode::Auto`](crate::DtwMode::Auto) found no alignment head preset for the model.
+    UnknownDtwPreset {
+        n_audio_layer: c_int,
+        n_text_layer: c_int,
+        n_vocab: c_int,
+        n_mels: c_int,
+    },
+    /// [`DtwMode::Auto`](crate::DtwMode::Auto) cannot tell which of two presets fits the model,
+    /// because both models have the same dimensions.
+    AmbiguousDtwPreset(crate::DtwModelPreset, crate::DtwModelPreset),
+    /// Custom DTW alignment heads were given, but the list is empty.
+    NoAlignmentHeads,
+    /// A custom DTW alignment head does not exist in the model.
+    InvalidAlignmentHead {
+        index: usize,
+        n_text_layer: c_int,
+        n_head: c_int,
+        model_n_text_layer: c_int,
+        model_n_text_head: c_int,
+    },
 }
 
 impl From<Utf8Error> for WhisperError {
@@ -104,6 +128,11 @@ impl std::fmt::Display for WhisperError {
                 "A null byte was detected in a user-provided string. Index: {}",
                 idx
             ),
+            InvalidRegex { idx } => write!(
+                f,
+                "A user-provided regular expression is not valid. Index: {}",
+                idx
+            ),
             NullPointer => write!(f, "Whisper returned a null pointer."),
             InvalidText => write!(
                 f,
@@ -134,6 +163,37 @@ impl std::fmt::Display for WhisperError {
                     size + 1
                 )
             }
+            InvalidLanguageId(id) => write!(f, "Invalid language id: {}.", id),
+            UnknownDtwPreset {
+                n_audio_layer,
+                n_text_layer,
+                n_vocab,
+                n_mels,
+            } => write!(
+                f,
+                "No DTW alignment head preset fits a model with {} audio layers, {} text layers, \
+                 {} tokens and {} mel bands. Use DtwMode::TopMost or DtwMode::Custom instead.",
+                n_audio_layer, n_text_layer, n_vocab, n_mels
+            ),
+            AmbiguousDtwPreset(a, b) => write!(
+                f,
+                "The model could be {:?} or {:?}, which need different DTW alignment heads. \
+                 Pick one with DtwMode::ModelPreset.",
+                a, b
+            ),
+            NoAlignmentHeads => write!(f, "The list of custom DTW alignment heads is empty."),
+            InvalidAlignmentHead {
+                index,
+                n_text_layer,
+                n_head,
+                model_n_text_layer,
+                model_n_text_head,
+            } => write!(
+                f,
+                "DTW alignment head {} (layer {}, head {}) is out of range, \
+                 the model has {} text layers of {} heads.",
+                index, n_text_layer, n_head, model_n_text_layer, model_n_text_head
+            ),
         }
     }
 }
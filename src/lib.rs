@@ -10,33 +10,58 @@
 pub mod vulkan;
 
 mod common_logging;
+mod compression;
 mod error;
 mod ggml_logging_hook;
 mod standalone;
 mod utilities;
+mod whisper_command;
 mod whisper_ctx;
 mod whisper_ctx_wrapper;
 mod whisper_grammar;
+mod whisper_hotwords;
+mod whisper_language;
 mod whisper_logging_hook;
+mod whisper_logit_bias;
 mod whisper_params;
+mod whisper_speech_snap;
 mod whisper_state;
+mod whisper_subtitles;
 mod whisper_vad;
 
 pub use common_logging::GGMLLogLevel;
+pub use compression::compression_ratio;
 pub use error::WhisperError;
 pub use standalone::*;
 pub use utilities::*;
+pub use whisper_command::{CommandMatch, CommandRecognizer};
 pub use whisper_ctx::DtwMode;
 pub use whisper_ctx::DtwModelPreset;
 pub use whisper_ctx::DtwParameters;
 pub use whisper_ctx::WhisperContextParameters;
 use whisper_ctx::WhisperInnerContext;
 pub use whisper_ctx_wrapper::WhisperContext;
-pub use whisper_grammar::{WhisperGrammarElement, WhisperGrammarElementType};
+pub use whisper_grammar::{
+    Grammar, GrammarParseError, WhisperGrammar, WhisperGrammarElement, WhisperGrammarElementType,
+};
+pub use whisper_hotwords::HotwordBoost;
+pub use whisper_language::{Language, LanguageParseError};
+pub use whisper_logit_bias::LogitBias;
 pub use whisper_params::{FullParams, SamplingStrategy, SegmentCallbackData};
 #[cfg(feature = "raw-api")]
 pub use whisper_rs_sys;
-pub use whisper_state::{WhisperSegment, WhisperState, WhisperStateSegmentIterator, WhisperToken};
+pub use whisper_speech_snap::SpeechSnapper;
+pub use whisper_state::{
+    AlignedSegment, AlignedWord, BilingualSegment, CheckedSegment, Decoder, DecoderParams,
+    FallbackCandidate, FallbackDecision, FallbackPolicy, Greedy, Hallucination,
+    HallucinationParams, Hypothesis, LanguageDetection, LanguageDetectionParams, LanguageSegment,
+    Nucleus, Sampler, SegmentFilter, SegmentMetrics, ThresholdFallback, TokenTimeSource, TopK,
+    TranscribedSegment, TranscribedWindow, VadMappedSegment, VadSegmentMap, WhisperSegment,
+    WhisperState, WhisperStateSegmentIterator, WhisperToken,
+};
+pub use whisper_subtitles::{
+    parse_srt, resync_subtitles, write_srt, ResyncedSubtitles, SubtitleCue, SubtitleParseError,
+};
 pub use whisper_vad::*;
 
 pub type WhisperSysContext = whisper_rs_sys::whisper_context;
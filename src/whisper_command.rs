@@ -0,0 +1,231 @@
+use crate::{Decoder, DecoderParams, WhisperContext, WhisperError, WhisperState, WhisperTokenId};
+
+/// Recognizes which one of a fixed list of commands was spoken, like `whisper.cpp`'s `command` example.
+///
+/// Instead of transcribing freely, every command is forced through the decoder
+/// and scored by how likely the model finds it, end of text included.
+/// This cannot produce anything that is not on the list, and commands sharing a prefix
+/// share the work of decoding it.
+///
+/// # Examples
+/// ```no_run
+/// # use whisper_rs::{CommandRecognizer, DecoderParams, WhisperContext, WhisperContextParameters};
+/// # let ctx = WhisperContext::new_with_params("model.bin", WhisperContextParameters::default()).unwrap();
+/// # let audio = vec![0.0f32; 16000];
+/// let recognizer = CommandRecognizer::new(&ctx, &["lights on", "lights off", "play music"]).unwrap();
+///
+/// let mut state = ctx.create_state().unwrap();
+/// state.pcm_to_mel(&audio, 1).unwrap();
+/// state.encode(0, 1).unwrap();
+/// let matches = recognizer.recognize(&mut state, &DecoderParams::default()).unwrap();
+/// if matches[0].probability > 0.8 {
+///     println!("heard {:?}", matches[0].text);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CommandRecognizer {
+    commands: Vec<(String, Vec<WhisperTokenId>)>,
+    prompt_tokens: Vec<WhisperTokenId>,
+    token_eot: WhisperTokenId,
+}
+
+/// How well one command of a [`CommandRecognizer`] matches the audio.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandMatch {
+    /// Index of the command in the list the recognizer was created with.
+    pub index: usize,
+    /// The command as given.
+    pub text: String,
+    /// Log probability of the model producing exactly this command.
+    pub logprob: f32,
+    /// Probability of this command relative to the others in the list. These add up to 1.
+    pub probability: f32,
+}
+
+impl CommandRecognizer {
+    /// Tokenize `commands` with [`WhisperContext::tokenize`].
+    ///
+    /// The commands are also used as the decoder prompt, which makes the model expect them.
+    ///
+    /// # Errors
+    /// * [`WhisperError::NullByteInString`] if a command contains a null byte.
+    /// * [`WhisperError::InvalidText`] if there are no commands or one could not be tokenized or is empty.
+    pub fn new(ctx: &WhisperContext, commands: &[&str]) -> Result<Self, WhisperError> {
+        if commands.is_empty() {
+            return Err(WhisperError::InvalidText);
+        }
+        let commands = commands
+            .iter()
+            .map(|&command| {
+                // whisper puts a space before every word, including the first
+                let text = format!(" {}", command.trim());
+                let tokens = ctx.tokenize(&text, text.len())?;
+                if tokens.is_empty() {
+                    return Err(WhisperError::InvalidText);
+                }
+                Ok((command.to_string(), tokens))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let prompt = commands
+            .iter()
+            .map(|(command, _)| command.trim())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let prompt_tokens = ctx.tokenize(&prompt, prompt.len())?;
+        Ok(Self {
+            commands,
+            prompt_tokens,
+            token_eot: ctx.token_eot(),
+        })
+    }
+
+    /// The commands and their tokens.
+    pub fn commands(&self) -> impl Iterator<Item = (&str, &[WhisperTokenId])> {
+        self.commands
+            .iter()
+            .map(|(text, tokens)| (text.as_str(), tokens.as_slice()))
+    }
+
+    /// Score every command against the audio last encoded into `state`, best match first.
+    ///
+    /// `params` chooses the language and threads. Timestamps are always turned off,
+    /// and if `params` has no prompt tokens the list of commands is used.
+    ///
+    /// # Errors
+    /// Whatever [`Decoder::new`] or [`Decoder::logprobs`] return.
+    pub fn recognize(
+        &self,
+        state: &mut WhisperState,
+        params: &DecoderParams,
+    ) -> Result<Vec<CommandMatch>, WhisperError> {
+        let mut params = params.clone();
+        params.timestamps(false).suppress_blank(false);
+        if params.prompt_tokens.is_empty() {
+            params.prompt_tokens(&self.prompt_tokens);
+        }
+        let mut decoder = Decoder::new(state, &params)?;
+
+        // visiting the commands in token order keeps shared prefixes in the KV cache
+        let mut order: Vec<usize> = (0..self.commands.len()).collect();
+        order.sort_by(|&a, &b| self.commands[a].1.cmp(&self.commands[b].1));
+        let mut logprobs = vec![0.0; self.commands.len()];
+        for i in order {
+            let tokens = &self.commands[i].1;
+            let common = decoder
+                .tokens()
+                .iter()
+                .zip(tokens)
+                .take_while(|(a, b)| a == b)
+                .count();
+            decoder.truncate(common);
+            for &token in &tokens[common..] {
+                decoder.logprobs()?;
+                decoder.push(token);
+            }
+            logprobs[i] = decoder.sum_logprob() + decoder.logprobs()?[self.token_eot as usize];
+        }
+        Ok(self.rank(&logprobs))
+    }
+
+    fn rank(&self, logprobs: &[f32]) -> Vec<CommandMatch> {
+        let max = logprobs.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let sum: f32 = logprobs.iter().map(|&logprob| (logprob - max).exp()).sum();
+        let mut matches: Vec<_> = self
+            .commands
+            .iter()
+            .zip(logprobs)
+            .enumerate()
+            .map(|(index, ((text, _), &logprob))| CommandMatch {
+                index,
+                text: text.clone(),
+                logprob,
+                probability: (logprob - max).exp() / sum,
+            })
+            .collect();
+        matches.sort_by(|a, b| b.logprob.total_cmp(&a.logprob));
+        matches
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn recognizer(commands: &[(&str, &[WhisperTokenId])]) -> CommandRecognizer {
+        CommandRecognizer {
+            commands: commands
+                .iter()
+                .map(|&(text, tokens)| (text.to_string(), tokens.to_vec()))
+                .collect(),
+            prompt_tokens: Vec::new(),
+            token_eot: 100,
+        }
+    }
+
+    #[test]
+    fn test_rank() {
+        let recognizer = recognizer(&[("stop", &[1]), ("go", &[2]), ("go left", &[2, 3])]);
+        let matches = recognizer.rank(&[-3.0, -0.5, -2.0]);
+        let order: Vec<_> = matches.iter().map(|m| m.text.as_str()).collect();
+        assert_eq!(order, ["go", "go left", "stop"]);
+        assert_eq!(matches[0].index, 1);
+        let total: f32 = matches.iter().map(|m| m.probability).sum();
+        assert!((total - 1.0).abs() < 1e-6);
+        assert!((matches[0].probability / matches[1].probability - 1.5f32.exp()).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_rank_impossible_command() {
+        let recognizer = recognizer(&[("stop", &[1]), ("go", &[2])]);
+        let matches = recognizer.rank(&[f32::NEG_INFINITY, -1.0]);
+        assert_eq!(matches[0].probability, 1.0);
+        assert_eq!(matches[1].probability, 0.0);
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "test-with-tiny-model")]
+mod test_with_tiny_model {
+    use super::*;
+    use crate::WhisperContextParameters;
+    const MODEL_PATH: &str = "./sys/whisper.cpp/models/ggml-tiny.en.bin";
+    const SAMPLE_PATH: &str = "./sys/whisper.cpp/samples/jfk.wav";
+
+    // These tests expect that the tiny.en model has been downloaded
+    // using the script `sys/whisper.cpp/models/download-ggml-model.sh tiny.en`
+
+    #[test]
+    fn test_recognizes_spoken_command() {
+        let ctx = WhisperContext::new_with_params(MODEL_PATH, WhisperContextParameters::default())
+            .expect("Download the ggml-tiny.en model using 'sys/whisper.cpp/models/download-ggml-model.sh tiny.en'");
+        let samples: Vec<i16> = hound::WavReader::open(SAMPLE_PATH)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(Result::unwrap)
+            .collect();
+        let mut audio = vec![0.0f32; samples.len()];
+        crate::convert_integer_to_float_audio(&samples, &mut audio).unwrap();
+
+        let recognizer = CommandRecognizer::new(
+            &ctx,
+            &[
+                "turn on the lights",
+                "And so my fellow Americans, ask not what your country can do for you, ask what you can do for your country.",
+                "And so my fellow Americans",
+            ],
+        )
+        .unwrap();
+        let mut state = ctx.create_state().unwrap();
+        state.pcm_to_mel(&audio, 1).unwrap();
+        state.encode(0, 1).unwrap();
+        let matches = recognizer
+            .recognize(&mut state, &DecoderParams::default())
+            .unwrap();
+        assert_eq!(matches[0].index, 1);
+        // the whole sentence must win clearly, not just by a rounding error
+        assert!(matches[0].logprob - matches[1].logprob > 5.0);
+        assert!(matches[0].probability > 0.99);
+        // the lights were never mentioned
+        assert_eq!(matches[2].index, 0);
+    }
+}
@@ -2,6 +2,7 @@ use crate::error::WhisperError;
 use crate::WhisperTokenId;
 use std::borrow::Cow;
 use std::ffi::{c_int, CStr, CString};
+use std::io::Read;
 
 /// Safe Rust wrapper around a Whisper context.
 ///
@@ -11,6 +12,9 @@ use std::ffi::{c_int, CStr, CString};
 #[derive(Debug)]
 pub struct WhisperInnerContext {
     pub(crate) ctx: *mut whisper_rs_sys::whisper_context,
+    /// Custom DTW alignment heads. whisper.cpp keeps a pointer to them and reads them
+    /// again for every new state, so they have to live as long as `ctx`.
+    _aheads: Option<Box<[whisper_rs_sys::whisper_ahead]>>,
 }
 
 impl WhisperInnerContext {
@@ -30,16 +34,17 @@ impl WhisperInnerContext {
         parameters: WhisperContextParameters,
     ) -> Result<Self, WhisperError> {
         let path_cstr = CString::new(path)?;
+        let (params, aheads) = parameters.to_c_struct(|| ModelDims::from_file(path))?;
         let ctx = unsafe {
-            whisper_rs_sys::whisper_init_from_file_with_params_no_state(
-                path_cstr.as_ptr(),
-                parameters.to_c_struct(),
-            )
+            whisper_rs_sys::whisper_init_from_file_with_params_no_state(path_cstr.as_ptr(), params)
         };
         if ctx.is_null() {
             Err(WhisperError::InitError)
         } else {
-            Ok(Self { ctx })
+            Ok(Self {
+                ctx,
+                _aheads: aheads,
+            })
         }
     }
 
@@ -57,17 +62,21 @@ impl WhisperInnerContext {
         buffer: &[u8],
         parameters: WhisperContextParameters,
     ) -> Result<Self, WhisperError> {
+        let (params, aheads) = parameters.to_c_struct(|| ModelDims::from_bytes(buffer))?;
         let ctx = unsafe {
             whisper_rs_sys::whisper_init_from_buffer_with_params_no_state(
                 buffer.as_ptr() as _,
                 buffer.len(),
-                parameters.to_c_struct(),
+                params,
             )
         };
         if ctx.is_null() {
             Err(WhisperError::InitError)
         } else {
-            Ok(Self { ctx })
+            Ok(Self {
+                ctx,
+                _aheads: aheads,
+            })
         }
     }
 
@@ -479,7 +488,21 @@ impl<'a> WhisperContextParameters<'a> {
         self
     }
 
-    fn to_c_struct(&self) -> whisper_rs_sys::whisper_context_params {
+    /// The parameters for whisper.cpp. `model` is only read if the DTW mode depends on the model.
+    ///
+    /// Custom alignment heads are copied into the returned box, which the returned struct
+    /// points into and which must be kept for as long as the context.
+    #[allow(clippy::type_complexity)]
+    fn to_c_struct(
+        &self,
+        model: impl FnOnce() -> Result<ModelDims, WhisperError>,
+    ) -> Result<
+        (
+            whisper_rs_sys::whisper_context_params,
+            Option<Box<[whisper_rs_sys::whisper_ahead]>>,
+        ),
+        WhisperError,
+    > {
         let dtw_token_timestamps = !matches!(self.dtw_parameters.mode, DtwMode::None);
         let mut dtw_aheads_preset =
             whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_NONE;
@@ -488,6 +511,7 @@ impl<'a> WhisperContextParameters<'a> {
             n_heads: 0,
             heads: std::ptr::null(),
         };
+        let mut owned_aheads: Option<Box<[whisper_rs_sys::whisper_ahead]>> = None;
 
         match &self.dtw_parameters.mode {
             DtwMode::None => {}
@@ -497,67 +521,27 @@ impl<'a> WhisperContextParameters<'a> {
                 dtw_n_top = *n_top;
             }
             DtwMode::Custom { aheads } => {
-                dtw_aheads_preset =
-                    whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_CUSTOM;
-
-                dtw_aheads = whisper_rs_sys::whisper_aheads {
-                    n_heads: aheads.len(),
-                    heads: aheads.as_ptr(),
-                };
+                model()?.check_aheads(aheads)?;
+                owned_aheads = Some(Box::from(*aheads));
             }
-            DtwMode::ModelPreset { model_preset } => match model_preset {
-                DtwModelPreset::TinyEn => {
-                    dtw_aheads_preset =
-                        whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_TINY_EN;
-                }
-                DtwModelPreset::Tiny => {
-                    dtw_aheads_preset =
-                        whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_TINY;
-                }
-                DtwModelPreset::BaseEn => {
-                    dtw_aheads_preset =
-                        whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_BASE_EN;
-                }
-                DtwModelPreset::Base => {
-                    dtw_aheads_preset =
-                        whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_BASE;
-                }
-                DtwModelPreset::SmallEn => {
-                    dtw_aheads_preset =
-                        whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_SMALL_EN;
-                }
-                DtwModelPreset::Small => {
-                    dtw_aheads_preset =
-                        whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_SMALL;
-                }
-                DtwModelPreset::MediumEn => {
-                    dtw_aheads_preset =
-                        whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_MEDIUM_EN;
-                }
-                DtwModelPreset::Medium => {
-                    dtw_aheads_preset =
-                        whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_MEDIUM;
-                }
-                DtwModelPreset::LargeV1 => {
-                    dtw_aheads_preset =
-                        whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_LARGE_V1;
-                }
-                DtwModelPreset::LargeV2 => {
-                    dtw_aheads_preset =
-                        whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_LARGE_V2;
-                }
-                DtwModelPreset::LargeV3 => {
-                    dtw_aheads_preset =
-                        whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_LARGE_V3;
-                }
-                DtwModelPreset::LargeV3Turbo => {
-                    dtw_aheads_preset =
-                        whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_LARGE_V3_TURBO;
-                }
-            },
+            DtwMode::CustomOwned { aheads } => {
+                model()?.check_aheads(aheads)?;
+                owned_aheads = Some(Box::from(aheads.as_slice()));
+            }
+            DtwMode::Auto => dtw_aheads_preset = model()?.preset()?.to_c_preset(),
+            DtwMode::ModelPreset { model_preset } => dtw_aheads_preset = model_preset.to_c_preset(),
+        }
+
+        if let Some(aheads) = &owned_aheads {
+            dtw_aheads_preset =
+                whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_CUSTOM;
+            dtw_aheads = whisper_rs_sys::whisper_aheads {
+                n_heads: aheads.len(),
+                heads: aheads.as_ptr(),
+            };
         }
 
-        whisper_rs_sys::whisper_context_params {
+        let params = whisper_rs_sys::whisper_context_params {
             use_gpu: self.use_gpu,
             flash_attn: self.flash_attn,
             gpu_device: self.gpu_device,
@@ -566,7 +550,8 @@ impl<'a> WhisperContextParameters<'a> {
             dtw_n_top,
             dtw_aheads,
             dtw_mem_size: self.dtw_parameters.dtw_mem_size,
-        }
+        };
+        Ok((params, owned_aheads))
     }
 }
 
@@ -596,16 +581,27 @@ pub enum DtwMode<'a> {
         n_top: c_int,
     },
     /// Use custom aheads, non-empty list of whisper_ahead.
-    /// 0 < n_text_layer < model n_text_layer, 0 < n_head < model n_text_head for each element
+    /// 0 <= n_text_layer < model n_text_layer, 0 <= n_head < model n_text_head for each element,
+    /// otherwise creating the context fails with [`WhisperError::InvalidAlignmentHead`]
     /// See details https://github.com/ggerganov/whisper.cpp/pull/1485#discussion_r1519681143
     Custom {
         aheads: &'a [whisper_rs_sys::whisper_ahead],
     },
+    /// Use custom aheads, like [`DtwMode::Custom`] but owned.
+    CustomOwned {
+        aheads: Vec<whisper_rs_sys::whisper_ahead>,
+    },
     /// Use predefined preset for standard models
     ModelPreset { model_preset: DtwModelPreset },
+    /// Use the predefined preset that fits the dimensions of the loaded model.
+    ///
+    /// Fails with [`WhisperError::UnknownDtwPreset`] for models that are not a standard size,
+    /// such as distilled ones, and with [`WhisperError::AmbiguousDtwPreset`] for large-v1 and large-v2,
+    /// which cannot be told apart.
+    Auto,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum DtwModelPreset {
     TinyEn,
     Tiny,
@@ -621,11 +617,254 @@ pub enum DtwModelPreset {
     LargeV3Turbo,
 }
 
+impl DtwModelPreset {
+    fn to_c_preset(self) -> whisper_rs_sys::whisper_alignment_heads_preset {
+        match self {
+            DtwModelPreset::TinyEn => {
+                whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_TINY_EN
+            }
+            DtwModelPreset::Tiny => {
+                whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_TINY
+            }
+            DtwModelPreset::BaseEn => {
+                whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_BASE_EN
+            }
+            DtwModelPreset::Base => {
+                whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_BASE
+            }
+            DtwModelPreset::SmallEn => {
+                whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_SMALL_EN
+            }
+            DtwModelPreset::Small => {
+                whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_SMALL
+            }
+            DtwModelPreset::MediumEn => {
+                whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_MEDIUM_EN
+            }
+            DtwModelPreset::Medium => {
+                whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_MEDIUM
+            }
+            DtwModelPreset::LargeV1 => {
+                whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_LARGE_V1
+            }
+            DtwModelPreset::LargeV2 => {
+                whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_LARGE_V2
+            }
+            DtwModelPreset::LargeV3 => {
+                whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_LARGE_V3
+            }
+            DtwModelPreset::LargeV3Turbo => {
+                whisper_rs_sys::whisper_alignment_heads_preset_WHISPER_AHEADS_LARGE_V3_TURBO
+            }
+        }
+    }
+}
+
+/// The dimensions of a model, from the header of its file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ModelDims {
+    n_vocab: c_int,
+    n_audio_layer: c_int,
+    n_text_head: c_int,
+    n_text_layer: c_int,
+    n_mels: c_int,
+}
+
+impl ModelDims {
+    /// The magic number that starts a ggml model file, "ggml" in little endian.
+    const MAGIC: u32 = 0x6767_6d6c;
+    /// The magic number and the hyperparameters up to and including n_mels.
+    const HEADER_LEN: usize = 4 + 10 * 4;
+
+    fn from_file(path: &str) -> Result<Self, WhisperError> {
+        let mut header = [0; Self::HEADER_LEN];
+        std::fs::File::open(path)
+            .and_then(|mut file| file.read_exact(&mut header))
+            .map_err(|_| WhisperError::InitError)?;
+        Self::from_bytes(&header)
+    }
+
+    /// Read the header at the start of a model file.
+    fn from_bytes(bytes: &[u8]) -> Result<Self, WhisperError> {
+        if bytes.len() < Self::HEADER_LEN {
+            return Err(WhisperError::InitError);
+        }
+        let word = |i: usize| {
+            let start = 4 * i;
+            u32::from_le_bytes([
+                bytes[start],
+                bytes[start + 1],
+                bytes[start + 2],
+                bytes[start + 3],
+            ])
+        };
+        if word(0) != Self::MAGIC {
+            return Err(WhisperError::InitError);
+        }
+        // n_vocab, n_audio_ctx, n_audio_state, n_audio_head, n_audio_layer,
+        // n_text_ctx, n_text_state, n_text_head, n_text_layer, n_mels
+        let hparam = |i: usize| word(i + 1) as c_int;
+        Ok(Self {
+            n_vocab: hparam(0),
+            n_audio_layer: hparam(4),
+            n_text_head: hparam(7),
+            n_text_layer: hparam(8),
+            n_mels: hparam(9),
+        })
+    }
+
+    /// The alignment head preset for this model, going by the sizes of the released models.
+    fn preset(&self) -> Result<DtwModelPreset, WhisperError> {
+        use DtwModelPreset::*;
+        // the English-only models have one token less
+        let multilingual = self.n_vocab >= 51865;
+        let preset = match (self.n_audio_layer, self.n_text_layer, self.n_mels) {
+            (4, 4, 80) => Some(if multilingual { Tiny } else { TinyEn }),
+            (6, 6, 80) => Some(if multilingual { Base } else { BaseEn }),
+            (12, 12, 80) => Some(if multilingual { Small } else { SmallEn }),
+            (24, 24, 80) => Some(if multilingual { Medium } else { MediumEn }),
+            (32, 32, 80) if multilingual => {
+                return Err(WhisperError::AmbiguousDtwPreset(LargeV1, LargeV2))
+            }
+            (32, 32, 128) if multilingual => Some(LargeV3),
+            (32, 4, 128) if multilingual => Some(LargeV3Turbo),
+            _ => None,
+        };
+        preset.ok_or(WhisperError::UnknownDtwPreset {
+            n_audio_layer: self.n_audio_layer,
+            n_text_layer: self.n_text_layer,
+            n_vocab: self.n_vocab,
+            n_mels: self.n_mels,
+        })
+    }
+
+    fn check_aheads(&self, aheads: &[whisper_rs_sys::whisper_ahead]) -> Result<(), WhisperError> {
+        if aheads.is_empty() {
+            return Err(WhisperError::NoAlignmentHeads);
+        }
+        match aheads.iter().position(|ahead| {
+            !(0..self.n_text_layer).contains(&ahead.n_text_layer)
+                || !(0..self.n_text_head).contains(&ahead.n_head)
+        }) {
+            Some(index) => Err(WhisperError::InvalidAlignmentHead {
+                index,
+                n_text_layer: aheads[index].n_text_layer,
+                n_head: aheads[index].n_head,
+                model_n_text_layer: self.n_text_layer,
+                model_n_text_head: self.n_text_head,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn header(hparams: [c_int; 10]) -> Vec<u8> {
+        let mut bytes = ModelDims::MAGIC.to_le_bytes().to_vec();
+        for hparam in hparams {
+            bytes.extend_from_slice(&hparam.to_le_bytes());
+        }
+        // ftype and the rest of the file
+        bytes.extend_from_slice(&[0; 8]);
+        bytes
+    }
+
+    fn dims(n_vocab: c_int, n_audio_layer: c_int, n_text_layer: c_int, n_mels: c_int) -> ModelDims {
+        ModelDims {
+            n_vocab,
+            n_audio_layer,
+            n_text_head: 20,
+            n_text_layer,
+            n_mels,
+        }
+    }
+
+    #[test]
+    fn test_read_header() {
+        let tiny_en = header([51864, 1500, 384, 6, 4, 448, 384, 6, 4, 80]);
+        assert_eq!(
+            ModelDims::from_bytes(&tiny_en).unwrap(),
+            ModelDims {
+                n_vocab: 51864,
+                n_audio_layer: 4,
+                n_text_head: 6,
+                n_text_layer: 4,
+                n_mels: 80,
+            }
+        );
+        assert!(ModelDims::from_bytes(&tiny_en[..20]).is_err());
+        let mut not_ggml = tiny_en;
+        not_ggml[0] = b'x';
+        assert!(ModelDims::from_bytes(&not_ggml).is_err());
+    }
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(
+            dims(51864, 4, 4, 80).preset().unwrap(),
+            DtwModelPreset::TinyEn
+        );
+        assert_eq!(
+            dims(51865, 12, 12, 80).preset().unwrap(),
+            DtwModelPreset::Small
+        );
+        assert_eq!(
+            dims(51866, 32, 32, 128).preset().unwrap(),
+            DtwModelPreset::LargeV3
+        );
+        assert_eq!(
+            dims(51866, 32, 4, 128).preset().unwrap(),
+            DtwModelPreset::LargeV3Turbo
+        );
+        assert!(matches!(
+            dims(51865, 32, 32, 80).preset(),
+            Err(WhisperError::AmbiguousDtwPreset(..))
+        ));
+        // distil-large-v3
+        assert!(matches!(
+            dims(51866, 32, 2, 128).preset(),
+            Err(WhisperError::UnknownDtwPreset {
+                n_text_layer: 2,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_check_aheads() {
+        let ahead = |n_text_layer, n_head| whisper_rs_sys::whisper_ahead {
+            n_text_layer,
+            n_head,
+        };
+        let model = dims(51864, 6, 6, 80);
+        assert!(model.check_aheads(&[ahead(3, 1), ahead(5, 19)]).is_ok());
+        assert!(matches!(
+            model.check_aheads(&[]),
+            Err(WhisperError::NoAlignmentHeads)
+        ));
+        assert!(matches!(
+            model.check_aheads(&[ahead(3, 1), ahead(6, 0)]),
+            Err(WhisperError::InvalidAlignmentHead {
+                index: 1,
+                n_text_layer: 6,
+                model_n_text_layer: 6,
+                ..
+            })
+        ));
+        assert!(model.check_aheads(&[ahead(0, 20)]).is_err());
+        assert!(model.check_aheads(&[ahead(-1, 0)]).is_err());
+    }
+}
+
 #[cfg(test)]
 #[cfg(feature = "test-with-tiny-model")]
 mod test_with_tiny_model {
     use super::*;
     const MODEL_PATH: &str = "./sys/whisper.cpp/models/ggml-tiny.en.bin";
+    const SAMPLE_PATH: &str = "./sys/whisper.cpp/samples/jfk.wav";
 
     // These tests expect that the tiny.en model has been downloaded
     // using the script `sys/whisper.cpp/models/download-ggml-model.sh tiny.en`
@@ -642,4 +881,69 @@ mod test_with_tiny_model {
             .join("");
         assert_eq!(text_in, text_out);
     }
+
+    #[test]
+    fn test_dtw_auto_and_owned_heads() {
+        let mut params = WhisperContextParameters::default();
+        params.dtw_parameters.mode = DtwMode::Auto;
+        WhisperInnerContext::new_with_params(MODEL_PATH, params).expect("Download the ggml-tiny.en model using 'sys/whisper.cpp/models/download-ggml-model.sh tiny.en'");
+
+        // tiny.en has 4 text layers
+        let mut params = WhisperContextParameters::default();
+        params.dtw_parameters.mode = DtwMode::CustomOwned {
+            aheads: vec![whisper_rs_sys::whisper_ahead {
+                n_text_layer: 4,
+                n_head: 0,
+            }],
+        };
+        assert!(matches!(
+            WhisperInnerContext::new_with_params(MODEL_PATH, params),
+            Err(WhisperError::InvalidAlignmentHead {
+                model_n_text_layer: 4,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_owned_heads_outlive_the_parameters() {
+        let ctx = {
+            let mut params = WhisperContextParameters::default();
+            params.dtw_parameters.mode = DtwMode::CustomOwned {
+                aheads: vec![
+                    whisper_rs_sys::whisper_ahead {
+                        n_text_layer: 2,
+                        n_head: 2,
+                    },
+                    whisper_rs_sys::whisper_ahead {
+                        n_text_layer: 3,
+                        n_head: 5,
+                    },
+                ],
+            };
+            crate::WhisperContext::new_with_params(MODEL_PATH, params).expect("Download the ggml-tiny.en model using 'sys/whisper.cpp/models/download-ggml-model.sh tiny.en'")
+        };
+        // the parameters and their heads are gone, the states read the context's copy
+        let samples: Vec<i16> = hound::WavReader::open(SAMPLE_PATH)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(Result::unwrap)
+            .collect();
+        let mut audio = vec![0.0f32; samples.len()];
+        crate::convert_integer_to_float_audio(&samples, &mut audio).unwrap();
+        for _ in 0..2 {
+            let mut state = ctx.create_state().unwrap();
+            state
+                .full(
+                    crate::FullParams::new(crate::SamplingStrategy::Greedy { best_of: 1 }),
+                    &audio,
+                )
+                .unwrap();
+            let segment = state.get_segment(0).unwrap();
+            assert!(segment.to_str().unwrap().contains("fellow Americans"));
+            assert!((0..segment.n_tokens())
+                .filter_map(|i| segment.get_token(i))
+                .any(|token| token.token_data().t_dtw > 0));
+        }
+    }
 }
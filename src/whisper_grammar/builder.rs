@@ -0,0 +1,489 @@
+//! Build grammars out of Rust values instead of GBNF text.
+
+use super::{WhisperGrammar, WhisperGrammarElement, WhisperGrammarElementType};
+use std::ops::RangeInclusive;
+
+/// A grammar expression, built up with combinators and compiled into a [`WhisperGrammar`].
+///
+/// Alternations and repetitions nested within a sequence are compiled into generated rules,
+/// in the same way the GBNF parser rewrites groups and repetition operators.
+///
+/// Ready-made grammars for common inputs are available as well, such as [`Grammar::yes_no`],
+/// [`Grammar::spoken_number`], [`Grammar::date`] and [`Grammar::chess_move`].
+///
+/// Keep in mind that Whisper's tokens for words in the middle of a sentence start with a space,
+/// and that the transcript usually starts with one as well.
+///
+/// # Examples
+/// ```
+/// # use whisper_rs::Grammar;
+/// let command = Grammar::alt(["play", "pause"]).then(Grammar::number(1..=100));
+/// let grammar = Grammar::literal(" ").then(command).build();
+/// assert_eq!(grammar.to_string().lines().next(), Some("root ::= \" \" root_1 root_2"));
+///
+/// // params.set_grammar(Some(&grammar.elements()));
+/// // params.set_start_rule(grammar.start_rule());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grammar(Node);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Node {
+    Literal(String),
+    Class {
+        ranges: Vec<(char, char)>,
+        negated: bool,
+    },
+    Sequence(Vec<Grammar>),
+    Alternatives(Vec<Grammar>),
+    Repeat {
+        item: Box<Grammar>,
+        kind: Repeat,
+    },
+    Named {
+        name: String,
+        body: Box<Grammar>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Repeat {
+    Optional,
+    ZeroOrMore,
+    OneOrMore,
+}
+
+impl Grammar {
+    /// Match `text` exactly.
+    pub fn literal(text: impl Into<String>) -> Self {
+        Self(Node::Literal(text.into()))
+    }
+
+    /// Match the empty string.
+    pub fn empty() -> Self {
+        Self::literal("")
+    }
+
+    /// Match any one character within the inclusive ranges.
+    ///
+    /// # Panics
+    /// If `ranges` is empty.
+    pub fn class(ranges: impl IntoIterator<Item = RangeInclusive<char>>) -> Self {
+        Self::class_impl(ranges, false)
+    }
+
+    /// Match any one character that is not within the inclusive ranges.
+    ///
+    /// # Panics
+    /// If `ranges` is empty.
+    pub fn not_class(ranges: impl IntoIterator<Item = RangeInclusive<char>>) -> Self {
+        Self::class_impl(ranges, true)
+    }
+
+    fn class_impl(ranges: impl IntoIterator<Item = RangeInclusive<char>>, negated: bool) -> Self {
+        let ranges: Vec<_> = ranges
+            .into_iter()
+            .map(|range| (*range.start(), *range.end()))
+            .collect();
+        assert!(!ranges.is_empty(), "character class must not be empty");
+        Self(Node::Class { ranges, negated })
+    }
+
+    /// Match any one of the characters in `chars`.
+    ///
+    /// # Panics
+    /// If `chars` is empty.
+    pub fn one_of(chars: &str) -> Self {
+        Self::class(chars.chars().map(|c| c..=c))
+    }
+
+    /// Match each item in turn.
+    pub fn seq<G: Into<Grammar>>(items: impl IntoIterator<Item = G>) -> Self {
+        Self(Node::Sequence(items.into_iter().map(Into::into).collect()))
+    }
+
+    /// Match any one of the alternatives.
+    ///
+    /// # Panics
+    /// If there are no alternatives.
+    pub fn alt<G: Into<Grammar>>(alternatives: impl IntoIterator<Item = G>) -> Self {
+        let alternatives: Vec<_> = alternatives.into_iter().map(Into::into).collect();
+        assert!(
+            !alternatives.is_empty(),
+            "alternation needs at least one alternative"
+        );
+        Self(Node::Alternatives(alternatives))
+    }
+
+    /// Match a whole decimal number within `range`, without leading zeros.
+    ///
+    /// The grammar is built digit by digit, so large ranges stay small.
+    ///
+    /// # Panics
+    /// If `range` is empty.
+    pub fn number(range: RangeInclusive<u64>) -> Self {
+        let (lo, hi) = (*range.start(), *range.end());
+        assert!(lo <= hi, "number range must not be empty");
+
+        // split the range up by number of digits, then match each part digit by digit
+        let mut parts = Vec::new();
+        let mut start = lo;
+        loop {
+            let digits = start.to_string().len() as u32;
+            let end = 10u64
+                .checked_pow(digits)
+                .map_or(u64::MAX, |next| next - 1)
+                .min(hi);
+            parts.push(digit_range(
+                start.to_string().as_bytes(),
+                end.to_string().as_bytes(),
+            ));
+            if end == hi {
+                break;
+            }
+            start = end + 1;
+        }
+        if parts.len() == 1 {
+            parts.remove(0)
+        } else {
+            Self::alt(parts)
+        }
+    }
+
+    /// Match this, then `next`.
+    pub fn then(self, next: impl Into<Grammar>) -> Self {
+        match self.0 {
+            Node::Sequence(mut items) => {
+                items.push(next.into());
+                Self(Node::Sequence(items))
+            }
+            node => Self(Node::Sequence(vec![Self(node), next.into()])),
+        }
+    }
+
+    /// Match this, or `other`.
+    pub fn or(self, other: impl Into<Grammar>) -> Self {
+        match self.0 {
+            Node::Alternatives(mut alternatives) => {
+                alternatives.push(other.into());
+                Self(Node::Alternatives(alternatives))
+            }
+            node => Self(Node::Alternatives(vec![Self(node), other.into()])),
+        }
+    }
+
+    /// Match this zero or one times, like `?` in GBNF.
+    pub fn optional(self) -> Self {
+        self.repeat(Repeat::Optional)
+    }
+
+    /// Match this any number of times, like `*` in GBNF.
+    pub fn zero_or_more(self) -> Self {
+        self.repeat(Repeat::ZeroOrMore)
+    }
+
+    /// Match this at least once, like `+` in GBNF.
+    pub fn one_or_more(self) -> Self {
+        self.repeat(Repeat::OneOrMore)
+    }
+
+    fn repeat(self, kind: Repeat) -> Self {
+        Self(Node::Repeat {
+            item: Box::new(self),
+            kind,
+        })
+    }
+
+    /// Compile this into its own rule called `name`, rather than a generated one.
+    ///
+    /// Named rules used more than once are only compiled once. If different grammars are given
+    /// the same name, the later ones have their rule ID appended to the name, e.g. `device_4`.
+    ///
+    /// Names may only contain ASCII letters, digits, `-` and `_`, so that
+    /// the printed grammar is valid GBNF.
+    pub fn named(self, name: impl Into<String>) -> Self {
+        Self(Node::Named {
+            name: name.into(),
+            body: Box::new(self),
+        })
+    }
+
+    /// Compile this into a grammar, with this expression as its `root` rule.
+    pub fn build(&self) -> WhisperGrammar {
+        let mut compiler = Compiler::default();
+        let root = compiler.add_rule("root");
+        compiler.compile_rule(root, "root", self);
+        WhisperGrammar::from_parts(compiler.rules, compiler.names, root)
+    }
+
+    /// Compile this into a flat list of elements for [`FullParams::set_grammar`](crate::FullParams::set_grammar),
+    /// with this expression as the start rule 0.
+    pub fn elements(&self) -> Vec<WhisperGrammarElement> {
+        self.build().elements()
+    }
+}
+
+impl From<&str> for Grammar {
+    fn from(text: &str) -> Self {
+        Self::literal(text)
+    }
+}
+
+impl From<String> for Grammar {
+    fn from(text: String) -> Self {
+        Self::literal(text)
+    }
+}
+
+impl From<char> for Grammar {
+    fn from(c: char) -> Self {
+        Self::literal(c)
+    }
+}
+
+impl From<&Grammar> for Grammar {
+    fn from(grammar: &Grammar) -> Self {
+        grammar.clone()
+    }
+}
+
+impl From<Grammar> for WhisperGrammar {
+    fn from(grammar: Grammar) -> Self {
+        grammar.build()
+    }
+}
+
+/// Match the numbers from `lo` to `hi`, given as ASCII digits of the same length.
+fn digit_range(lo: &[u8], hi: &[u8]) -> Grammar {
+    let digit_class = |lo: u8, hi: u8| Grammar::class([lo as char..=hi as char]);
+    let any_digits = |count: usize| Grammar::seq((0..count).map(|_| digit_class(b'0', b'9')));
+
+    let (first_lo, rest_lo) = lo.split_first().expect("numbers have at least one digit");
+    let (first_hi, rest_hi) = hi.split_first().expect("numbers have at least one digit");
+    if rest_lo.is_empty() {
+        return if first_lo == first_hi {
+            Grammar::literal(*first_lo as char)
+        } else {
+            digit_class(*first_lo, *first_hi)
+        };
+    }
+    if first_lo == first_hi {
+        return Grammar::literal(*first_lo as char).then(digit_range(rest_lo, rest_hi));
+    }
+
+    // e.g. 123..=456 --> 12[3-9] | 1[3-9][0-9] | [2-3][0-9][0-9] | 4[0-4][0-9] | 45[0-6]
+    let mut alternatives = Vec::new();
+    let mut middle_lo = *first_lo;
+    if rest_lo.iter().any(|&d| d != b'0') {
+        let nines = vec![b'9'; rest_lo.len()];
+        alternatives.push(Grammar::literal(*first_lo as char).then(digit_range(rest_lo, &nines)));
+        middle_lo += 1;
+    }
+    let mut middle_hi = *first_hi;
+    let mut upper = None;
+    if rest_hi.iter().any(|&d| d != b'9') {
+        let zeros = vec![b'0'; rest_hi.len()];
+        upper = Some(Grammar::literal(*first_hi as char).then(digit_range(&zeros, rest_hi)));
+        middle_hi -= 1;
+    }
+    if middle_lo <= middle_hi {
+        alternatives.push(digit_class(middle_lo, middle_hi).then(any_digits(rest_lo.len())));
+    }
+    alternatives.extend(upper);
+    Grammar::alt(alternatives)
+}
+
+#[derive(Default)]
+struct Compiler<'g> {
+    rules: Vec<Vec<WhisperGrammarElement>>,
+    names: Vec<String>,
+    named: Vec<(&'g str, &'g Grammar, usize)>,
+}
+
+impl<'g> Compiler<'g> {
+    fn add_rule(&mut self, name: &str) -> usize {
+        let id = self.rules.len();
+        self.rules.push(Vec::new());
+        self.names.push(name.to_string());
+        id
+    }
+
+    fn generate_rule(&mut self, base_name: &str) -> usize {
+        let id = self.add_rule(base_name);
+        self.names[id] = format!("{}_{}", base_name, id);
+        id
+    }
+
+    fn compile_rule(&mut self, id: usize, rule_name: &str, grammar: &'g Grammar) {
+        let mut rule = Vec::new();
+        match &grammar.0 {
+            Node::Alternatives(alternatives) => {
+                for (i, alternative) in alternatives.iter().enumerate() {
+                    if i > 0 {
+                        rule.push(element(WhisperGrammarElementType::Alternate, 0));
+                    }
+                    self.compile_sequence(rule_name, alternative, &mut rule);
+                }
+            }
+            Node::Named { body, .. } => return self.compile_rule(id, rule_name, body),
+            _ => self.compile_sequence(rule_name, grammar, &mut rule),
+        }
+        rule.push(element(WhisperGrammarElementType::End, 0));
+        self.rules[id] = rule;
+    }
+
+    fn compile_sequence(
+        &mut self,
+        rule_name: &str,
+        grammar: &'g Grammar,
+        out: &mut Vec<WhisperGrammarElement>,
+    ) {
+        use WhisperGrammarElementType::*;
+
+        match &grammar.0 {
+            Node::Literal(text) => {
+                out.extend(text.chars().map(|c| element(Character, c as u32)));
+            }
+            Node::Class { ranges, negated } => {
+                for (i, &(start, end)) in ranges.iter().enumerate() {
+                    let element_type = match (i, *negated) {
+                        (0, false) => Character,
+                        (0, true) => NotCharacter,
+                        _ => CharacterAlternate,
+                    };
+                    out.push(element(element_type, start as u32));
+                    if end != start {
+                        out.push(element(CharacterRangeUpper, end as u32));
+                    }
+                }
+            }
+            Node::Sequence(items) => {
+                for item in items {
+                    self.compile_sequence(rule_name, item, out);
+                }
+            }
+            Node::Alternatives(alternatives) if alternatives.len() == 1 => {
+                self.compile_sequence(rule_name, &alternatives[0], out);
+            }
+            Node::Alternatives(_) => {
+                let id = self.generate_rule(rule_name);
+                self.compile_rule(id, rule_name, grammar);
+                out.push(element(RuleReference, id as u32));
+            }
+            Node::Repeat { item, kind } => {
+                // same rewrites as the GBNF parser:
+                // S* --> S' ::= S S' |
+                // S+ --> S' ::= S S' | S
+                // S? --> S' ::= S |
+                let id = self.generate_rule(rule_name);
+                let mut item_elements = Vec::new();
+                self.compile_sequence(rule_name, item, &mut item_elements);
+
+                let mut rule = item_elements.clone();
+                if *kind != Repeat::Optional {
+                    rule.push(element(RuleReference, id as u32));
+                }
+                rule.push(element(Alternate, 0));
+                if *kind == Repeat::OneOrMore {
+                    rule.extend(item_elements);
+                }
+                rule.push(element(End, 0));
+                self.rules[id] = rule;
+                out.push(element(RuleReference, id as u32));
+            }
+            Node::Named { name, body } => {
+                let id = match self
+                    .named
+                    .iter()
+                    .find(|(n, g, _)| *n == name.as_str() && *g == body.as_ref())
+                {
+                    Some(&(_, _, id)) => id,
+                    None => {
+                        let id = if self.names.contains(name) {
+                            self.generate_rule(name)
+                        } else {
+                            self.add_rule(name)
+                        };
+                        self.named.push((name, body, id));
+                        self.compile_rule(id, name, body);
+                        id
+                    }
+                };
+                out.push(element(RuleReference, id as u32));
+            }
+        }
+    }
+}
+
+fn element(element_type: WhisperGrammarElementType, value: u32) -> WhisperGrammarElement {
+    WhisperGrammarElement::new(element_type, value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn gbnf(grammar: Grammar) -> String {
+        grammar.build().to_string()
+    }
+
+    fn assert_same_rules(built: Grammar, src: &str) {
+        let parsed = WhisperGrammar::parse(src).expect("test grammar should parse");
+        assert_eq!(built.build().rules(), parsed.rules(), "\n{}", gbnf(built));
+    }
+
+    #[test]
+    fn test_matches_parsed_grammar() {
+        assert_same_rules(
+            Grammar::literal("turn ")
+                .then(Grammar::alt(["on", "off"]))
+                .then(Grammar::literal(" light").then(Grammar::literal("s").optional())),
+            r#"root ::= "turn " ("on" | "off") " light" "s"?"#,
+        );
+        assert_same_rules(Grammar::alt(["yes", "no"]), r#"root ::= "yes" | "no""#);
+        assert_same_rules(
+            Grammar::class(['a'..='z', '_'..='_'])
+                .one_or_more()
+                .then(Grammar::not_class(['\n'..='\n']).zero_or_more()),
+            r#"root ::= [a-z_]+ [^\n]*"#,
+        );
+    }
+
+    #[test]
+    fn test_named_rules_are_shared() {
+        let device = Grammar::alt(["lights", "fan"]).named("device");
+        let grammar = Grammar::seq([
+            Grammar::literal("turn on ").then(&device),
+            Grammar::literal(" and ").then(&device),
+        ]);
+        assert_eq!(
+            gbnf(grammar),
+            "root ::= \"turn on \" device \" and \" device\ndevice ::= \"lights\" | \"fan\"\n"
+        );
+
+        let grammar = Grammar::alt([
+            Grammar::alt(["a"]).named("x"),
+            Grammar::alt(["b"]).named("x"),
+        ]);
+        assert_eq!(
+            gbnf(grammar),
+            "root ::= x | x_2\nx ::= \"a\"\nx_2 ::= \"b\"\n"
+        );
+    }
+
+    #[test]
+    fn test_number() {
+        assert_eq!(gbnf(Grammar::number(3..=7)), "root ::= [3-7]\n");
+        assert_eq!(gbnf(Grammar::number(42..=42)), "root ::= \"42\"\n");
+        assert_eq!(
+            gbnf(Grammar::number(1..=100)),
+            "root ::= [1-9] | [1-9] [0-9] | \"100\"\n"
+        );
+        assert_eq!(
+            gbnf(Grammar::number(123..=456)),
+            "root ::= \"1\" root_1 | [2-3] [0-9] [0-9] | \"4\" root_2\n\
+             root_1 ::= \"2\" [3-9] | [3-9] [0-9]\n\
+             root_2 ::= [0-4] [0-9] | \"5\" [0-6]\n"
+        );
+    }
+}
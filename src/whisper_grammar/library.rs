@@ -0,0 +1,467 @@
+//! Ready-made grammars for common spoken inputs.
+//!
+//! These match what Whisper writes down for the input, not the words as spoken:
+//! "twenty five" is usually transcribed as "25", but might be "twenty-five".
+//! Where both are common, both are accepted.
+//!
+//! None of these include the leading space or trailing punctuation Whisper puts around a transcript,
+//! so that they can be used as parts of larger grammars.
+
+use super::Grammar;
+
+/// A word, with either an upper or lower case first letter.
+fn word(word: &str) -> Grammar {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) if first.is_alphabetic() => {
+            let upper = first.to_uppercase().next().unwrap_or(first);
+            let lower = first.to_lowercase().next().unwrap_or(first);
+            Grammar::class([upper..=upper, lower..=lower]).then(chars.as_str())
+        }
+        _ => Grammar::literal(word),
+    }
+}
+
+fn words<'a>(words: impl IntoIterator<Item = &'a str>) -> Grammar {
+    Grammar::alt(words.into_iter().map(word))
+}
+
+const UNITS: [&str; 9] = [
+    "one", "two", "three", "four", "five", "six", "seven", "eight", "nine",
+];
+const TEENS: [&str; 10] = [
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+const TENS: [&str; 8] = [
+    "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+const ORDINAL_UNITS: [&str; 9] = [
+    "first", "second", "third", "fourth", "fifth", "sixth", "seventh", "eighth", "ninth",
+];
+const ORDINAL_TEENS: [&str; 10] = [
+    "tenth",
+    "eleventh",
+    "twelfth",
+    "thirteenth",
+    "fourteenth",
+    "fifteenth",
+    "sixteenth",
+    "seventeenth",
+    "eighteenth",
+    "nineteenth",
+];
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+const NATO_ALPHABET: [&str; 29] = [
+    "Alfa", "Alpha", "Bravo", "Charlie", "Delta", "Echo", "Foxtrot", "Golf", "Hotel", "India",
+    "Juliett", "Juliet", "Kilo", "Lima", "Mike", "November", "Oscar", "Papa", "Quebec", "Romeo",
+    "Sierra", "Tango", "Uniform", "Victor", "Whiskey", "X-ray", "Xray", "Yankee", "Zulu",
+];
+const CHESS_PIECES: [&str; 6] = ["king", "queen", "rook", "bishop", "knight", "pawn"];
+
+impl Grammar {
+    /// "yes" or "no", capitalized or not.
+    pub fn yes_no() -> Self {
+        words(["yes", "no"]).named("yes_no")
+    }
+
+    /// One or more of the digits `0` to `9`.
+    pub fn digits() -> Self {
+        Self::class(['0'..='9']).one_or_more().named("digits")
+    }
+
+    /// A number from zero to 999,999,999 written out in English words,
+    /// e.g. "two hundred and forty-one thousand, five hundred".
+    ///
+    /// Tens and units may be joined by a space or a hyphen,
+    /// and "and" and commas between the parts are optional.
+    pub fn spoken_number() -> Self {
+        let below_hundred = words(UNITS)
+            .or(words(TEENS))
+            .or(words(TENS).then(Self::one_of(" -").then(words(UNITS)).optional()))
+            .named("spoken_below_hundred");
+        let below_thousand = words(UNITS)
+            .then(" hundred")
+            .then(
+                Self::literal(" and")
+                    .optional()
+                    .then(" ")
+                    .then(&below_hundred)
+                    .optional(),
+            )
+            .or(&below_hundred)
+            .named("spoken_below_thousand");
+
+        // each group is optionally followed by the next smaller one
+        let separator = || Self::alt([",", " and", ""]).then(" ");
+        let below_million = below_thousand
+            .clone()
+            .then(Self::literal(" thousand").then(separator().then(&below_thousand).optional()))
+            .or(&below_thousand)
+            .named("spoken_below_million");
+        let below_billion = below_thousand
+            .then(Self::literal(" million").then(separator().then(&below_million).optional()))
+            .or(below_million);
+
+        word("zero").or(below_billion).named("spoken_number")
+    }
+
+    /// A calendar date, e.g. "March 3rd, 2024", "3 March 2024", or "the third of March".
+    ///
+    /// Days may be written as digits, optionally with an ordinal suffix, or as ordinal words.
+    /// Years are four digits from 1000 to 2999.
+    pub fn date() -> Self {
+        let month = words(MONTHS).named("month");
+        let day = Self::number(1..=31)
+            .then(Self::alt(["st", "nd", "rd", "th"]).optional())
+            .or(Self::spoken_ordinal_day())
+            .named("day");
+        let year = Self::one_of("12")
+            .then(Self::class(['0'..='9']))
+            .then(Self::class(['0'..='9']))
+            .then(Self::class(['0'..='9']))
+            .named("year");
+
+        let month_first = month.clone().then(" ").then(&day).then(
+            Self::literal(",")
+                .optional()
+                .then(" ")
+                .then(&year)
+                .optional(),
+        );
+        let day_first = word("the")
+            .then(" ")
+            .optional()
+            .then(&day)
+            .then(Self::literal(" of").optional())
+            .then(" ")
+            .then(month)
+            .then(
+                Self::literal(",")
+                    .optional()
+                    .then(" ")
+                    .then(year)
+                    .optional(),
+            );
+        month_first.or(day_first).named("date")
+    }
+
+    /// "first" to "thirty-first".
+    fn spoken_ordinal_day() -> Self {
+        words(ORDINAL_UNITS)
+            .or(words(ORDINAL_TEENS))
+            .or(words(["twentieth", "thirtieth"]))
+            .or(word("twenty")
+                .then(Self::one_of(" -"))
+                .then(words(ORDINAL_UNITS)))
+            .or(word("thirty").then(Self::one_of(" -")).then("first"))
+    }
+
+    /// A time of day, e.g. "14:30", "2:30 p.m.", "5 PM", "7 o'clock", "noon" or "midnight".
+    pub fn time() -> Self {
+        let hour = Self::number(0..=23).or(Self::literal("0").then(Self::class(['0'..='9'])));
+        let minute = Self::class(['0'..='5']).then(Self::class(['0'..='9']));
+        let meridiem = Self::alt(["AM", "PM", "am", "pm", "a.m.", "p.m.", "A.M.", "P.M."]);
+
+        hour.clone()
+            .then(":")
+            .then(minute)
+            .then(Self::literal(" ").optional().then(&meridiem).optional())
+            .or(hour
+                .then(" ")
+                .then(Self::alt([meridiem, Self::literal("o'clock")])))
+            .or(words(["noon", "midnight"]))
+            .named("time")
+    }
+
+    /// A single letter of the NATO phonetic alphabet, e.g. "Alpha" or "X-ray".
+    ///
+    /// Common alternate spellings are accepted, e.g. both "Alfa" and "Alpha".
+    pub fn nato_letter() -> Self {
+        words(NATO_ALPHABET).named("nato_letter")
+    }
+
+    /// A sequence of NATO phonetic alphabet letters, separated by spaces, commas or hyphens,
+    /// e.g. "Bravo, Oscar, Bravo".
+    pub fn nato_spelling() -> Self {
+        let letter = Self::nato_letter();
+        letter
+            .clone()
+            .then(Self::alt([", ", " ", "-"]).then(letter).zero_or_more())
+            .named("nato_spelling")
+    }
+
+    /// An email address, either as written (`jane.doe@example.com`)
+    /// or as spoken ("jane dot doe at example dot com").
+    pub fn email() -> Self {
+        let local = Self::class(['a'..='z', 'A'..='Z', '0'..='9'])
+            .or(Self::one_of("._%+-"))
+            .one_or_more();
+        let label = Self::class(['a'..='z', 'A'..='Z', '0'..='9'])
+            .then(Self::class(['a'..='z', 'A'..='Z', '0'..='9', '-'..='-']).zero_or_more());
+        let written = local
+            .then("@")
+            .then(&label)
+            .then(Self::literal(".").then(label).one_or_more());
+
+        let spoken_word = Self::class(['a'..='z', 'A'..='Z', '0'..='9']).one_or_more();
+        let spoken = spoken_word
+            .clone()
+            .then(Self::literal(" dot ").then(&spoken_word).zero_or_more())
+            .then(" at ")
+            .then(&spoken_word)
+            .then(Self::literal(" dot ").then(spoken_word).one_or_more());
+
+        written.or(spoken).named("email")
+    }
+
+    /// A chess move, either in standard algebraic notation (`Nf3`, `exd5`, `e8=Q+`, `O-O-O`)
+    /// or spoken ("knight to f3", "pawn takes d5", "castles kingside").
+    pub fn chess_move() -> Self {
+        let file = Self::class(['a'..='h']);
+        let rank = Self::class(['1'..='8']);
+        let square = file.clone().then(&rank).named("square");
+
+        let check = Self::one_of("+#").optional();
+        let san = Self::one_of("KQRBN")
+            .optional()
+            .then(file.optional())
+            .then(rank.optional())
+            .then(Self::literal("x").optional())
+            .then(&square)
+            .then(Self::literal("=").then(Self::one_of("QRBN")).optional())
+            .then(check.clone())
+            .or(Self::literal("O-O")
+                .then(Self::literal("-O").optional())
+                .then(check));
+
+        let piece = words(CHESS_PIECES).named("chess_piece");
+        let spoken = piece
+            .then(" ")
+            .then(Self::alt(["to ", "takes ", "captures "]).optional())
+            .then(&square)
+            .or(square.clone().then(" to ").then(square))
+            .or(word("castles")
+                .then(Self::alt([" kingside", " queenside", " short", " long"]).optional()));
+
+        san.or(spoken).named("chess_move")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{WhisperGrammar, WhisperGrammarElementType};
+
+    /// The end positions of all the ways `rule` can match `input` from `pos`.
+    fn match_rule(grammar: &WhisperGrammar, rule: usize, input: &[char], pos: usize) -> Vec<usize> {
+        use WhisperGrammarElementType::*;
+
+        let elements = &grammar.rules()[rule];
+        let mut ends = Vec::new();
+        let mut positions = vec![pos];
+        let mut i = 0;
+        while i < elements.len() {
+            let element = elements[i];
+            match element.element_type {
+                End | Alternate => {
+                    ends.append(&mut positions);
+                    positions.push(pos);
+                    i += 1;
+                    if element.element_type == End {
+                        break;
+                    }
+                }
+                RuleReference => {
+                    positions = positions
+                        .iter()
+                        .flat_map(|&p| match_rule(grammar, element.value as usize, input, p))
+                        .collect();
+                    i += 1;
+                }
+                Character | NotCharacter => {
+                    let mut end = i + 1;
+                    while elements.get(end).is_some_and(|e| {
+                        matches!(e.element_type, CharacterRangeUpper | CharacterAlternate)
+                    }) {
+                        end += 1;
+                    }
+                    let class = &elements[i..end];
+                    let in_class = |c: u32| {
+                        (0..class.len()).any(|j| {
+                            if class[j].element_type == CharacterRangeUpper {
+                                return false;
+                            }
+                            match class.get(j + 1) {
+                                Some(upper) if upper.element_type == CharacterRangeUpper => {
+                                    (class[j].value..=upper.value).contains(&c)
+                                }
+                                _ => class[j].value == c,
+                            }
+                        })
+                    };
+                    let negated = element.element_type == NotCharacter;
+                    positions
+                        .retain(|&p| input.get(p).is_some_and(|&c| in_class(c as u32) != negated));
+                    positions.iter_mut().for_each(|p| *p += 1);
+                    i = end;
+                }
+                CharacterRangeUpper | CharacterAlternate => unreachable!(),
+            }
+            positions.sort_unstable();
+            positions.dedup();
+        }
+        ends.sort_unstable();
+        ends.dedup();
+        ends
+    }
+
+    fn accepts(grammar: &Grammar, text: &str) -> bool {
+        let grammar = grammar.build();
+        let input: Vec<char> = text.chars().collect();
+        match_rule(&grammar, grammar.start_rule(), &input, 0).contains(&input.len())
+    }
+
+    fn check(grammar: Grammar, valid: &[&str], invalid: &[&str]) {
+        // the printed grammar must be valid GBNF
+        let printed = grammar.build().to_string();
+        WhisperGrammar::parse(&printed).expect("printed grammar should parse");
+
+        for text in valid {
+            assert!(accepts(&grammar, text), "should accept {:?}", text);
+        }
+        for text in invalid {
+            assert!(!accepts(&grammar, text), "should reject {:?}", text);
+        }
+    }
+
+    #[test]
+    fn test_yes_no_and_digits() {
+        check(Grammar::yes_no(), &["yes", "No"], &["yep", "yes no", ""]);
+        check(Grammar::digits(), &["0", "0042"], &["", "4a"]);
+    }
+
+    #[test]
+    fn test_spoken_number() {
+        check(
+            Grammar::spoken_number(),
+            &[
+                "zero",
+                "Seven",
+                "nineteen",
+                "forty-two",
+                "ninety nine",
+                "one hundred",
+                "three hundred and five",
+                "two hundred and forty-one thousand, five hundred",
+                "six million one hundred",
+            ],
+            &[
+                "",
+                "zero one",
+                "hundred",
+                "twenty-ten",
+                "one thousand thousand",
+            ],
+        );
+    }
+
+    #[test]
+    fn test_date_and_time() {
+        check(
+            Grammar::date(),
+            &[
+                "March 3rd, 2024",
+                "3 March 2024",
+                "the third of March",
+                "December 31",
+                "twenty-first of June",
+            ],
+            &["March", "32 March", "Smarch 3", "March 3, 24"],
+        );
+        check(
+            Grammar::time(),
+            &["14:30", "2:30 p.m.", "09:05", "5 PM", "7 o'clock", "noon"],
+            &["24:00", "7:60", "7", "7 oclock"],
+        );
+    }
+
+    #[test]
+    fn test_nato_spelling() {
+        check(
+            Grammar::nato_spelling(),
+            &["Bravo, Oscar, Bravo", "alpha X-ray", "Juliet-Juliett"],
+            &["", "Bravo,", "Bravo, B"],
+        );
+    }
+
+    #[test]
+    fn test_email() {
+        check(
+            Grammar::email(),
+            &[
+                "jane.doe+news@mail.example.com",
+                "jane dot doe at example dot com",
+            ],
+            &["jane@example", "jane at example", "@example.com"],
+        );
+    }
+
+    #[test]
+    fn test_chess_move() {
+        check(
+            Grammar::chess_move(),
+            &[
+                "e4",
+                "Nf3",
+                "exd5",
+                "Raxe1",
+                "e8=Q+",
+                "O-O",
+                "O-O-O#",
+                "knight to f3",
+                "Pawn takes d5",
+                "e2 to e4",
+                "castles kingside",
+            ],
+            &["i4", "e9", "Xe4", "knight f9", "castles sideways"],
+        );
+    }
+
+    #[test]
+    fn test_combined_with_user_rules() {
+        let grammar = Grammar::literal(" Set an alarm for ")
+            .then(Grammar::time())
+            .then(Grammar::literal(" on ").then(Grammar::date()).optional())
+            .then(".");
+        check(
+            grammar,
+            &[
+                " Set an alarm for 7:30 am.",
+                " Set an alarm for noon on the third of March.",
+            ],
+            &[" Set an alarm for tomorrow."],
+        );
+    }
+}
@@ -5,6 +5,14 @@ use whisper_rs_sys::{
     whisper_gretype_WHISPER_GRETYPE_RULE_REF,
 };
 
+mod builder;
+mod library;
+mod parser;
+
+pub use builder::Grammar;
+pub(crate) use parser::split_rules;
+pub use parser::{GrammarParseError, WhisperGrammar};
+
 #[cfg_attr(any(not(windows), target_env = "gnu"), repr(u32))] // include windows-gnu
 #[cfg_attr(all(windows, not(target_env = "gnu")), repr(i32))] // msvc being *special* again
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
@@ -0,0 +1,808 @@
+//! A parser for the GBNF grammar format used by whisper.cpp and llama.cpp.
+//!
+//! See <https://github.com/ggml-org/llama.cpp/blob/master/grammars/README.md> for a description of the format.
+
+use super::{WhisperGrammarElement, WhisperGrammarElementType};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+/// A grammar made up of named rules, ready to be passed to
+/// [`FullParams::set_grammar`](crate::FullParams::set_grammar).
+///
+/// Each rule is a sequence of [`WhisperGrammarElement`]s, with alternates separated by
+/// [`WhisperGrammarElementType::Alternate`] and terminated by [`WhisperGrammarElementType::End`].
+/// Rule references point to the index of a rule.
+///
+/// The [`fmt::Display`] implementation prints the grammar back out as GBNF, which is useful for debugging.
+///
+/// # Examples
+/// ```
+/// # use whisper_rs::WhisperGrammar;
+/// let grammar: WhisperGrammar = r#"
+///     root   ::= "turn " ("on" | "off") " the " device
+///     device ::= "lights" | "fan"
+/// "#
+/// .parse()
+/// .expect("grammar should be valid");
+/// assert_eq!(grammar.rule_name(grammar.start_rule()), Some("root"));
+///
+/// // params.set_grammar(Some(&grammar.elements()));
+/// // params.set_start_rule(grammar.start_rule());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhisperGrammar {
+    rules: Vec<Vec<WhisperGrammarElement>>,
+    names: Vec<String>,
+    start_rule: usize,
+}
+
+impl WhisperGrammar {
+    /// Parse GBNF grammar text.
+    ///
+    /// The start rule is the rule named `root` if there is one, otherwise the first rule.
+    ///
+    /// Supported syntax:
+    /// * `name ::= alternates`, one rule per line
+    /// * string literals: `"text"`, with the escapes `\n`, `\r`, `\t`, `\\`, `\"`, `\[`, `\]`,
+    ///   `\xHH`, `\uHHHH` and `\UHHHHHHHH`
+    /// * character classes: `[a-z0-9_]`, and negated: `[^"\n]`
+    /// * rule references, grouping with `( ... )` and alternation with `|`
+    /// * the repetition operators `*`, `+` and `?`
+    /// * comments starting with `#`
+    ///
+    /// # Errors
+    /// A [`GrammarParseError`] with the line and column of the first syntax error.
+    pub fn parse(src: &str) -> Result<Self, GrammarParseError> {
+        Parser::new(src).parse()
+    }
+
+    /// Create a grammar from a flat list of elements, where each rule is terminated by
+    /// [`WhisperGrammarElementType::End`].
+    ///
+    /// Rules are named `rule_<index>`.
+    /// The elements are not validated, this is mostly useful to print grammars built by hand.
+    pub fn from_elements(elements: &[WhisperGrammarElement], start_rule: usize) -> Self {
+        let rules = split_rules(elements);
+        let names = (0..rules.len()).map(|i| format!("rule_{}", i)).collect();
+        Self {
+            rules,
+            names,
+            start_rule,
+        }
+    }
+
+    pub(crate) fn from_parts(
+        rules: Vec<Vec<WhisperGrammarElement>>,
+        names: Vec<String>,
+        start_rule: usize,
+    ) -> Self {
+        debug_assert_eq!(rules.len(), names.len());
+        Self {
+            rules,
+            names,
+            start_rule,
+        }
+    }
+
+    /// The rules of this grammar, indexed by their rule ID.
+    pub fn rules(&self) -> &[Vec<WhisperGrammarElement>] {
+        &self.rules
+    }
+
+    /// All rules concatenated, in the format expected by [`FullParams::set_grammar`](crate::FullParams::set_grammar).
+    pub fn elements(&self) -> Vec<WhisperGrammarElement> {
+        self.rules.concat()
+    }
+
+    /// The index of the start rule, for [`FullParams::set_start_rule`](crate::FullParams::set_start_rule).
+    pub fn start_rule(&self) -> usize {
+        self.start_rule
+    }
+
+    /// Use the rule called `name` as the start rule.
+    ///
+    /// Returns `false` and leaves the start rule unchanged if there is no such rule.
+    pub fn set_start_rule(&mut self, name: &str) -> bool {
+        match self.rule_id(name) {
+            Some(id) => {
+                self.start_rule = id;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The index of the rule called `name`.
+    pub fn rule_id(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+
+    /// The name of the rule at `id`.
+    ///
+    /// Rules generated for groups and repetitions are named after the rule they appear in,
+    /// followed by their ID, e.g. `root_3`.
+    pub fn rule_name(&self, id: usize) -> Option<&str> {
+        self.names.get(id).map(String::as_str)
+    }
+}
+
+impl FromStr for WhisperGrammar {
+    type Err = GrammarParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+/// Split a flat list of elements into rules, each ending with [`WhisperGrammarElementType::End`].
+///
+/// A trailing rule that is missing its terminator gets one added.
+pub(crate) fn split_rules(elements: &[WhisperGrammarElement]) -> Vec<Vec<WhisperGrammarElement>> {
+    let mut rules = Vec::new();
+    let mut rule = Vec::new();
+    for &element in elements {
+        rule.push(element);
+        if element.element_type == WhisperGrammarElementType::End {
+            rules.push(std::mem::take(&mut rule));
+        }
+    }
+    if !rule.is_empty() {
+        rule.push(WhisperGrammarElement::new(
+            WhisperGrammarElementType::End,
+            0,
+        ));
+        rules.push(rule);
+    }
+    rules
+}
+
+/// A syntax error in GBNF grammar text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrammarParseError {
+    /// The line the error occurred on, starting at 1.
+    pub line: usize,
+    /// The column the error occurred at in characters, starting at 1.
+    pub column: usize,
+    /// What went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for GrammarParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Grammar syntax error at line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for GrammarParseError {}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+    symbol_ids: HashMap<String, u32>,
+    names: Vec<String>,
+    rules: Vec<Option<Vec<WhisperGrammarElement>>>,
+    /// Where each rule was first referenced, to report undefined rules.
+    first_reference: Vec<usize>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(src: &'a str) -> Self {
+        Self {
+            src,
+            pos: 0,
+            symbol_ids: HashMap::new(),
+            names: Vec::new(),
+            rules: Vec::new(),
+            first_reference: Vec::new(),
+        }
+    }
+
+    fn parse(mut self) -> Result<WhisperGrammar, GrammarParseError> {
+        self.parse_space(true);
+        while self.peek().is_some() {
+            self.parse_rule()?;
+        }
+
+        if self.rules.is_empty() {
+            return Err(self.error_at(self.pos, "expected at least one rule"));
+        }
+        let mut rules = Vec::with_capacity(self.rules.len());
+        for (id, rule) in self.rules.iter().enumerate() {
+            match rule {
+                Some(rule) => rules.push(rule.clone()),
+                None => {
+                    return Err(self.error_at(
+                        self.first_reference[id],
+                        format!("undefined rule identifier '{}'", self.names[id]),
+                    ))
+                }
+            }
+        }
+        let start_rule = self.symbol_ids.get("root").copied().unwrap_or(0) as usize;
+
+        Ok(WhisperGrammar::from_parts(rules, self.names, start_rule))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.src[self.pos..].chars().next()
+    }
+
+    fn peek_nth(&self, n: usize) -> Option<char> {
+        self.src[self.pos..].chars().nth(n)
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn error_at(&self, pos: usize, message: impl Into<String>) -> GrammarParseError {
+        let before = &self.src[..pos];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        GrammarParseError {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            message: message.into(),
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> GrammarParseError {
+        self.error_at(self.pos, message)
+    }
+
+    fn parse_space(&mut self, newline_ok: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' => {
+                    self.bump();
+                }
+                '#' => {
+                    while !matches!(self.peek(), None | Some('\r' | '\n')) {
+                        self.bump();
+                    }
+                }
+                '\r' | '\n' if newline_ok => {
+                    self.bump();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn is_word_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '-' || c == '_'
+    }
+
+    fn parse_name(&mut self) -> Result<&'a str, GrammarParseError> {
+        let start = self.pos;
+        while self.peek().is_some_and(Self::is_word_char) {
+            self.bump();
+        }
+        if start == self.pos {
+            return Err(self.error("expecting name"));
+        }
+        Ok(&self.src[start..self.pos])
+    }
+
+    fn symbol_id(&mut self, name: &str, pos: usize) -> u32 {
+        if let Some(&id) = self.symbol_ids.get(name) {
+            return id;
+        }
+        let id = self.names.len() as u32;
+        self.symbol_ids.insert(name.to_string(), id);
+        self.names.push(name.to_string());
+        self.rules.push(None);
+        self.first_reference.push(pos);
+        id
+    }
+
+    fn generate_symbol_id(&mut self, base_name: &str) -> u32 {
+        let id = self.names.len() as u32;
+        let name = format!("{}_{}", base_name, id);
+        self.symbol_ids.insert(name.clone(), id);
+        self.names.push(name);
+        self.rules.push(None);
+        self.first_reference.push(self.pos);
+        id
+    }
+
+    fn parse_rule(&mut self) -> Result<(), GrammarParseError> {
+        let name_pos = self.pos;
+        let name = self.parse_name()?;
+        self.parse_space(false);
+        let rule_id = self.symbol_id(name, name_pos);
+        if self.rules[rule_id as usize].is_some() {
+            return Err(self.error_at(
+                name_pos,
+                format!("rule '{}' is defined more than once", name),
+            ));
+        }
+
+        if !self.src[self.pos..].starts_with("::=") {
+            return Err(self.error("expecting ::="));
+        }
+        self.pos += 3;
+        self.parse_space(true);
+
+        self.parse_alternates(name, rule_id, false)?;
+
+        match self.peek() {
+            Some('\r') => {
+                self.bump();
+                if self.peek() == Some('\n') {
+                    self.bump();
+                }
+            }
+            Some('\n') => {
+                self.bump();
+            }
+            None => {}
+            Some(_) => return Err(self.error("expecting newline or end")),
+        }
+        self.parse_space(true);
+        Ok(())
+    }
+
+    fn parse_alternates(
+        &mut self,
+        rule_name: &str,
+        rule_id: u32,
+        is_nested: bool,
+    ) -> Result<(), GrammarParseError> {
+        let mut rule = Vec::new();
+        self.parse_sequence(rule_name, &mut rule, is_nested)?;
+        while self.peek() == Some('|') {
+            rule.push(WhisperGrammarElement::new(
+                WhisperGrammarElementType::Alternate,
+                0,
+            ));
+            self.bump();
+            self.parse_space(true);
+            self.parse_sequence(rule_name, &mut rule, is_nested)?;
+        }
+        rule.push(WhisperGrammarElement::new(
+            WhisperGrammarElementType::End,
+            0,
+        ));
+        self.rules[rule_id as usize] = Some(rule);
+        Ok(())
+    }
+
+    fn parse_sequence(
+        &mut self,
+        rule_name: &str,
+        out: &mut Vec<WhisperGrammarElement>,
+        is_nested: bool,
+    ) -> Result<(), GrammarParseError> {
+        use WhisperGrammarElementType::*;
+
+        let mut last_sym_start = out.len();
+        while let Some(c) = self.peek() {
+            match c {
+                '"' => {
+                    self.bump();
+                    last_sym_start = out.len();
+                    loop {
+                        match self.peek() {
+                            None => return Err(self.error("unexpected end of input")),
+                            Some('"') => break,
+                            Some(_) => {
+                                let c = self.parse_char()?;
+                                out.push(WhisperGrammarElement::new(Character, c));
+                            }
+                        }
+                    }
+                    self.bump();
+                    self.parse_space(is_nested);
+                }
+                '[' => {
+                    self.bump();
+                    let mut start_type = Character;
+                    if self.peek() == Some('^') {
+                        self.bump();
+                        start_type = NotCharacter;
+                    }
+                    last_sym_start = out.len();
+                    loop {
+                        match self.peek() {
+                            None => return Err(self.error("unexpected end of input")),
+                            Some(']') => break,
+                            Some(_) => {
+                                let c = self.parse_char()?;
+                                let element_type = if last_sym_start < out.len() {
+                                    CharacterAlternate
+                                } else {
+                                    start_type
+                                };
+                                out.push(WhisperGrammarElement::new(element_type, c));
+                                if self.peek() == Some('-')
+                                    && !matches!(self.peek_nth(1), Some(']'))
+                                {
+                                    self.bump();
+                                    if self.peek().is_none() {
+                                        return Err(self.error("unexpected end of input"));
+                                    }
+                                    let end = self.parse_char()?;
+                                    out.push(WhisperGrammarElement::new(CharacterRangeUpper, end));
+                                }
+                            }
+                        }
+                    }
+                    if last_sym_start == out.len() {
+                        return Err(self.error("empty character class"));
+                    }
+                    self.bump();
+                    self.parse_space(is_nested);
+                }
+                c if Self::is_word_char(c) => {
+                    let name_pos = self.pos;
+                    let name = self.parse_name()?;
+                    let ref_rule_id = self.symbol_id(name, name_pos);
+                    self.parse_space(is_nested);
+                    last_sym_start = out.len();
+                    out.push(WhisperGrammarElement::new(RuleReference, ref_rule_id));
+                }
+                '(' => {
+                    self.bump();
+                    self.parse_space(true);
+                    let sub_rule_id = self.generate_symbol_id(rule_name);
+                    self.parse_alternates(rule_name, sub_rule_id, true)?;
+                    last_sym_start = out.len();
+                    out.push(WhisperGrammarElement::new(RuleReference, sub_rule_id));
+                    if self.peek() != Some(')') {
+                        return Err(self.error("expecting ')'"));
+                    }
+                    self.bump();
+                    self.parse_space(is_nested);
+                }
+                '*' | '+' | '?' => {
+                    if last_sym_start == out.len() {
+                        return Err(self.error(format!("expecting preceding item to {}", c)));
+                    }
+                    // apply the repetition to the previous symbol by rewriting it into a new rule:
+                    // S* --> S' ::= S S' |
+                    // S+ --> S' ::= S S' | S
+                    // S? --> S' ::= S |
+                    let sub_rule_id = self.generate_symbol_id(rule_name);
+                    let symbol = out.split_off(last_sym_start);
+                    let mut sub_rule = symbol.clone();
+                    if c == '*' || c == '+' {
+                        sub_rule.push(WhisperGrammarElement::new(RuleReference, sub_rule_id));
+                    }
+                    sub_rule.push(WhisperGrammarElement::new(Alternate, 0));
+                    if c == '+' {
+                        sub_rule.extend_from_slice(&symbol);
+                    }
+                    sub_rule.push(WhisperGrammarElement::new(End, 0));
+                    self.rules[sub_rule_id as usize] = Some(sub_rule);
+
+                    out.push(WhisperGrammarElement::new(RuleReference, sub_rule_id));
+                    self.bump();
+                    self.parse_space(is_nested);
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_char(&mut self) -> Result<u32, GrammarParseError> {
+        let start = self.pos;
+        match self.bump() {
+            Some('\\') => match self.bump() {
+                Some('x') => self.parse_hex(2),
+                Some('u') => self.parse_hex(4),
+                Some('U') => self.parse_hex(8),
+                Some('t') => Ok('\t' as u32),
+                Some('r') => Ok('\r' as u32),
+                Some('n') => Ok('\n' as u32),
+                Some(c @ ('\\' | '"' | '[' | ']')) => Ok(c as u32),
+                Some(_) => Err(self.error_at(start, "unknown escape")),
+                None => Err(self.error("unexpected end of input")),
+            },
+            Some(c) => Ok(c as u32),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn parse_hex(&mut self, digits: usize) -> Result<u32, GrammarParseError> {
+        let mut value = 0;
+        for _ in 0..digits {
+            match self.peek().and_then(|c| c.to_digit(16)) {
+                Some(digit) => {
+                    value = (value << 4) + digit;
+                    self.bump();
+                }
+                None => {
+                    return Err(self.error(format!("expecting {} hex chars", digits)));
+                }
+            }
+        }
+        Ok(value)
+    }
+}
+
+impl fmt::Display for WhisperGrammar {
+    /// Print this grammar as GBNF, one rule per line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (id, rule) in self.rules.iter().enumerate() {
+            write!(f, "{} ::=", self.names[id])?;
+            self.fmt_rule(f, rule)?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+impl WhisperGrammar {
+    fn fmt_rule(&self, f: &mut fmt::Formatter<'_>, rule: &[WhisperGrammarElement]) -> fmt::Result {
+        use WhisperGrammarElementType::*;
+
+        let mut literal = String::new();
+        let flush = |f: &mut fmt::Formatter<'_>, literal: &mut String| {
+            if literal.is_empty() {
+                return Ok(());
+            }
+            write!(f, " \"{}\"", literal)?;
+            literal.clear();
+            Ok(())
+        };
+
+        // an empty alternate is printed as `""`, as a trailing `|` would continue onto the next line
+        let mut empty = true;
+        let mut i = 0;
+        while i < rule.len() {
+            let element = rule[i];
+            if !matches!(element.element_type, End | Alternate) {
+                empty = false;
+            }
+            match element.element_type {
+                End => break,
+                Alternate => {
+                    flush(f, &mut literal)?;
+                    if empty {
+                        write!(f, " \"\"")?;
+                    }
+                    write!(f, " |")?;
+                    empty = true;
+                    i += 1;
+                }
+                RuleReference => {
+                    flush(f, &mut literal)?;
+                    match self.names.get(element.value as usize) {
+                        Some(name) => write!(f, " {}", name)?,
+                        None => write!(f, " rule_{}", element.value)?,
+                    }
+                    i += 1;
+                }
+                Character | NotCharacter => {
+                    // a character class continues for as long as there are ranges or alternates
+                    let mut end = i + 1;
+                    while rule.get(end).is_some_and(|e| {
+                        matches!(e.element_type, CharacterRangeUpper | CharacterAlternate)
+                    }) {
+                        end += 1;
+                    }
+
+                    if element.element_type == Character && end == i + 1 {
+                        push_escaped(&mut literal, element.value, false);
+                    } else {
+                        flush(f, &mut literal)?;
+                        let mut class = String::new();
+                        for e in &rule[i..end] {
+                            if e.element_type == CharacterRangeUpper {
+                                class.push('-');
+                            }
+                            push_escaped(&mut class, e.value, true);
+                        }
+                        let negate = if element.element_type == NotCharacter {
+                            "^"
+                        } else {
+                            ""
+                        };
+                        write!(f, " [{}{}]", negate, class)?;
+                    }
+                    i = end;
+                }
+                CharacterRangeUpper | CharacterAlternate => {
+                    // malformed, not preceded by a character
+                    flush(f, &mut literal)?;
+                    write!(f, " <{:?} {:#x}>", element.element_type, element.value)?;
+                    i += 1;
+                }
+            }
+        }
+        flush(f, &mut literal)?;
+        if empty {
+            write!(f, " \"\"")?;
+        }
+        Ok(())
+    }
+}
+
+fn push_escaped(out: &mut String, value: u32, in_class: bool) {
+    match char::from_u32(value) {
+        Some('\n') => out.push_str("\\n"),
+        Some('\r') => out.push_str("\\r"),
+        Some('\t') => out.push_str("\\t"),
+        Some(c @ ('\\' | '"')) => {
+            out.push('\\');
+            out.push(c);
+        }
+        Some(c @ ('[' | ']')) if in_class => {
+            out.push('\\');
+            out.push(c);
+        }
+        // these have a special meaning in classes, but cannot be escaped with a backslash
+        Some(c @ ('-' | '^')) if in_class => out.push_str(&format!("\\x{:02X}", c as u32)),
+        Some(c) if !c.is_control() => out.push(c),
+        _ if value <= 0xFF => out.push_str(&format!("\\x{:02X}", value)),
+        _ if value <= 0xFFFF => out.push_str(&format!("\\u{:04X}", value)),
+        _ => out.push_str(&format!("\\U{:08X}", value)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use WhisperGrammarElementType::*;
+
+    fn e(element_type: WhisperGrammarElementType, value: u32) -> WhisperGrammarElement {
+        WhisperGrammarElement::new(element_type, value)
+    }
+
+    #[test]
+    fn test_parse_literal_and_alternates() {
+        let grammar = WhisperGrammar::parse("root ::= \"ab\" | [x-z] other\nother ::= [^\\n]\n")
+            .expect("grammar should parse");
+        assert_eq!(grammar.start_rule(), 0);
+        assert_eq!(grammar.rule_name(1), Some("other"));
+        assert_eq!(
+            grammar.rules()[0],
+            [
+                e(Character, 'a' as u32),
+                e(Character, 'b' as u32),
+                e(Alternate, 0),
+                e(Character, 'x' as u32),
+                e(CharacterRangeUpper, 'z' as u32),
+                e(RuleReference, 1),
+                e(End, 0),
+            ]
+        );
+        assert_eq!(
+            grammar.rules()[1],
+            [e(NotCharacter, '\n' as u32), e(End, 0)]
+        );
+    }
+
+    #[test]
+    fn test_parse_repetition() {
+        let grammar =
+            WhisperGrammar::parse("root ::= [0-9]+ \"!\"?").expect("grammar should parse");
+        assert_eq!(
+            grammar.rules()[0],
+            [e(RuleReference, 1), e(RuleReference, 2), e(End, 0)]
+        );
+        // S+ --> S' ::= S S' | S
+        assert_eq!(
+            grammar.rules()[1],
+            [
+                e(Character, '0' as u32),
+                e(CharacterRangeUpper, '9' as u32),
+                e(RuleReference, 1),
+                e(Alternate, 0),
+                e(Character, '0' as u32),
+                e(CharacterRangeUpper, '9' as u32),
+                e(End, 0),
+            ]
+        );
+        // S? --> S' ::= S |
+        assert_eq!(
+            grammar.rules()[2],
+            [e(Character, '!' as u32), e(Alternate, 0), e(End, 0)]
+        );
+        assert_eq!(grammar.rule_name(1), Some("root_1"));
+    }
+
+    #[test]
+    fn test_start_rule_is_root() {
+        let grammar = WhisperGrammar::parse("# a comment\nitem ::= \"a\"\nroot ::= item*\n")
+            .expect("grammar should parse");
+        assert_eq!(grammar.start_rule(), 1);
+        assert_eq!(grammar.rule_id("item"), Some(0));
+    }
+
+    #[test]
+    fn test_parse_escapes() {
+        let grammar =
+            WhisperGrammar::parse(r#"root ::= "\x41é\"\\" [\]\[]"#).expect("grammar should parse");
+        assert_eq!(
+            grammar.rules()[0],
+            [
+                e(Character, 'A' as u32),
+                e(Character, 'é' as u32),
+                e(Character, '"' as u32),
+                e(Character, '\\' as u32),
+                e(Character, ']' as u32),
+                e(CharacterAlternate, '[' as u32),
+                e(End, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_error_positions() {
+        let err = WhisperGrammar::parse("root ::= \"a\"\n  other = \"b\"").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 9));
+        assert_eq!(err.message, "expecting ::=");
+
+        let err = WhisperGrammar::parse("root ::= item\n").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 10));
+        assert_eq!(err.message, "undefined rule identifier 'item'");
+
+        let err = WhisperGrammar::parse("root ::= (\"a\" | \"b\"").unwrap_err();
+        assert_eq!(err.line, 1);
+        assert_eq!(err.message, "expecting ')'");
+
+        let err = WhisperGrammar::parse("root ::= \"a\n").unwrap_err();
+        assert_eq!(err.message, "unexpected end of input");
+
+        let err = WhisperGrammar::parse("root ::= * \"a\"").unwrap_err();
+        assert_eq!((err.line, err.column), (1, 10));
+
+        let err = WhisperGrammar::parse("root ::= \"a\"\nroot ::= \"b\"").unwrap_err();
+        assert_eq!((err.line, err.column), (2, 1));
+
+        assert!(WhisperGrammar::parse("  # nothing here\n").is_err());
+    }
+
+    #[test]
+    fn test_print_round_trip() {
+        let src = r#"
+            root   ::= greeting (" " name)* "." [^\n-]?
+            greeting ::= "hi" | "hello \"there\""
+            name   ::= [A-Z] [a-z-]+
+        "#;
+        let grammar = WhisperGrammar::parse(src).expect("grammar should parse");
+        let printed = grammar.to_string();
+        assert!(
+            printed.starts_with("root ::= greeting root_4 \".\" root_5\n"),
+            "{}",
+            printed
+        );
+        let reparsed = WhisperGrammar::parse(&printed).expect("printed grammar should parse");
+
+        // rule IDs are assigned in order of first use, so only the set of rules is preserved
+        let sorted_lines = |grammar: &WhisperGrammar| {
+            let mut lines: Vec<String> = grammar.to_string().lines().map(String::from).collect();
+            lines.sort();
+            lines
+        };
+        assert_eq!(sorted_lines(&reparsed), sorted_lines(&grammar));
+        assert_eq!(reparsed.rules().len(), grammar.rules().len());
+    }
+
+    #[test]
+    fn test_from_elements() {
+        let elements = [
+            e(Character, 'a' as u32),
+            e(End, 0),
+            e(RuleReference, 0),
+            e(Alternate, 0),
+            e(Character, 'b' as u32),
+        ];
+        let grammar = WhisperGrammar::from_elements(&elements, 1);
+        assert_eq!(grammar.rules().len(), 2);
+        assert_eq!(
+            grammar.to_string(),
+            "rule_0 ::= \"a\"\nrule_1 ::= rule_0 | \"b\"\n"
+        );
+        assert_eq!(grammar.elements().len(), elements.len() + 1);
+    }
+}
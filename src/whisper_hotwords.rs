@@ -0,0 +1,264 @@
+use crate::whisper_logit_bias::{logit_mut, text_tokens, tokenize_phrase};
+use crate::{WhisperContext, WhisperError, WhisperTokenData, WhisperTokenId};
+use std::collections::{HashMap, HashSet};
+
+const ROOT: usize = 0;
+
+#[derive(Debug, Clone, Default)]
+struct TrieNode {
+    children: HashMap<WhisperTokenId, usize>,
+    /// Every phrase whose token sequence passes through (or ends at) this node.
+    phrases: Vec<usize>,
+    /// Phrases whose token sequence ends at this node.
+    ends: Vec<usize>,
+}
+
+/// Contextual hotword boosting.
+///
+/// Hotword phrases are tokenized and stored in a prefix trie.
+/// At each decoding step, the first token of every phrase is boosted,
+/// and the continuation tokens of a phrase are boosted only while the tokens decoded so far end with
+/// a prefix of that phrase. Unlike [`LogitBias`](crate::LogitBias), this steers the model through
+/// multi-token phrases without boosting their later pieces everywhere.
+///
+/// If a token receives a boost from several phrases, the largest weight is used.
+///
+/// Pass this to [`FullParams::set_hotwords`](crate::FullParams::set_hotwords)
+/// to apply it during [`WhisperState::full`](crate::WhisperState::full).
+///
+/// # Examples
+/// ```no_run
+/// # use whisper_rs::{FullParams, HotwordBoost, SamplingStrategy, WhisperContext, WhisperContextParameters};
+/// # let ctx = WhisperContext::new_with_params("model.bin", WhisperContextParameters::default()).unwrap();
+/// let mut hotwords = HotwordBoost::new();
+/// hotwords.add_phrase(&ctx, " Kubernetes", 4.0).unwrap();
+/// hotwords.add_phrase(&ctx, " Grafana Loki", 3.0).unwrap();
+/// hotwords.set_cancel_on_abandon(true);
+///
+/// let mut params = FullParams::new(SamplingStrategy::BeamSearch { beam_size: 5, patience: -1.0 });
+/// params.set_hotwords(Some(hotwords));
+/// ```
+#[derive(Debug, Clone)]
+pub struct HotwordBoost {
+    nodes: Vec<TrieNode>,
+    weights: Vec<f32>,
+    cancel_on_abandon: bool,
+    token_eot: Option<WhisperTokenId>,
+}
+
+impl Default for HotwordBoost {
+    fn default() -> Self {
+        Self {
+            nodes: vec![TrieNode::default()],
+            weights: Vec::new(),
+            cancel_on_abandon: false,
+            token_eot: None,
+        }
+    }
+}
+
+impl HotwordBoost {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a hotword phrase, tokenized with [`WhisperContext::tokenize`] exactly as given.
+    ///
+    /// Words in the middle of a sentence start with a space in Whisper's vocabulary,
+    /// so you likely want to add `" Phrase"` rather than `"Phrase"`.
+    ///
+    /// # Arguments
+    /// * ctx: The context the phrase will be decoded with.
+    /// * phrase: The text of the phrase.
+    /// * weight: The amount added to the logit of each boosted token of this phrase.
+    ///
+    /// # Errors
+    /// * [`WhisperError::NullByteInString`] if `phrase` contains a null byte.
+    /// * [`WhisperError::InvalidText`] if `phrase` could not be tokenized or is empty.
+    pub fn add_phrase(
+        &mut self,
+        ctx: &WhisperContext,
+        phrase: &str,
+        weight: f32,
+    ) -> Result<(), WhisperError> {
+        let tokens = tokenize_phrase(ctx, phrase)?;
+        self.token_eot = Some(ctx.token_eot());
+        self.add_tokens(&tokens, weight);
+        Ok(())
+    }
+
+    /// Add a hotword phrase from its token IDs. Empty sequences are ignored.
+    ///
+    /// See [`Self::add_phrase`].
+    pub fn add_tokens(&mut self, tokens: &[WhisperTokenId], weight: f32) {
+        if tokens.is_empty() {
+            return;
+        }
+        let phrase = self.weights.len();
+        self.weights.push(weight);
+
+        let mut node = ROOT;
+        for &token in tokens {
+            node = match self.nodes[node].children.get(&token) {
+                Some(&child) => child,
+                None => {
+                    let child = self.nodes.len();
+                    self.nodes.push(TrieNode::default());
+                    self.nodes[node].children.insert(token, child);
+                    child
+                }
+            };
+            self.nodes[node].phrases.push(phrase);
+        }
+        self.nodes[node].ends.push(phrase);
+    }
+
+    /// Stop boosting a phrase for the rest of the window once the model has started it
+    /// but then continued with something else.
+    ///
+    /// This keeps a rejected hotword from being pushed again and again,
+    /// at the cost of not boosting it if it legitimately appears after a false start.
+    ///
+    /// Defaults to false.
+    pub fn set_cancel_on_abandon(&mut self, cancel_on_abandon: bool) {
+        self.cancel_on_abandon = cancel_on_abandon;
+    }
+
+    /// Returns true if no phrases have been added.
+    pub fn is_empty(&self) -> bool {
+        self.weights.is_empty()
+    }
+
+    /// Apply the hotword boosts to `logits`, given the `tokens` decoded so far.
+    ///
+    /// This is what [`FullParams::set_hotwords`](crate::FullParams::set_hotwords) runs
+    /// for each decoding step. Call it yourself from within a closure passed to
+    /// [`FullParams::set_filter_logits_callback_safe`](crate::FullParams::set_filter_logits_callback_safe)
+    /// to combine it with your own filtering.
+    pub fn apply(&self, tokens: &[WhisperTokenData], logits: &mut [f32]) {
+        if self.is_empty() {
+            return;
+        }
+
+        // find every trie node matched by a suffix of the decoded text,
+        // noting phrases that were started and then left along the way
+        let mut cancelled: HashSet<usize> = HashSet::new();
+        let mut active: Vec<usize> = Vec::new();
+        for token in text_tokens(tokens, self.token_eot) {
+            let mut next = Vec::with_capacity(active.len() + 1);
+            for &node in &active {
+                let node = &self.nodes[node];
+                match node.children.get(&token) {
+                    Some(&child) => next.push(child),
+                    None if self.cancel_on_abandon => cancelled.extend(
+                        node.phrases
+                            .iter()
+                            .filter(|&phrase| !node.ends.contains(phrase)),
+                    ),
+                    None => {}
+                }
+            }
+            if let Some(&child) = self.nodes[ROOT].children.get(&token) {
+                next.push(child);
+            }
+            next.sort_unstable();
+            next.dedup();
+            active = next;
+        }
+
+        let mut boosts: HashMap<WhisperTokenId, f32> = HashMap::new();
+        for node in std::iter::once(ROOT).chain(active) {
+            for (&token, &child) in &self.nodes[node].children {
+                let weight = self.nodes[child]
+                    .phrases
+                    .iter()
+                    .filter(|phrase| !cancelled.contains(*phrase))
+                    .map(|&phrase| self.weights[phrase])
+                    .reduce(f32::max);
+                if let Some(weight) = weight {
+                    let boost = boosts.entry(token).or_insert(weight);
+                    *boost = boost.max(weight);
+                }
+            }
+        }
+
+        for (token, boost) in boosts {
+            if let Some(logit) = logit_mut(logits, token) {
+                *logit += boost;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::whisper_logit_bias::test::token_data;
+
+    fn decoded(ids: &[WhisperTokenId]) -> Vec<WhisperTokenData> {
+        ids.iter().copied().map(token_data).collect()
+    }
+
+    fn boosted(hotwords: &HotwordBoost, ids: &[WhisperTokenId]) -> Vec<f32> {
+        let mut logits = vec![0.0; 10];
+        hotwords.apply(&decoded(ids), &mut logits);
+        logits
+    }
+
+    #[test]
+    fn test_first_token_boosted_everywhere() {
+        let mut hotwords = HotwordBoost::new();
+        hotwords.add_tokens(&[1, 2, 3], 2.0);
+        assert_eq!(
+            boosted(&hotwords, &[]),
+            [0.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]
+        );
+        assert_eq!(boosted(&hotwords, &[5, 6])[1], 2.0);
+        assert_eq!(boosted(&hotwords, &[5, 6])[2], 0.0);
+    }
+
+    #[test]
+    fn test_continuation_boosted_only_after_prefix() {
+        let mut hotwords = HotwordBoost::new();
+        hotwords.add_tokens(&[1, 2, 3], 2.0);
+        assert_eq!(boosted(&hotwords, &[5, 1])[2], 2.0);
+        assert_eq!(boosted(&hotwords, &[5, 1])[3], 0.0);
+        assert_eq!(boosted(&hotwords, &[1, 2])[3], 2.0);
+        assert_eq!(boosted(&hotwords, &[1, 5])[2], 0.0);
+    }
+
+    #[test]
+    fn test_shared_prefix_uses_largest_weight() {
+        let mut hotwords = HotwordBoost::new();
+        hotwords.add_tokens(&[1, 2], 1.0);
+        hotwords.add_tokens(&[1, 3], 4.0);
+        let logits = boosted(&hotwords, &[1]);
+        assert_eq!(logits[2], 1.0);
+        assert_eq!(logits[3], 4.0);
+        assert_eq!(boosted(&hotwords, &[])[1], 4.0);
+    }
+
+    #[test]
+    fn test_special_tokens_are_skipped() {
+        let mut hotwords = HotwordBoost::new();
+        hotwords.add_tokens(&[1, 2], 1.0);
+        hotwords.token_eot = Some(8);
+        assert_eq!(boosted(&hotwords, &[1, 9])[2], 1.0);
+    }
+
+    #[test]
+    fn test_cancel_on_abandon() {
+        let mut hotwords = HotwordBoost::new();
+        hotwords.add_tokens(&[1, 2, 3], 2.0);
+        hotwords.add_tokens(&[4, 5], 1.0);
+        assert_eq!(boosted(&hotwords, &[1, 6])[1], 2.0);
+
+        hotwords.set_cancel_on_abandon(true);
+        let logits = boosted(&hotwords, &[1, 6]);
+        assert_eq!(logits[1], 0.0, "abandoned phrase is no longer boosted");
+        assert_eq!(logits[4], 1.0, "other phrases are unaffected");
+
+        // a completed phrase was not abandoned
+        assert_eq!(boosted(&hotwords, &[4, 5, 6])[4], 1.0);
+    }
+}
@@ -0,0 +1,326 @@
+use std::ffi::c_int;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{WhisperContext, WhisperTokenId};
+
+macro_rules! languages {
+    ($($variant:ident: $code:literal, $name:literal, $iso_639_1:expr, $iso_639_3:literal;)*) => {
+        /// A language Whisper knows, or [`Language::Auto`] to have it detected.
+        ///
+        /// The order is that of the ids whisper.cpp uses, see [`Language::id`].
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum Language {
+            /// Detect the language.
+            Auto,
+            $($variant,)*
+        }
+
+        impl Language {
+            /// Every language, without [`Language::Auto`], by id.
+            pub const ALL: &'static [Language] = &[$(Language::$variant,)*];
+        }
+
+        /// The whisper.cpp code, whisper.cpp name, ISO 639-1 and ISO 639-3 code of each language, by id.
+        const LANGUAGES: &[(&str, &str, Option<&str>, &str)] = &[$(($code, $name, $iso_639_1, $iso_639_3),)*];
+    };
+}
+
+languages! {
+    English: "en", "english", Some("en"), "eng";
+    Chinese: "zh", "chinese", Some("zh"), "zho";
+    German: "de", "german", Some("de"), "deu";
+    Spanish: "es", "spanish", Some("es"), "spa";
+    Russian: "ru", "russian", Some("ru"), "rus";
+    Korean: "ko", "korean", Some("ko"), "kor";
+    French: "fr", "french", Some("fr"), "fra";
+    Japanese: "ja", "japanese", Some("ja"), "jpn";
+    Portuguese: "pt", "portuguese", Some("pt"), "por";
+    Turkish: "tr", "turkish", Some("tr"), "tur";
+    Polish: "pl", "polish", Some("pl"), "pol";
+    Catalan: "ca", "catalan", Some("ca"), "cat";
+    Dutch: "nl", "dutch", Some("nl"), "nld";
+    Arabic: "ar", "arabic", Some("ar"), "ara";
+    Swedish: "sv", "swedish", Some("sv"), "swe";
+    Italian: "it", "italian", Some("it"), "ita";
+    Indonesian: "id", "indonesian", Some("id"), "ind";
+    Hindi: "hi", "hindi", Some("hi"), "hin";
+    Finnish: "fi", "finnish", Some("fi"), "fin";
+    Vietnamese: "vi", "vietnamese", Some("vi"), "vie";
+    Hebrew: "he", "hebrew", Some("he"), "heb";
+    Ukrainian: "uk", "ukrainian", Some("uk"), "ukr";
+    Greek: "el", "greek", Some("el"), "ell";
+    Malay: "ms", "malay", Some("ms"), "msa";
+    Czech: "cs", "czech", Some("cs"), "ces";
+    Romanian: "ro", "romanian", Some("ro"), "ron";
+    Danish: "da", "danish", Some("da"), "dan";
+    Hungarian: "hu", "hungarian", Some("hu"), "hun";
+    Tamil: "ta", "tamil", Some("ta"), "tam";
+    Norwegian: "no", "norwegian", Some("no"), "nor";
+    Thai: "th", "thai", Some("th"), "tha";
+    Urdu: "ur", "urdu", Some("ur"), "urd";
+    Croatian: "hr", "croatian", Some("hr"), "hrv";
+    Bulgarian: "bg", "bulgarian", Some("bg"), "bul";
+    Lithuanian: "lt", "lithuanian", Some("lt"), "lit";
+    Latin: "la", "latin", Some("la"), "lat";
+    Maori: "mi", "maori", Some("mi"), "mri";
+    Malayalam: "ml", "malayalam", Some("ml"), "mal";
+    Welsh: "cy", "welsh", Some("cy"), "cym";
+    Slovak: "sk", "slovak", Some("sk"), "slk";
+    Telugu: "te", "telugu", Some("te"), "tel";
+    Persian: "fa", "persian", Some("fa"), "fas";
+    Latvian: "lv", "latvian", Some("lv"), "lav";
+    Bengali: "bn", "bengali", Some("bn"), "ben";
+    Serbian: "sr", "serbian", Some("sr"), "srp";
+    Azerbaijani: "az", "azerbaijani", Some("az"), "aze";
+    Slovenian: "sl", "slovenian", Some("sl"), "slv";
+    Kannada: "kn", "kannada", Some("kn"), "kan";
+    Estonian: "et", "estonian", Some("et"), "est";
+    Macedonian: "mk", "macedonian", Some("mk"), "mkd";
+    Breton: "br", "breton", Some("br"), "bre";
+    Basque: "eu", "basque", Some("eu"), "eus";
+    Icelandic: "is", "icelandic", Some("is"), "isl";
+    Armenian: "hy", "armenian", Some("hy"), "hye";
+    Nepali: "ne", "nepali", Some("ne"), "nep";
+    Mongolian: "mn", "mongolian", Some("mn"), "mon";
+    Bosnian: "bs", "bosnian", Some("bs"), "bos";
+    Kazakh: "kk", "kazakh", Some("kk"), "kaz";
+    Albanian: "sq", "albanian", Some("sq"), "sqi";
+    Swahili: "sw", "swahili", Some("sw"), "swa";
+    Galician: "gl", "galician", Some("gl"), "glg";
+    Marathi: "mr", "marathi", Some("mr"), "mar";
+    Punjabi: "pa", "punjabi", Some("pa"), "pan";
+    Sinhala: "si", "sinhala", Some("si"), "sin";
+    Khmer: "km", "khmer", Some("km"), "khm";
+    Shona: "sn", "shona", Some("sn"), "sna";
+    Yoruba: "yo", "yoruba", Some("yo"), "yor";
+    Somali: "so", "somali", Some("so"), "som";
+    Afrikaans: "af", "afrikaans", Some("af"), "afr";
+    Occitan: "oc", "occitan", Some("oc"), "oci";
+    Georgian: "ka", "georgian", Some("ka"), "kat";
+    Belarusian: "be", "belarusian", Some("be"), "bel";
+    Tajik: "tg", "tajik", Some("tg"), "tgk";
+    Sindhi: "sd", "sindhi", Some("sd"), "snd";
+    Gujarati: "gu", "gujarati", Some("gu"), "guj";
+    Amharic: "am", "amharic", Some("am"), "amh";
+    Yiddish: "yi", "yiddish", Some("yi"), "yid";
+    Lao: "lo", "lao", Some("lo"), "lao";
+    Uzbek: "uz", "uzbek", Some("uz"), "uzb";
+    Faroese: "fo", "faroese", Some("fo"), "fao";
+    HaitianCreole: "ht", "haitian creole", Some("ht"), "hat";
+    Pashto: "ps", "pashto", Some("ps"), "pus";
+    Turkmen: "tk", "turkmen", Some("tk"), "tuk";
+    Nynorsk: "nn", "nynorsk", Some("nn"), "nno";
+    Maltese: "mt", "maltese", Some("mt"), "mlt";
+    Sanskrit: "sa", "sanskrit", Some("sa"), "san";
+    Luxembourgish: "lb", "luxembourgish", Some("lb"), "ltz";
+    Myanmar: "my", "myanmar", Some("my"), "mya";
+    Tibetan: "bo", "tibetan", Some("bo"), "bod";
+    Tagalog: "tl", "tagalog", Some("tl"), "tgl";
+    Malagasy: "mg", "malagasy", Some("mg"), "mlg";
+    Assamese: "as", "assamese", Some("as"), "asm";
+    Tatar: "tt", "tatar", Some("tt"), "tat";
+    Hawaiian: "haw", "hawaiian", None, "haw";
+    Lingala: "ln", "lingala", Some("ln"), "lin";
+    Hausa: "ha", "hausa", Some("ha"), "hau";
+    Bashkir: "ba", "bashkir", Some("ba"), "bak";
+    // whisper.cpp has its own code for Javanese
+    Javanese: "jw", "javanese", Some("jv"), "jav";
+    Sundanese: "su", "sundanese", Some("su"), "sun";
+    Cantonese: "yue", "cantonese", None, "yue";
+}
+
+impl Language {
+    /// The id whisper.cpp uses for this language, as returned by [`get_lang_id`](crate::get_lang_id),
+    /// [`WhisperState::lang_detect`](crate::WhisperState::lang_detect)
+    /// and [`WhisperState::full_lang_id_from_state`](crate::WhisperState::full_lang_id_from_state).
+    ///
+    /// [`None`] for [`Language::Auto`].
+    pub fn id(self) -> Option<c_int> {
+        Self::ALL
+            .iter()
+            .position(|&language| language == self)
+            .map(|id| id as c_int)
+    }
+
+    /// The language with whisper.cpp id `id`, [`None`] if there is none.
+    pub fn from_id(id: c_int) -> Option<Self> {
+        usize::try_from(id)
+            .ok()
+            .and_then(|id| Self::ALL.get(id))
+            .copied()
+    }
+
+    /// The code whisper.cpp uses for this language, like "de", as returned by
+    /// [`get_lang_str`](crate::get_lang_str) and taken by [`FullParams::set_language`](crate::FullParams::set_language).
+    ///
+    /// "auto" for [`Language::Auto`].
+    pub fn code(self) -> &'static str {
+        self.entry().map_or("auto", |(code, ..)| code)
+    }
+
+    /// The lowercase English name whisper.cpp uses for this language, like "german",
+    /// as returned by [`get_lang_str_full`](crate::get_lang_str_full).
+    ///
+    /// "auto" for [`Language::Auto`].
+    pub fn name(self) -> &'static str {
+        self.entry().map_or("auto", |(_, name, ..)| name)
+    }
+
+    /// The two letter ISO 639-1 code, if the language has one.
+    ///
+    /// This differs from [`Language::code`] for Javanese, Hawaiian and Cantonese.
+    pub fn iso_639_1(self) -> Option<&'static str> {
+        self.entry().and_then(|(_, _, iso_639_1, _)| iso_639_1)
+    }
+
+    /// The three letter ISO 639-3 code, [`None`] for [`Language::Auto`].
+    pub fn iso_639_3(self) -> Option<&'static str> {
+        self.entry().map(|(.., iso_639_3)| iso_639_3)
+    }
+
+    /// The token that tells the decoder to transcribe in this language, see [`WhisperContext::token_lang`].
+    ///
+    /// [`None`] for [`Language::Auto`].
+    pub fn token(self, ctx: &WhisperContext) -> Option<WhisperTokenId> {
+        self.id().map(|id| ctx.token_lang(id))
+    }
+
+    /// The language of a language token, like the ones [`Language::token`] returns.
+    pub fn from_token(ctx: &WhisperContext, token: WhisperTokenId) -> Option<Self> {
+        Self::from_id(token - ctx.token_lang(0))
+    }
+
+    fn entry(
+        self,
+    ) -> Option<(
+        &'static str,
+        &'static str,
+        Option<&'static str>,
+        &'static str,
+    )> {
+        self.id().map(|id| LANGUAGES[id as usize])
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl TryFrom<c_int> for Language {
+    type Error = LanguageParseError;
+
+    /// See [`Language::from_id`].
+    fn try_from(id: c_int) -> Result<Self, Self::Error> {
+        Self::from_id(id).ok_or_else(|| LanguageParseError {
+            input: id.to_string(),
+        })
+    }
+}
+
+impl FromStr for Language {
+    type Err = LanguageParseError;
+
+    /// Parse a whisper.cpp code, ISO 639-1 or ISO 639-3 code or whisper.cpp name, ignoring case.
+    /// "auto" parses as [`Language::Auto`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let input = s.trim().to_lowercase();
+        if input == "auto" {
+            return Ok(Self::Auto);
+        }
+        Self::ALL
+            .iter()
+            .zip(LANGUAGES)
+            .find(|(_, &(code, name, iso_639_1, iso_639_3))| {
+                [code, name, iso_639_3].contains(&input.as_str()) || iso_639_1 == Some(&input)
+            })
+            .map(|(&language, _)| language)
+            .ok_or(LanguageParseError {
+                input: s.to_string(),
+            })
+    }
+}
+
+/// Error returned when parsing a [`Language`] fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguageParseError {
+    /// What was parsed.
+    pub input: String,
+}
+
+impl fmt::Display for LanguageParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown language: {:?}.", self.input)
+    }
+}
+
+impl std::error::Error for LanguageParseError {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ids() {
+        assert_eq!(Language::ALL.len(), 100);
+        assert_eq!(Language::English.id(), Some(0));
+        assert_eq!(Language::German.id(), Some(2));
+        assert_eq!(Language::Cantonese.id(), Some(99));
+        assert_eq!(Language::Auto.id(), None);
+        for (id, &language) in Language::ALL.iter().enumerate() {
+            assert_eq!(Language::from_id(id as c_int), Some(language));
+        }
+        assert_eq!(Language::from_id(-1), None);
+        assert_eq!(Language::from_id(100), None);
+        assert_eq!(Language::try_from(15), Ok(Language::Italian));
+        assert!(Language::try_from(100).is_err());
+    }
+
+    #[test]
+    fn test_codes() {
+        assert_eq!(Language::German.code(), "de");
+        assert_eq!(Language::German.name(), "german");
+        assert_eq!(Language::German.iso_639_3(), Some("deu"));
+        assert_eq!(Language::Javanese.code(), "jw");
+        assert_eq!(Language::Javanese.iso_639_1(), Some("jv"));
+        assert_eq!(Language::Cantonese.iso_639_1(), None);
+        assert_eq!(Language::HaitianCreole.name(), "haitian creole");
+        assert_eq!(Language::Auto.code(), "auto");
+        assert_eq!(Language::Auto.iso_639_3(), None);
+        assert_eq!(Language::Norwegian.to_string(), "no");
+    }
+
+    #[test]
+    fn test_from_str() {
+        for &language in Language::ALL {
+            assert_eq!(language.code().parse(), Ok(language));
+            assert_eq!(language.name().parse(), Ok(language));
+            assert_eq!(language.iso_639_3().unwrap().parse(), Ok(language));
+        }
+        assert_eq!("DE".parse(), Ok(Language::German));
+        assert_eq!(" Haitian Creole ".parse(), Ok(Language::HaitianCreole));
+        assert_eq!("jv".parse(), Ok(Language::Javanese));
+        assert_eq!("auto".parse(), Ok(Language::Auto));
+        let err = "klingon".parse::<Language>().unwrap_err();
+        assert_eq!(err.to_string(), "Unknown language: \"klingon\".");
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "test-with-tiny-model")]
+mod test_with_tiny_model {
+    use super::*;
+
+    #[test]
+    fn test_table_matches_whisper_cpp() {
+        assert_eq!(crate::get_lang_max_id() as usize + 1, Language::ALL.len());
+        for &language in Language::ALL {
+            let id = language.id().unwrap();
+            assert_eq!(crate::get_lang_str(id), Some(language.code()));
+            assert_eq!(crate::get_lang_str_full(id), Some(language.name()));
+            assert_eq!(crate::get_lang_id(language.code()), Some(id));
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use crate::{WhisperContext, WhisperError, WhisperTokenData, WhisperTokenId};
+use std::collections::{HashMap, HashSet};
+
+/// A set of additive logit biases and hard token suppressions to apply while decoding.
+///
+/// Pass this to [`FullParams::set_logit_bias`](crate::FullParams::set_logit_bias)
+/// to apply it during [`WhisperState::full`](crate::WhisperState::full).
+///
+/// Words and phrases are tokenized with [`WhisperContext::tokenize`] exactly as given.
+/// Whisper tokenizes a word differently depending on whether it starts with a space
+/// and on its capitalization, so you likely want to add `" word"` as well as `"word"`,
+/// and possibly `" Word"`.
+///
+/// # Examples
+/// ```no_run
+/// # use whisper_rs::{FullParams, LogitBias, SamplingStrategy, WhisperContext, WhisperContextParameters};
+/// # let ctx = WhisperContext::new_with_params("model.bin", WhisperContextParameters::default()).unwrap();
+/// let mut bias = LogitBias::new();
+/// bias.bias_text(&ctx, " Kubernetes", 5.0).unwrap();
+/// bias.suppress_text(&ctx, " darn").unwrap();
+///
+/// let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+/// params.set_logit_bias(Some(bias));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct LogitBias {
+    token_biases: HashMap<WhisperTokenId, f32>,
+    suppressed_tokens: HashSet<WhisperTokenId>,
+    suppressed_sequences: Vec<Vec<WhisperTokenId>>,
+    token_eot: Option<WhisperTokenId>,
+}
+
+impl LogitBias {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add `bias` to the logit of `token` at every decoding step.
+    ///
+    /// Positive values make the token more likely, negative values less likely.
+    /// Biases for the same token accumulate.
+    pub fn bias_token(&mut self, token: WhisperTokenId, bias: f32) {
+        *self.token_biases.entry(token).or_insert(0.0) += bias;
+    }
+
+    /// Never sample `token`.
+    pub fn suppress_token(&mut self, token: WhisperTokenId) {
+        self.suppressed_tokens.insert(token);
+    }
+
+    /// Add `bias` to the logit of the first token of `text` at every decoding step.
+    ///
+    /// Only the first token is biased, as this is plain logit biasing:
+    /// once the model has started a word it will usually finish it on its own.
+    ///
+    /// # Errors
+    /// * [`WhisperError::NullByteInString`] if `text` contains a null byte.
+    /// * [`WhisperError::InvalidText`] if `text` could not be tokenized or is empty.
+    pub fn bias_text(
+        &mut self,
+        ctx: &WhisperContext,
+        text: &str,
+        bias: f32,
+    ) -> Result<(), WhisperError> {
+        let tokens = self.tokenize(ctx, text)?;
+        self.bias_token(tokens[0], bias);
+        Ok(())
+    }
+
+    /// Never produce `text`.
+    ///
+    /// If `text` is a single token, that token is suppressed at every step.
+    /// Otherwise the last token of `text` is suppressed whenever the tokens decoded so far end with
+    /// the rest of `text`, so the exact token sequence can never be completed
+    /// while other words sharing a prefix with it are still allowed.
+    ///
+    /// # Errors
+    /// * [`WhisperError::NullByteInString`] if `text` contains a null byte.
+    /// * [`WhisperError::InvalidText`] if `text` could not be tokenized or is empty.
+    pub fn suppress_text(&mut self, ctx: &WhisperContext, text: &str) -> Result<(), WhisperError> {
+        let tokens = self.tokenize(ctx, text)?;
+        if let [token] = tokens[..] {
+            self.suppress_token(token);
+        } else {
+            self.suppressed_sequences.push(tokens);
+        }
+        Ok(())
+    }
+
+    fn tokenize(
+        &mut self,
+        ctx: &WhisperContext,
+        text: &str,
+    ) -> Result<Vec<WhisperTokenId>, WhisperError> {
+        let tokens = tokenize_phrase(ctx, text)?;
+        self.token_eot = Some(ctx.token_eot());
+        Ok(tokens)
+    }
+
+    /// Returns true if this applies no biases or suppressions at all.
+    pub fn is_empty(&self) -> bool {
+        self.token_biases.is_empty()
+            && self.suppressed_tokens.is_empty()
+            && self.suppressed_sequences.is_empty()
+    }
+
+    /// Apply the biases and suppressions to `logits`, given the `tokens` decoded so far.
+    ///
+    /// This is what [`FullParams::set_logit_bias`](crate::FullParams::set_logit_bias) runs
+    /// for each decoding step. Call it yourself from within a closure passed to
+    /// [`FullParams::set_filter_logits_callback_safe`](crate::FullParams::set_filter_logits_callback_safe)
+    /// to combine it with your own filtering.
+    ///
+    /// Token IDs outside of `logits` are ignored.
+    pub fn apply(&self, tokens: &[WhisperTokenData], logits: &mut [f32]) {
+        for (&token, &bias) in &self.token_biases {
+            if let Some(logit) = logit_mut(logits, token) {
+                *logit += bias;
+            }
+        }
+
+        for &token in &self.suppressed_tokens {
+            if let Some(logit) = logit_mut(logits, token) {
+                *logit = f32::NEG_INFINITY;
+            }
+        }
+
+        if self.suppressed_sequences.is_empty() {
+            return;
+        }
+        let text_tokens = text_tokens(tokens, self.token_eot);
+        for sequence in &self.suppressed_sequences {
+            let (&last, prefix) = sequence
+                .split_last()
+                .expect("suppressed sequences are never empty");
+            if text_tokens.ends_with(prefix) {
+                if let Some(logit) = logit_mut(logits, last) {
+                    *logit = f32::NEG_INFINITY;
+                }
+            }
+        }
+    }
+}
+
+/// Tokenize a user-provided word or phrase, rejecting text that results in no tokens.
+pub(crate) fn tokenize_phrase(
+    ctx: &WhisperContext,
+    text: &str,
+) -> Result<Vec<WhisperTokenId>, WhisperError> {
+    // every token is at least one byte long
+    let tokens = ctx.tokenize(text, text.len() + 1)?;
+    if tokens.is_empty() {
+        return Err(WhisperError::InvalidText);
+    }
+    Ok(tokens)
+}
+
+/// The IDs of the decoded text tokens, without any special or timestamp tokens.
+///
+/// whisper.cpp interleaves timestamp tokens with text, so these must be skipped over
+/// to match multi-token phrases. All special tokens have an ID of at least `token_eot`.
+pub(crate) fn text_tokens(
+    tokens: &[WhisperTokenData],
+    token_eot: Option<WhisperTokenId>,
+) -> Vec<WhisperTokenId> {
+    tokens
+        .iter()
+        .map(|t| t.id)
+        .filter(|&id| token_eot.is_none_or(|eot| id < eot))
+        .collect()
+}
+
+pub(crate) fn logit_mut(logits: &mut [f32], token: WhisperTokenId) -> Option<&mut f32> {
+    usize::try_from(token).ok().and_then(|t| logits.get_mut(t))
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    pub(crate) fn token_data(id: WhisperTokenId) -> WhisperTokenData {
+        WhisperTokenData {
+            id,
+            tid: -1,
+            p: 0.0,
+            plog: 0.0,
+            pt: 0.0,
+            ptsum: 0.0,
+            t0: -1,
+            t1: -1,
+            t_dtw: -1,
+            vlen: 0.0,
+        }
+    }
+
+    #[test]
+    fn test_token_bias_accumulates() {
+        let mut bias = LogitBias::new();
+        bias.bias_token(2, 1.5);
+        bias.bias_token(2, 1.0);
+        bias.bias_token(0, -3.0);
+        let mut logits = vec![0.0; 4];
+        bias.apply(&[], &mut logits);
+        assert_eq!(logits, [-3.0, 0.0, 2.5, 0.0]);
+    }
+
+    #[test]
+    fn test_suppress_token_overrides_bias() {
+        let mut bias = LogitBias::new();
+        bias.bias_token(1, 10.0);
+        bias.suppress_token(1);
+        let mut logits = vec![0.0; 3];
+        bias.apply(&[], &mut logits);
+        assert_eq!(logits[1], f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_out_of_range_tokens_are_ignored() {
+        let mut bias = LogitBias::new();
+        bias.bias_token(-1, 1.0);
+        bias.suppress_token(100);
+        let mut logits = vec![0.0; 3];
+        bias.apply(&[], &mut logits);
+        assert_eq!(logits, [0.0; 3]);
+    }
+
+    #[test]
+    fn test_suppressed_sequence_only_blocks_completion() {
+        let mut bias = LogitBias::new();
+        bias.suppressed_sequences.push(vec![3, 4, 5]);
+        bias.token_eot = Some(10);
+
+        let mut logits = vec![0.0; 12];
+        bias.apply(&[token_data(1), token_data(3)], &mut logits);
+        assert_eq!(logits[5], 0.0, "prefix is incomplete");
+
+        // timestamp tokens in between are skipped
+        let decoded = [token_data(3), token_data(11), token_data(4)];
+        bias.apply(&decoded, &mut logits);
+        assert_eq!(logits[5], f32::NEG_INFINITY);
+        assert_eq!(logits[3], 0.0);
+        assert_eq!(logits[4], 0.0);
+    }
+}
@@ -1,8 +1,11 @@
-use crate::whisper_grammar::WhisperGrammarElement;
+use crate::common_logging::generic_error;
+use crate::whisper_grammar::{split_rules, WhisperGrammarElement};
+use crate::whisper_hotwords::HotwordBoost;
+use crate::whisper_logit_bias::LogitBias;
 use crate::whisper_vad::WhisperVadParams;
-use std::ffi::{c_char, c_float, c_int, CString};
-use std::marker::PhantomData;
-use std::sync::Arc;
+use crate::{Language, WhisperError, WhisperTokenData};
+use std::ffi::{c_float, c_int, c_void, CStr, CString};
+use std::sync::{Arc, Mutex};
 use whisper_rs_sys::whisper_token;
 
 /// The sampling strategy to use to pick tokens from a list of likely possibilities.
@@ -15,6 +18,9 @@ pub enum SamplingStrategy {
     },
     /// Beam search. Much harder to explain in a blurb.
     /// Tends to be more accurate in exchange for more CPU time.
+    ///
+    /// Only the best hypothesis is returned. Use [`Decoder::beam_search`](crate::Decoder::beam_search)
+    /// to get the others as well.
     BeamSearch {
         /// The maximum width of the beam.
         /// Higher values are better (to a point) at the cost of exponential CPU time.
@@ -36,22 +42,63 @@ pub struct SegmentCallbackData {
     pub text: String,
 }
 
-type SegmentCallbackFn = Box<dyn FnMut(SegmentCallbackData)>;
+type SegmentCallbackFn = Box<dyn FnMut(SegmentCallbackData) + Send>;
+type ProgressCallbackFn = Box<dyn FnMut(i32) + Send>;
+type AbortCallbackFn = Box<dyn FnMut() -> bool + Send>;
+type LogitsFilterFn = Box<dyn FnMut(&[WhisperTokenData], &mut [f32]) + Send>;
+
+/// Grammar rules converted to C types, and the array of pointers to each rule that whisper.cpp takes.
+/// These are only kept alive for `whisper_full_params::grammar_rules`.
+#[allow(dead_code)]
+struct GrammarRules {
+    rules: Vec<Vec<whisper_rs_sys::whisper_grammar_element>>,
+    rule_ptrs: Vec<*const whisper_rs_sys::whisper_grammar_element>,
+}
 
+// the pointers only point into the rules, which are never mutated
+unsafe impl Send for GrammarRules {}
+unsafe impl Sync for GrammarRules {}
+
+/// Parameters for [`WhisperState::full`](crate::WhisperState::full).
+///
+/// `FullParams` owns every string, token buffer, grammar and closure set on it,
+/// and frees them when the last clone referring to them is dropped.
+/// It can be stored for as long as needed and sent to other threads.
+///
+/// Clones share the strings, buffers and closures that were set before cloning,
+/// so a closure set on one `FullParams` is called by every clone of it.
+/// Calls to the same closure are serialized.
 #[derive(Clone)]
-pub struct FullParams<'a, 'b> {
+pub struct FullParams {
     pub(crate) fp: whisper_rs_sys::whisper_full_params,
-    phantom_lang: PhantomData<&'a str>,
-    phantom_tokens: PhantomData<&'b [c_int]>,
-    grammar: Option<Vec<whisper_rs_sys::whisper_grammar_element>>,
-    progress_callback_safe: Option<Arc<Box<dyn FnMut(i32)>>>,
-    abort_callback_safe: Option<Arc<Box<dyn FnMut() -> bool>>>,
-    segment_calllback_safe: Option<Arc<SegmentCallbackFn>>,
+    language: Option<Arc<CString>>,
+    allowed_languages: Option<Arc<[Language]>>,
+    prompt_tokens: Option<Arc<[c_int]>>,
+    initial_prompt: Option<Arc<CString>>,
+    vad_model_path: Option<Arc<CString>>,
+    grammar: Option<Arc<GrammarRules>>,
+    suppress_regex: Option<Arc<CString>>,
+    progress_callback_safe: Option<Arc<Mutex<ProgressCallbackFn>>>,
+    abort_callback_safe: Option<Arc<Mutex<AbortCallbackFn>>>,
+    segment_callback_safe: Option<Arc<Mutex<SegmentCallbackFn>>>,
+    logits_filter_callback_safe: Option<Arc<Mutex<LogitsFilterFn>>>,
+}
+
+/// Lock a closure shared with C, which remains usable even if a previous call panicked.
+fn lock_closure<T: ?Sized>(closure: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    closure
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
 }
 
-impl<'a, 'b> FullParams<'a, 'b> {
+/// A pointer to the shared closure, to pass to C as user data.
+fn closure_user_data<T>(closure: &Arc<Mutex<T>>) -> *mut c_void {
+    Arc::as_ptr(closure) as *mut c_void
+}
+
+impl FullParams {
     /// Create a new set of parameters for the decoder.
-    pub fn new(sampling_strategy: SamplingStrategy) -> FullParams<'a, 'b> {
+    pub fn new(sampling_strategy: SamplingStrategy) -> FullParams {
         let mut fp = unsafe {
             whisper_rs_sys::whisper_full_default_params(match sampling_strategy {
                 SamplingStrategy::Greedy { .. } => {
@@ -82,12 +129,37 @@ impl<'a, 'b> FullParams<'a, 'b> {
 
         Self {
             fp,
-            phantom_lang: PhantomData,
-            phantom_tokens: PhantomData,
+            language: None,
+            allowed_languages: None,
+            prompt_tokens: None,
+            initial_prompt: None,
+            vad_model_path: None,
             grammar: None,
+            suppress_regex: None,
             progress_callback_safe: None,
             abort_callback_safe: None,
-            segment_calllback_safe: None,
+            segment_callback_safe: None,
+            logits_filter_callback_safe: None,
+        }
+    }
+
+    /// Switch to another sampling strategy, keeping every other parameter.
+    pub fn set_sampling_strategy(&mut self, sampling_strategy: SamplingStrategy) {
+        match sampling_strategy {
+            SamplingStrategy::Greedy { best_of } => {
+                self.fp.strategy =
+                    whisper_rs_sys::whisper_sampling_strategy_WHISPER_SAMPLING_GREEDY as _;
+                self.fp.greedy.best_of = best_of;
+            }
+            SamplingStrategy::BeamSearch {
+                beam_size,
+                patience,
+            } => {
+                self.fp.strategy =
+                    whisper_rs_sys::whisper_sampling_strategy_WHISPER_SAMPLING_BEAM_SEARCH as _;
+                self.fp.beam_search.beam_size = beam_size.max(1);
+                self.fp.beam_search.patience = patience;
+            }
         }
     }
 
@@ -266,8 +338,12 @@ impl<'a, 'b> FullParams<'a, 'b> {
     ///
     /// Calling this more than once will overwrite the previous tokens.
     ///
+    /// The tokens are copied.
+    ///
     /// Defaults to an empty vector.
-    pub fn set_tokens(&mut self, tokens: &'b [c_int]) {
+    pub fn set_tokens(&mut self, tokens: &[c_int]) {
+        let tokens: Arc<[c_int]> = Arc::from(tokens);
+
         // turn into ptr and len
         let tokens_ptr: *const whisper_token = tokens.as_ptr();
         let tokens_len: c_int = tokens.len() as c_int;
@@ -275,6 +351,7 @@ impl<'a, 'b> FullParams<'a, 'b> {
         // set the tokens
         self.fp.prompt_tokens = tokens_ptr;
         self.fp.prompt_n_tokens = tokens_len;
+        self.prompt_tokens = Some(tokens);
     }
 
     /// Set the target language.
@@ -282,13 +359,39 @@ impl<'a, 'b> FullParams<'a, 'b> {
     /// For auto-detection, set this to either "auto" or None.
     ///
     /// Defaults to "en".
-    pub fn set_language(&mut self, language: Option<&'a str>) {
-        self.fp.language = match language {
-            Some(language) => CString::new(language)
-                .expect("Language contains null byte")
-                .into_raw() as *const _,
-            None => std::ptr::null(),
+    ///
+    /// # Panics
+    /// This method will panic if `language` contains a null byte.
+    pub fn set_language(&mut self, language: Option<&str>) {
+        let language = language
+            .map(|language| Arc::new(CString::new(language).expect("Language contains null byte")));
+        self.fp.language = language
+            .as_ref()
+            .map_or(std::ptr::null(), |language| language.as_ptr());
+        self.language = language;
+    }
+
+    /// Restrict language detection to `languages`.
+    ///
+    /// If the language is left to be detected, [`WhisperState::full`](crate::WhisperState::full)
+    /// picks the most likely of these with [`WhisperState::lang_detect_among`](crate::WhisperState::lang_detect_among)
+    /// and transcribes in it, instead of letting whisper.cpp pick any language it knows.
+    /// Not applied with `detect_language`, use `lang_detect_among` to only detect the language.
+    ///
+    /// Defaults to None, allowing every language.
+    pub fn set_allowed_languages(&mut self, languages: Option<&[Language]>) {
+        self.allowed_languages = languages.map(Arc::from);
+    }
+
+    /// The languages to detect among before running whisper.cpp, if any.
+    pub(crate) fn restricted_detection(&self) -> Option<&[Language]> {
+        let auto = self.fp.language.is_null() || {
+            let language = unsafe { std::ffi::CStr::from_ptr(self.fp.language) }.to_bytes();
+            language.is_empty() || language == b"auto"
         };
+        self.allowed_languages
+            .as_deref()
+            .filter(|_| auto && !self.fp.detect_language)
     }
 
     /// Set `detect_language`.
@@ -318,6 +421,40 @@ impl<'a, 'b> FullParams<'a, 'b> {
         self.fp.suppress_nst = suppress_nst;
     }
 
+    /// Never sample a token whose text matches `regex`.
+    ///
+    /// The pattern uses C++ `std::regex` (ECMAScript) syntax, and has to match the whole token.
+    /// Tokens that start a new word include the leading space, so to suppress all numbers use
+    /// `" ?[0-9]+"`, and to suppress every token containing a digit use `".*[0-9].*"`.
+    ///
+    /// Passing `None` stops suppressing tokens by pattern.
+    ///
+    /// # Errors
+    /// * [`WhisperError::NullByteInString`] if `regex` contains a null byte.
+    /// * [`WhisperError::InvalidRegex`] if `regex` is not a valid pattern: unbalanced
+    ///   parentheses or brackets, a quantifier with nothing to repeat, a malformed `{m,n}`,
+    ///   an unknown `(?` group, a reversed range in a class, an unknown class name like `[[:foo:]]`,
+    ///   a malformed `\x`, `\u` or `\c` escape, a backreference to a group that does not exist
+    ///   or a trailing backslash.
+    ///
+    /// The previously set pattern is kept in either case.
+    ///
+    /// `whisper.cpp` only compiles the pattern once decoding starts and aborts the process
+    /// if that fails, so the syntax is checked here first.
+    ///
+    /// Defaults to None.
+    pub fn set_suppress_regex(&mut self, regex: Option<&str>) -> Result<(), WhisperError> {
+        if let Some(regex) = regex {
+            check_regex(regex).map_err(|idx| WhisperError::InvalidRegex { idx })?;
+        }
+        let regex = regex.map(CString::new).transpose()?.map(Arc::new);
+        self.fp.suppress_regex = regex
+            .as_ref()
+            .map_or(std::ptr::null(), |regex| regex.as_ptr());
+        self.suppress_regex = regex;
+        Ok(())
+    }
+
     /// Set initial decoding temperature.
     /// See <https://ai.stackexchange.com/a/32478> for more information.
     ///
@@ -417,25 +554,22 @@ impl<'a, 'b> FullParams<'a, 'b> {
     /// Defaults to None.
     pub fn set_segment_callback_safe<O, F>(&mut self, closure: O)
     where
-        F: FnMut(SegmentCallbackData) + 'static,
+        F: FnMut(SegmentCallbackData) + Send + 'static,
         O: Into<Option<F>>,
     {
-        use std::ffi::{c_void, CStr};
+        use std::ffi::CStr;
         use whisper_rs_sys::{whisper_context, whisper_state};
 
-        extern "C" fn trampoline<F>(
+        extern "C" fn trampoline(
             _: *mut whisper_context,
             state: *mut whisper_state,
             n_new: i32,
             user_data: *mut c_void,
-        ) where
-            F: FnMut(SegmentCallbackData) + 'static,
-        {
+        ) {
             unsafe {
-                let user_data = &mut *(user_data as *mut SegmentCallbackFn);
+                let mut user_data = lock_closure(&*(user_data as *const Mutex<SegmentCallbackFn>));
                 let n_segments = whisper_rs_sys::whisper_full_n_segments_from_state(state);
                 let s0 = n_segments - n_new;
-                //let user_data = user_data as *mut Box<dyn FnMut(SegmentCallbackData)>;
 
                 for i in s0..n_segments {
                     let text = whisper_rs_sys::whisper_full_get_segment_text_from_state(state, i);
@@ -456,25 +590,12 @@ impl<'a, 'b> FullParams<'a, 'b> {
             }
         }
 
-        match closure.into() {
-            Some(closure) => {
-                // Stable address
-                let closure = Box::new(closure) as SegmentCallbackFn;
-                // Thin pointer
-                let closure = Box::new(closure);
-                // Raw pointer
-                let closure = Box::into_raw(closure);
-
-                self.fp.new_segment_callback_user_data = closure as *mut c_void;
-                self.fp.new_segment_callback = Some(trampoline::<SegmentCallbackFn>);
-                self.segment_calllback_safe = None;
-            }
-            None => {
-                self.segment_calllback_safe = None;
-                self.fp.new_segment_callback = None;
-                self.fp.new_segment_callback_user_data = std::ptr::null_mut::<c_void>();
-            }
-        }
+        self.set_segment_callback_closure(
+            closure
+                .into()
+                .map(|closure| Box::new(closure) as SegmentCallbackFn),
+            trampoline,
+        );
     }
 
     /// Set the callback for segment updates.
@@ -486,25 +607,22 @@ impl<'a, 'b> FullParams<'a, 'b> {
     /// Defaults to None.
     pub fn set_segment_callback_safe_lossy<O, F>(&mut self, closure: O)
     where
-        F: FnMut(SegmentCallbackData) + 'static,
+        F: FnMut(SegmentCallbackData) + Send + 'static,
         O: Into<Option<F>>,
     {
-        use std::ffi::{c_void, CStr};
+        use std::ffi::CStr;
         use whisper_rs_sys::{whisper_context, whisper_state};
 
-        extern "C" fn trampoline<F>(
+        extern "C" fn trampoline(
             _: *mut whisper_context,
             state: *mut whisper_state,
             n_new: i32,
             user_data: *mut c_void,
-        ) where
-            F: FnMut(SegmentCallbackData) + 'static,
-        {
+        ) {
             unsafe {
-                let user_data = &mut *(user_data as *mut SegmentCallbackFn);
+                let mut user_data = lock_closure(&*(user_data as *const Mutex<SegmentCallbackFn>));
                 let n_segments = whisper_rs_sys::whisper_full_n_segments_from_state(state);
                 let s0 = n_segments - n_new;
-                //let user_data = user_data as *mut Box<dyn FnMut(SegmentCallbackData)>;
 
                 for i in s0..n_segments {
                     let text = whisper_rs_sys::whisper_full_get_segment_text_from_state(state, i);
@@ -522,23 +640,37 @@ impl<'a, 'b> FullParams<'a, 'b> {
             }
         }
 
-        match closure.into() {
+        self.set_segment_callback_closure(
+            closure
+                .into()
+                .map(|closure| Box::new(closure) as SegmentCallbackFn),
+            trampoline,
+        );
+    }
+
+    fn set_segment_callback_closure(
+        &mut self,
+        closure: Option<SegmentCallbackFn>,
+        trampoline: unsafe extern "C" fn(
+            *mut whisper_rs_sys::whisper_context,
+            *mut whisper_rs_sys::whisper_state,
+            i32,
+            *mut c_void,
+        ),
+    ) {
+        match closure {
             Some(closure) => {
-                // Stable address
-                let closure = Box::new(closure) as SegmentCallbackFn;
-                // Thin pointer
-                let closure = Box::new(closure);
-                // Raw pointer
-                let closure = Box::into_raw(closure);
-
-                self.fp.new_segment_callback_user_data = closure as *mut c_void;
-                self.fp.new_segment_callback = Some(trampoline::<SegmentCallbackFn>);
-                self.segment_calllback_safe = None;
+                // Stable address, owned by self and any clones
+                let closure = Arc::new(Mutex::new(closure));
+
+                self.fp.new_segment_callback_user_data = closure_user_data(&closure);
+                self.fp.new_segment_callback = Some(trampoline);
+                self.segment_callback_safe = Some(closure);
             }
             None => {
-                self.segment_calllback_safe = None;
                 self.fp.new_segment_callback = None;
                 self.fp.new_segment_callback_user_data = std::ptr::null_mut::<c_void>();
+                self.segment_callback_safe = None;
             }
         }
     }
@@ -570,32 +702,29 @@ impl<'a, 'b> FullParams<'a, 'b> {
     /// Defaults to None.
     pub fn set_progress_callback_safe<O, F>(&mut self, closure: O)
     where
-        F: FnMut(i32) + 'static,
+        F: FnMut(i32) + Send + 'static,
         O: Into<Option<F>>,
     {
-        use std::ffi::c_void;
         use whisper_rs_sys::{whisper_context, whisper_state};
 
-        unsafe extern "C" fn trampoline<F>(
+        unsafe extern "C" fn trampoline(
             _: *mut whisper_context,
             _: *mut whisper_state,
             progress: c_int,
             user_data: *mut c_void,
-        ) where
-            F: FnMut(i32),
-        {
-            let user_data = &mut *(user_data as *mut F);
+        ) {
+            let mut user_data = lock_closure(&*(user_data as *const Mutex<ProgressCallbackFn>));
             user_data(progress);
         }
 
         match closure.into() {
             Some(closure) => {
-                self.fp.progress_callback = Some(trampoline::<Box<dyn FnMut(i32)>>);
-                let boxed_closure = Box::new(closure) as Box<dyn FnMut(i32)>;
-                let boxed_closure = Box::new(boxed_closure);
-                let raw_ptr = Box::into_raw(boxed_closure);
-                self.fp.progress_callback_user_data = raw_ptr as *mut c_void;
-                self.progress_callback_safe = None;
+                // Stable address, owned by self and any clones
+                let closure = Arc::new(Mutex::new(Box::new(closure) as ProgressCallbackFn));
+
+                self.fp.progress_callback = Some(trampoline);
+                self.fp.progress_callback_user_data = closure_user_data(&closure);
+                self.progress_callback_safe = Some(closure);
             }
             None => {
                 self.fp.progress_callback = None;
@@ -616,31 +745,22 @@ impl<'a, 'b> FullParams<'a, 'b> {
     /// Defaults to None.
     pub fn set_abort_callback_safe<O, F>(&mut self, closure: O)
     where
-        F: FnMut() -> bool + 'static,
+        F: FnMut() -> bool + Send + 'static,
         O: Into<Option<F>>,
     {
-        use std::ffi::c_void;
-
-        unsafe extern "C" fn trampoline<F>(user_data: *mut c_void) -> bool
-        where
-            F: FnMut() -> bool,
-        {
-            let user_data = &mut *(user_data as *mut F);
+        unsafe extern "C" fn trampoline(user_data: *mut c_void) -> bool {
+            let mut user_data = lock_closure(&*(user_data as *const Mutex<AbortCallbackFn>));
             user_data()
         }
 
         match closure.into() {
             Some(closure) => {
-                // Stable address
-                let closure = Box::new(closure) as Box<dyn FnMut() -> bool>;
-                // Thin pointer
-                let closure = Box::new(closure);
-                // Raw pointer
-                let closure = Box::into_raw(closure);
-
-                self.fp.abort_callback = Some(trampoline::<F>);
-                self.fp.abort_callback_user_data = closure as *mut c_void;
-                self.abort_callback_safe = None;
+                // Stable address, owned by self and any clones
+                let closure = Arc::new(Mutex::new(Box::new(closure) as AbortCallbackFn));
+
+                self.fp.abort_callback = Some(trampoline);
+                self.fp.abort_callback_user_data = closure_user_data(&closure);
+                self.abort_callback_safe = Some(closure);
             }
             None => {
                 self.fp.abort_callback = None;
@@ -722,6 +842,99 @@ impl<'a, 'b> FullParams<'a, 'b> {
         self.fp.logits_filter_callback_user_data = user_data;
     }
 
+    /// Set the callback that is called by each decoder to filter obtained logits, using a closure.
+    ///
+    /// The closure receives the tokens decoded so far in the current window
+    /// and a mutable slice of logits with a length of `n_vocab`.
+    /// Set a logit to [`f32::NEG_INFINITY`] to prevent that token from being sampled.
+    ///
+    /// whisper.cpp may run several decoders in parallel (e.g. when using beam search or `best_of > 1`),
+    /// so the closure must be [`Send`]. Calls are serialized, so the closure never runs concurrently with itself.
+    ///
+    /// Panics inside the closure are caught at the FFI boundary and logged.
+    /// The logits are left as the closure left them when it panicked.
+    ///
+    /// See `set_filter_logits_callback` if you need to use `whisper_context` and `whisper_state`.
+    ///
+    /// Defaults to None.
+    pub fn set_filter_logits_callback_safe<O, F>(&mut self, closure: O)
+    where
+        F: FnMut(&[WhisperTokenData], &mut [f32]) + Send + 'static,
+        O: Into<Option<F>>,
+    {
+        use whisper_rs_sys::{whisper_context, whisper_state, whisper_token_data};
+
+        unsafe extern "C" fn trampoline(
+            ctx: *mut whisper_context,
+            _: *mut whisper_state,
+            tokens: *const whisper_token_data,
+            n_tokens: c_int,
+            logits: *mut f32,
+            user_data: *mut c_void,
+        ) {
+            let n_vocab = whisper_rs_sys::whisper_n_vocab(ctx);
+            filter_logits(user_data, tokens, n_tokens, logits, n_vocab);
+        }
+
+        match closure.into() {
+            Some(closure) => {
+                // Stable address owned by self and any clones,
+                // and a lock as whisper.cpp may call this from several threads
+                let closure = Arc::new(Mutex::new(Box::new(closure) as LogitsFilterFn));
+
+                self.fp.logits_filter_callback = Some(trampoline);
+                self.fp.logits_filter_callback_user_data = closure_user_data(&closure);
+                self.logits_filter_callback_safe = Some(closure);
+            }
+            None => {
+                self.fp.logits_filter_callback = None;
+                self.fp.logits_filter_callback_user_data = std::ptr::null_mut::<c_void>();
+                self.logits_filter_callback_safe = None;
+            }
+        }
+    }
+
+    /// Bias or suppress tokens while decoding. See [`LogitBias`] for details.
+    ///
+    /// This is built on [`Self::set_filter_logits_callback_safe`], and replaces any closure set with it.
+    /// To combine both, call [`LogitBias::apply`] from within your own closure.
+    /// Passing `None` removes the logits filter.
+    ///
+    /// Defaults to None.
+    pub fn set_logit_bias(&mut self, logit_bias: Option<LogitBias>) {
+        match logit_bias {
+            Some(logit_bias) => self.set_filter_logits_callback_safe(
+                move |tokens: &[WhisperTokenData], logits: &mut [f32]| {
+                    logit_bias.apply(tokens, logits)
+                },
+            ),
+            None => {
+                self.set_filter_logits_callback_safe::<_, fn(&[WhisperTokenData], &mut [f32])>(None)
+            }
+        }
+    }
+
+    /// Boost hotword phrases depending on the tokens decoded so far. See [`HotwordBoost`] for details.
+    ///
+    /// This is built on [`Self::set_filter_logits_callback_safe`], and replaces any closure set with it,
+    /// including one set by [`Self::set_logit_bias`].
+    /// To combine them, call [`HotwordBoost::apply`] from within your own closure.
+    /// Passing `None` removes the logits filter.
+    ///
+    /// Defaults to None.
+    pub fn set_hotwords(&mut self, hotwords: Option<HotwordBoost>) {
+        match hotwords {
+            Some(hotwords) => self.set_filter_logits_callback_safe(
+                move |tokens: &[WhisperTokenData], logits: &mut [f32]| {
+                    hotwords.apply(tokens, logits)
+                },
+            ),
+            None => {
+                self.set_filter_logits_callback_safe::<_, fn(&[WhisperTokenData], &mut [f32])>(None)
+            }
+        }
+    }
+
     /// Set the callback that is called each time before ggml computation starts.
     ///
     /// Note that this callback has not been Rustified yet (and likely never will be, unless someone else feels the need to do so).
@@ -749,20 +962,26 @@ impl<'a, 'b> FullParams<'a, 'b> {
 
     /// Enable an array of grammar elements to be passed to the whisper model.
     ///
+    /// The elements of all rules are concatenated, and each rule must be terminated by
+    /// [`WhisperGrammarElementType::End`](crate::WhisperGrammarElementType::End).
+    /// Rule references refer to the index of a rule in this list.
+    /// See [`WhisperGrammar`](crate::WhisperGrammar) to build this from GBNF text.
+    ///
     /// Defaults to an empty vector.
     pub fn set_grammar(&mut self, grammar: Option<&[WhisperGrammarElement]>) {
         if let Some(grammar) = grammar {
-            // convert to c types
-            let inner = grammar.iter().map(|e| e.to_c_type()).collect::<Vec<_>>();
-            // turn into ptr and len
-            let grammar_ptr = inner.as_ptr() as *mut _;
-            let grammar_len = inner.len();
-
-            self.grammar = Some(inner);
-
-            // set the grammar
-            self.fp.grammar_rules = grammar_ptr;
-            self.fp.n_grammar_rules = grammar_len;
+            // convert to c types, one vector per rule
+            let rules = split_rules(grammar)
+                .into_iter()
+                .map(|rule| rule.into_iter().map(|e| e.to_c_type()).collect::<Vec<_>>())
+                .collect::<Vec<_>>();
+            // whisper.cpp takes an array of pointers to the start of each rule
+            let mut rule_ptrs = rules.iter().map(|rule| rule.as_ptr()).collect::<Vec<_>>();
+
+            self.fp.grammar_rules = rule_ptrs.as_mut_ptr();
+            self.fp.n_grammar_rules = rule_ptrs.len();
+
+            self.grammar = Some(Arc::new(GrammarRules { rules, rule_ptrs }));
         } else {
             self.grammar = None;
             self.fp.grammar_rules = std::ptr::null_mut();
@@ -806,9 +1025,10 @@ impl<'a, 'b> FullParams<'a, 'b> {
     /// // ... further usage of params ...
     /// ```
     pub fn set_initial_prompt(&mut self, initial_prompt: &str) {
-        self.fp.initial_prompt = CString::new(initial_prompt)
-            .expect("Initial prompt contains null byte")
-            .into_raw() as *const c_char;
+        let initial_prompt =
+            Arc::new(CString::new(initial_prompt).expect("Initial prompt contains null byte"));
+        self.fp.initial_prompt = initial_prompt.as_ptr();
+        self.initial_prompt = Some(initial_prompt);
     }
 
     /// Enable or disable VAD.
@@ -828,14 +1048,16 @@ impl<'a, 'b> FullParams<'a, 'b> {
     /// # Panics
     /// This method will panic if `vad_model_path` contains a null byte.
     pub fn set_vad_model_path(&mut self, vad_model_path: Option<&str>) {
-        self.fp.vad_model_path = if let Some(vad_model_path) = vad_model_path {
-            CString::new(vad_model_path)
-                .expect("VAD model path contains null byte")
-                .into_raw() as *const c_char
+        self.vad_model_path = if let Some(vad_model_path) = vad_model_path {
+            let vad_model_path =
+                Arc::new(CString::new(vad_model_path).expect("VAD model path contains null byte"));
+            self.fp.vad_model_path = vad_model_path.as_ptr();
+            Some(vad_model_path)
         } else {
             self.fp.vad = false;
+            self.fp.vad_model_path = std::ptr::null();
 
-            std::ptr::null()
+            None
         };
     }
 
@@ -843,19 +1065,231 @@ impl<'a, 'b> FullParams<'a, 'b> {
     pub fn set_vad_params(&mut self, params: WhisperVadParams) {
         self.fp.vad_params = params.into_inner();
     }
+
+    /// The VAD model path, if one is set.
+    pub(crate) fn vad_model_path(&self) -> Option<&CStr> {
+        self.vad_model_path.as_deref().map(CString::as_c_str)
+    }
 }
 
 // following implementations are safe
 // see https://github.com/ggerganov/whisper.cpp/issues/32#issuecomment-1272790388
-// concurrent usage is prevented by &mut self on methods that modify the struct
-unsafe impl Send for FullParams<'_, '_> {}
-unsafe impl Sync for FullParams<'_, '_> {}
+// concurrent usage is prevented by &mut self on methods that modify the struct,
+// the raw pointers in `fp` only point into the owned fields above, which are never mutated,
+// and all closures are Send and behind a Mutex
+unsafe impl Send for FullParams {}
+unsafe impl Sync for FullParams {}
+
+/// Call the closure set with [`FullParams::set_filter_logits_callback_safe`],
+/// everything its trampoline does once it knows `n_vocab`.
+///
+/// # Safety
+/// * `user_data` must point to the `Mutex<LogitsFilterFn>` of a live `FullParams`.
+/// * `tokens` must be null or point to `n_tokens` tokens.
+/// * `logits` must point to `n_vocab` logits.
+unsafe fn filter_logits(
+    user_data: *mut c_void,
+    tokens: *const whisper_rs_sys::whisper_token_data,
+    n_tokens: c_int,
+    logits: *mut f32,
+    n_vocab: c_int,
+) {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let user_data = &*(user_data as *const Mutex<LogitsFilterFn>);
+    let tokens = if tokens.is_null() || n_tokens <= 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(tokens, n_tokens as usize)
+    };
+    let logits = std::slice::from_raw_parts_mut(logits, n_vocab as usize);
+
+    let mut closure = lock_closure(user_data);
+    if catch_unwind(AssertUnwindSafe(|| closure(tokens, logits))).is_err() {
+        generic_error!("logits filter callback panicked, leaving logits as they were");
+    }
+}
+
+/// Check that `pattern` is a valid ECMAScript regular expression, as far as `std::regex` cares,
+/// and return the byte index of the first problem if it is not.
+///
+/// This errs on the side of accepting: it only rejects what `std::regex` is sure to reject.
+fn check_regex(pattern: &str) -> Result<(), usize> {
+    let bytes = pattern.as_bytes();
+    let mut groups = Vec::new();
+    // capture groups opened so far, which backreferences may refer to
+    let mut captures = 0;
+    // whether there is something before that a quantifier can repeat
+    let mut repeatable = false;
+    // whether the last thing was a quantifier, which `?` makes lazy
+    let mut quantified = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let was_quantified = std::mem::take(&mut quantified);
+        match c {
+            b'\\' => {
+                // \b and \B are assertions, everything else matches something
+                repeatable = !matches!(bytes.get(i + 1), Some(b'b' | b'B'));
+                i = check_escape(bytes, i, Some(captures))?;
+            }
+            b'(' => {
+                groups.push(i);
+                if bytes.get(i + 1) == Some(&b'?') {
+                    match bytes.get(i + 2) {
+                        Some(b':' | b'=' | b'!') => i += 2,
+                        _ => return Err(i),
+                    }
+                } else {
+                    captures += 1;
+                }
+                repeatable = false;
+            }
+            b')' => {
+                groups.pop().ok_or(i)?;
+                repeatable = true;
+            }
+            b'|' | b'^' | b'$' => repeatable = false,
+            b'?' if was_quantified => {}
+            b'*' | b'+' | b'?' | b'{' => {
+                if !repeatable {
+                    return Err(i);
+                }
+                if c == b'{' {
+                    i = check_braces(bytes, i)?;
+                }
+                repeatable = false;
+                quantified = true;
+            }
+            b'[' => {
+                i = check_class(bytes, i)?;
+                repeatable = true;
+            }
+            _ => repeatable = true,
+        }
+        i += 1;
+    }
+    match groups.first() {
+        Some(&open) => Err(open),
+        None => Ok(()),
+    }
+}
+
+/// Check the `{m}`, `{m,}` or `{m,n}` starting at `start` and return the index of its `}`.
+fn check_braces(bytes: &[u8], start: usize) -> Result<usize, usize> {
+    let close = start
+        + bytes[start..]
+            .iter()
+            .position(|&c| c == b'}')
+            .ok_or(start)?;
+    let bound = |digits: &[u8]| match digits.is_empty() {
+        false if digits.iter().all(u8::is_ascii_digit) => {
+            std::str::from_utf8(digits).ok()?.parse::<u64>().ok()
+        }
+        _ => None,
+    };
+    let inner = &bytes[start + 1..close];
+    let (min, max) = match inner.iter().position(|&c| c == b',') {
+        None => (bound(inner), bound(inner)),
+        Some(comma) if comma + 1 == inner.len() => (bound(&inner[..comma]), Some(u64::MAX)),
+        Some(comma) => (bound(&inner[..comma]), bound(&inner[comma + 1..])),
+    };
+    match (min, max) {
+        (Some(min), Some(max)) if min <= max => Ok(close),
+        _ => Err(start),
+    }
+}
+
+/// Check the escape starting with the backslash at `start` and return the index of its last byte.
+///
+/// `captures` is the number of capture groups opened before it, `None` inside a class,
+/// where there are no backreferences.
+fn check_escape(bytes: &[u8], start: usize, captures: Option<usize>) -> Result<usize, usize> {
+    let hex = |n: usize| match bytes.get(start + 2..start + 2 + n) {
+        Some(digits) if digits.iter().all(u8::is_ascii_hexdigit) => Ok(start + 1 + n),
+        _ => Err(start),
+    };
+    match (bytes.get(start + 1), captures) {
+        (None, _) => Err(start),
+        (Some(b'x'), _) => hex(2),
+        (Some(b'u'), _) => hex(4),
+        (Some(b'c'), _) if start + 2 == bytes.len() => Err(start),
+        (Some(b'c'), _) => Ok(start + 2),
+        (Some(b'1'..=b'9'), Some(captures)) => {
+            let end = start
+                + 1
+                + bytes[start + 1..]
+                    .iter()
+                    .take_while(|c| c.is_ascii_digit())
+                    .count();
+            let group = std::str::from_utf8(&bytes[start + 1..end])
+                .ok()
+                .and_then(|digits| digits.parse::<usize>().ok());
+            match group {
+                Some(group) if group <= captures => Ok(end - 1),
+                _ => Err(start),
+            }
+        }
+        (Some(_), _) => Ok(start + 1),
+    }
+}
+
+/// Check the character class starting at `start` and return the index of its `]`.
+fn check_class(bytes: &[u8], start: usize) -> Result<usize, usize> {
+    // the classes std::regex knows by name
+    const CLASS_NAMES: [&[u8]; 15] = [
+        b"alnum", b"alpha", b"blank", b"cntrl", b"digit", b"graph", b"lower", b"print", b"punct",
+        b"space", b"upper", b"xdigit", b"d", b"s", b"w",
+    ];
+    let mut i = start + 1;
+    if bytes.get(i) == Some(&b'^') {
+        i += 1;
+    }
+    // the last plain ASCII character, which may start a range
+    let mut previous: Option<u8> = None;
+    while i < bytes.len() {
+        match (bytes[i], previous, bytes.get(i + 1)) {
+            (b']', _, _) => return Ok(i),
+            (b'\\', _, _) => {
+                i = check_escape(bytes, i, None)?;
+                previous = None;
+            }
+            // [:name:], [.name.] and [=name=]
+            (b'[', _, Some(&kind @ (b':' | b'.' | b'='))) => {
+                let name_start = i + 2;
+                let len = bytes[name_start..]
+                    .windows(2)
+                    .position(|pair| pair == [kind, b']'])
+                    .ok_or(i)?;
+                let name = &bytes[name_start..name_start + len];
+                if name.is_empty() || kind == b':' && !CLASS_NAMES.contains(&name) {
+                    return Err(i);
+                }
+                i = name_start + len + 1;
+                previous = None;
+            }
+            (b'-', Some(from), Some(&to)) if to != b']' => {
+                if to.is_ascii() && to != b'\\' && to != b'[' && from > to {
+                    return Err(i);
+                }
+                // the end of the range cannot start another one
+                if to != b'\\' && to != b'[' {
+                    i += 1;
+                }
+                previous = None;
+            }
+            (c, _, _) => previous = c.is_ascii().then_some(c),
+        }
+        i += 1;
+    }
+    Err(start)
+}
 
 #[cfg(test)]
 mod test_whisper_params_initial_prompt {
     use super::*;
 
-    impl<'a, 'b> FullParams<'a, 'b> {
+    impl FullParams {
         pub fn get_initial_prompt(&self) -> &str {
             // SAFETY: Ensure this is safe and respects the lifetime of the string in self.fp
             unsafe {
@@ -927,3 +1361,373 @@ mod test_whisper_params_initial_prompt {
         );
     }
 }
+
+#[cfg(test)]
+mod test_whisper_params_filter_logits {
+    use super::*;
+
+    fn token(id: crate::WhisperTokenId) -> WhisperTokenData {
+        WhisperTokenData {
+            id,
+            tid: 0,
+            p: 0.5,
+            plog: 0.5f32.ln(),
+            pt: 0.0,
+            ptsum: 0.0,
+            t0: -1,
+            t1: -1,
+            t_dtw: -1,
+            vlen: 0.0,
+        }
+    }
+
+    fn filter(params: &FullParams, tokens: &[WhisperTokenData], logits: &mut [f32]) {
+        // SAFETY: the user data is kept alive by params, the buffers have the lengths passed
+        unsafe {
+            filter_logits(
+                params.fp.logits_filter_callback_user_data,
+                tokens.as_ptr(),
+                tokens.len() as c_int,
+                logits.as_mut_ptr(),
+                logits.len() as c_int,
+            )
+        }
+    }
+
+    #[test]
+    fn test_closure_sees_tokens_and_all_logits() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        let record = Arc::clone(&seen);
+        params.set_filter_logits_callback_safe(
+            move |tokens: &[WhisperTokenData], logits: &mut [f32]| {
+                let ids: Vec<_> = tokens.iter().map(|token| token.id).collect();
+                record.lock().unwrap().push((ids, logits.len()));
+                logits[3] = f32::NEG_INFINITY;
+            },
+        );
+        assert!(params.fp.logits_filter_callback.is_some());
+
+        let mut logits = vec![1.0f32; 51864];
+        filter(&params, &[token(7), token(42)], &mut logits);
+        filter(&params, &[], &mut logits);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [(vec![7, 42], 51864), (Vec::new(), 51864)]
+        );
+        assert_eq!(logits[3], f32::NEG_INFINITY);
+        assert_eq!(logits.iter().filter(|&&logit| logit == 1.0).count(), 51863);
+    }
+
+    #[test]
+    fn test_panicking_closure_is_caught() {
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_filter_logits_callback_safe(|_: &[WhisperTokenData], logits: &mut [f32]| {
+            logits[0] = f32::NEG_INFINITY;
+            panic!("filter failed");
+        });
+
+        let mut logits = vec![1.0f32; 8];
+        filter(&params, &[token(1)], &mut logits);
+        // whatever the closure did before panicking stays
+        assert_eq!(logits[0], f32::NEG_INFINITY);
+        assert_eq!(&logits[1..], [1.0; 7]);
+        // and the closure can be called again
+        filter(&params, &[token(1)], &mut logits);
+    }
+}
+
+#[cfg(test)]
+mod test_whisper_params_ownership {
+    use super::*;
+    use std::ffi::CStr;
+
+    fn assert_owned<T: Clone + Send + Sync + 'static>() {}
+
+    #[test]
+    fn test_full_params_is_owned() {
+        assert_owned::<FullParams>();
+    }
+
+    #[test]
+    fn test_clone_outlives_original() {
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        {
+            let language = String::from("de");
+            let tokens = vec![1, 2, 3];
+            params.set_language(Some(&language));
+            params.set_tokens(&tokens);
+            params.set_initial_prompt("Guten Tag");
+        }
+        let cloned = params.clone();
+        drop(params);
+
+        // SAFETY: the pointers are kept alive by the clone
+        unsafe {
+            assert_eq!(CStr::from_ptr(cloned.fp.language).to_str(), Ok("de"));
+            assert_eq!(
+                CStr::from_ptr(cloned.fp.initial_prompt).to_str(),
+                Ok("Guten Tag")
+            );
+            assert_eq!(
+                std::slice::from_raw_parts(cloned.fp.prompt_tokens, 3),
+                [1, 2, 3]
+            );
+        }
+        assert_eq!(cloned.fp.prompt_n_tokens, 3);
+    }
+
+    #[test]
+    fn test_closures_are_freed() {
+        let captured = Arc::new(());
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        let c = captured.clone();
+        params.set_progress_callback_safe(move |_| {
+            let _ = &c;
+        });
+        let c = captured.clone();
+        params.set_abort_callback_safe(move || {
+            let _ = &c;
+            false
+        });
+        let c = captured.clone();
+        params.set_segment_callback_safe(move |_| {
+            let _ = &c;
+        });
+        let c = captured.clone();
+        params.set_filter_logits_callback_safe(move |_: &[WhisperTokenData], _: &mut [f32]| {
+            let _ = &c;
+        });
+        assert_eq!(Arc::strong_count(&captured), 5);
+
+        let cloned = params.clone();
+        assert_eq!(
+            Arc::strong_count(&captured),
+            5,
+            "Clones should share closures."
+        );
+        drop(params);
+        assert_eq!(Arc::strong_count(&captured), 5);
+
+        drop(cloned);
+        assert_eq!(Arc::strong_count(&captured), 1);
+    }
+
+    #[test]
+    fn test_replaced_closure_is_freed() {
+        let captured = Arc::new(());
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        let c = captured.clone();
+        params.set_progress_callback_safe(move |_| {
+            let _ = &c;
+        });
+        assert_eq!(Arc::strong_count(&captured), 2);
+
+        params.set_progress_callback_safe(|_| {});
+        assert_eq!(Arc::strong_count(&captured), 1);
+        assert!(params.fp.progress_callback.is_some());
+
+        params.set_progress_callback_safe::<_, fn(i32)>(None);
+        assert!(params.fp.progress_callback.is_none());
+        assert!(params.fp.progress_callback_user_data.is_null());
+    }
+}
+
+#[cfg(test)]
+mod test_whisper_params_suppress_regex {
+    use super::*;
+    use std::ffi::CStr;
+
+    fn suppress_regex(params: &FullParams) -> Option<&str> {
+        if params.fp.suppress_regex.is_null() {
+            return None;
+        }
+        // SAFETY: the pointer is kept alive by params.suppress_regex
+        Some(
+            unsafe { CStr::from_ptr(params.fp.suppress_regex) }
+                .to_str()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_suppress_regex_set_and_clear() {
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        assert_eq!(suppress_regex(&params), None);
+
+        params.set_suppress_regex(Some(" ?[0-9]+")).unwrap();
+        assert_eq!(suppress_regex(&params), Some(" ?[0-9]+"));
+
+        params.set_suppress_regex(None).unwrap();
+        assert_eq!(suppress_regex(&params), None);
+    }
+
+    #[test]
+    fn test_suppress_regex_null_byte() {
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_suppress_regex(Some("[0-9]+")).unwrap();
+
+        let err = params.set_suppress_regex(Some("[0-9]\0+")).unwrap_err();
+        assert!(matches!(err, WhisperError::NullByteInString { idx: 5 }));
+        assert_eq!(
+            suppress_regex(&params),
+            Some("[0-9]+"),
+            "The previous pattern should be kept."
+        );
+    }
+
+    #[test]
+    fn test_suppress_regex_rejects_invalid_pattern() {
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_suppress_regex(Some("[0-9]+")).unwrap();
+
+        let err = params.set_suppress_regex(Some("([0-9]+")).unwrap_err();
+        assert!(matches!(err, WhisperError::InvalidRegex { idx: 0 }));
+        assert_eq!(
+            suppress_regex(&params),
+            Some("[0-9]+"),
+            "The previous pattern should be kept."
+        );
+    }
+
+    #[test]
+    fn test_check_regex() {
+        for valid in [
+            " ?[0-9]+",
+            ".*[0-9].*",
+            "(?:ab|cd)*?",
+            "a(?=b)|c(?!d)",
+            "[^a-zA-Z\\]]{2,}",
+            "x{3}y{1,4}",
+            "[]a-]\\(\\)",
+            "\\bword\\b",
+            "\u{e9}+",
+            "\\x41\\u00e9\\cJ\\0",
+            "(a)(b)\\2\\1",
+            "(a\\1)",
+            "[[:alpha:][:digit:]_]+",
+            "[\\x41-\\x5a\\d]",
+            "[[.a.]-z]",
+        ] {
+            assert_eq!(check_regex(valid), Ok(()), "{valid}");
+        }
+        for (invalid, idx) in [
+            ("(ab", 0),
+            ("ab)", 2),
+            ("[0-9", 0),
+            ("*a", 0),
+            ("a|+", 2),
+            ("a**", 2),
+            ("a{2", 1),
+            ("a{3,2}", 1),
+            ("a{x}", 1),
+            ("(?<name>a)", 0),
+            ("[z-a]", 2),
+            ("ab\\", 2),
+            ("^*", 1),
+            ("\\xZ", 0),
+            ("a\\u12", 1),
+            ("[\\x4]", 1),
+            ("\\c", 0),
+            ("(a)\\2", 3),
+            ("\\1(a)", 0),
+            ("[[:foo:]]", 1),
+            ("[[:alpha]", 1),
+        ] {
+            assert_eq!(check_regex(invalid), Err(idx), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_suppress_regex_survives_clone() {
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_suppress_regex(Some(".*[aA].*")).unwrap();
+        let cloned = params.clone();
+        drop(params);
+        assert_eq!(suppress_regex(&cloned), Some(".*[aA].*"));
+    }
+}
+
+#[cfg(test)]
+mod test_whisper_params_allowed_languages {
+    use super::*;
+
+    #[test]
+    fn test_allowed_languages_only_apply_to_detection() {
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_language(None);
+        assert_eq!(params.restricted_detection(), None);
+
+        let allowed = [Language::German, Language::English];
+        params.set_allowed_languages(Some(&allowed));
+        assert_eq!(params.restricted_detection(), Some(&allowed[..]));
+        params.set_language(Some("auto"));
+        assert_eq!(params.restricted_detection(), Some(&allowed[..]));
+
+        params.set_language(Some("de"));
+        assert_eq!(params.restricted_detection(), None);
+
+        params.set_language(Some(""));
+        params.set_detect_language(true);
+        assert_eq!(params.restricted_detection(), None);
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "test-with-tiny-model")]
+mod test_with_tiny_model {
+    use super::*;
+    use crate::{WhisperContext, WhisperContextParameters};
+    const MODEL_PATH: &str = "./sys/whisper.cpp/models/ggml-tiny.en.bin";
+    const SAMPLE_PATH: &str = "./sys/whisper.cpp/samples/jfk.wav";
+
+    // These tests expect that the tiny.en model has been downloaded
+    // using the script `sys/whisper.cpp/models/download-ggml-model.sh tiny.en`
+
+    fn text_tokens(regex: Option<&str>) -> Vec<String> {
+        let ctx = WhisperContext::new_with_params(MODEL_PATH, WhisperContextParameters::default())
+            .expect("Download the ggml-tiny.en model using 'sys/whisper.cpp/models/download-ggml-model.sh tiny.en'");
+        let samples: Vec<i16> = hound::WavReader::open(SAMPLE_PATH)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(Result::unwrap)
+            .collect();
+        let mut audio = vec![0.0f32; samples.len()];
+        crate::convert_integer_to_float_audio(&samples, &mut audio).unwrap();
+
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_suppress_regex(regex).unwrap();
+        let mut state = ctx.create_state().unwrap();
+        state.full(params, &audio).unwrap();
+
+        let mut tokens = Vec::new();
+        for segment in state.as_iter() {
+            for i in 0..segment.n_tokens() {
+                let token = segment.get_token(i).unwrap();
+                if token.token_id() < ctx.token_eot() {
+                    tokens.push(token.to_str_lossy().unwrap().into_owned());
+                }
+            }
+        }
+        tokens
+    }
+
+    #[test]
+    fn test_suppressed_tokens_never_appear() {
+        let unsuppressed = text_tokens(None);
+        assert!(
+            unsuppressed.iter().any(|t| t.contains(['a', 'A'])),
+            "The sample should contain the letter a."
+        );
+
+        let suppressed = text_tokens(Some(".*[aA].*"));
+        assert!(!suppressed.is_empty());
+        for token in &suppressed {
+            assert!(
+                !token.contains(['a', 'A']),
+                "Token {:?} should have been suppressed.",
+                token
+            );
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use crate::{AlignedSegment, AlignedWord, WhisperVadSegment};
+
+/// Samples per VAD probability, the window Silero VAD in whisper.cpp runs on.
+const VAD_WINDOW_SAMPLES: usize = 512;
+
+/// Moves timestamps onto the edges of detected speech.
+///
+/// Whisper's timestamps often start a bit early, in the silence before speech,
+/// or end a bit late. Given where speech was detected, a start within `tolerance`
+/// of the start of a speech region is moved onto it, and likewise for ends,
+/// which tightens subtitle timing without decoding anything again.
+/// Timestamps further away from any speech edge are left alone.
+///
+/// # Examples
+/// ```
+/// # use whisper_rs::{SpeechSnapper, WhisperVadSegment};
+/// let speech = [WhisperVadSegment { start: 120.0, end: 480.0 }];
+/// let snapper = SpeechSnapper::new(speech, 300);
+/// assert_eq!(snapper.snap_span(100, 500), (120, 480));
+/// assert_eq!(snapper.snap_span(0, 200), (0, 200));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpeechSnapper {
+    speech: Vec<WhisperVadSegment>,
+    tolerance: f32,
+}
+
+impl SpeechSnapper {
+    /// # Arguments
+    /// * speech: the speech regions, for example from
+    ///   [`WhisperVadContext::segments_from_samples`](crate::WhisperVadContext::segments_from_samples).
+    ///   Overlapping regions are merged.
+    /// * tolerance_ms: how far in milliseconds a timestamp may move.
+    pub fn new(speech: impl IntoIterator<Item = WhisperVadSegment>, tolerance_ms: u32) -> Self {
+        let mut regions: Vec<WhisperVadSegment> = speech
+            .into_iter()
+            .filter(|region| region.end > region.start)
+            .collect();
+        regions.sort_by(|a, b| a.start.total_cmp(&b.start));
+        let mut speech: Vec<WhisperVadSegment> = Vec::with_capacity(regions.len());
+        for region in regions {
+            match speech.last_mut() {
+                Some(last) if region.start <= last.end => last.end = last.end.max(region.end),
+                _ => speech.push(region),
+            }
+        }
+        Self {
+            speech,
+            tolerance: tolerance_ms as f32 / 10.0,
+        }
+    }
+
+    /// Build the speech regions from raw VAD probabilities instead, counting every window
+    /// with a probability of at least `threshold` as speech.
+    ///
+    /// # Arguments
+    /// * probabilities: from [`WhisperVadContext::probabilities`](crate::WhisperVadContext::probabilities),
+    ///   one for every 512 samples.
+    /// * threshold: like [`WhisperVadParams::set_threshold`](crate::WhisperVadParams::set_threshold).
+    /// * tolerance_ms: how far in milliseconds a timestamp may move.
+    pub fn from_probabilities(probabilities: &[f32], threshold: f32, tolerance_ms: u32) -> Self {
+        let window = VAD_WINDOW_SAMPLES as f32 * 100.0 / whisper_rs_sys::WHISPER_SAMPLE_RATE as f32;
+        let mut speech = Vec::new();
+        let mut start = None;
+        for (i, &probability) in probabilities.iter().enumerate() {
+            match (start, probability >= threshold) {
+                (None, true) => start = Some(i),
+                (Some(first), false) => {
+                    speech.push(WhisperVadSegment {
+                        start: first as f32 * window,
+                        end: i as f32 * window,
+                    });
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(first) = start {
+            speech.push(WhisperVadSegment {
+                start: first as f32 * window,
+                end: probabilities.len() as f32 * window,
+            });
+        }
+        Self::new(speech, tolerance_ms)
+    }
+
+    /// The merged speech regions, in order.
+    pub fn speech(&self) -> &[WhisperVadSegment] {
+        &self.speech
+    }
+
+    /// The speech onset nearest to `start` (in centiseconds) if it is within the tolerance, else `start`.
+    pub fn snap_start(&self, start: i64) -> i64 {
+        self.snap(start, |region| region.start)
+    }
+
+    /// The speech offset nearest to `end` (in centiseconds) if it is within the tolerance, else `end`.
+    pub fn snap_end(&self, end: i64) -> i64 {
+        self.snap(end, |region| region.end)
+    }
+
+    /// Snap both ends of a span, keeping the span as it is if snapping would turn it around.
+    pub fn snap_span(&self, start: i64, end: i64) -> (i64, i64) {
+        let (snapped_start, snapped_end) = (self.snap_start(start), self.snap_end(end));
+        if snapped_start <= snapped_end {
+            (snapped_start, snapped_end)
+        } else {
+            (start, end)
+        }
+    }
+
+    /// Snap words, for example from [`WhisperState::words`](crate::WhisperState::words).
+    pub fn snap_words(&self, words: &mut [AlignedWord]) {
+        for word in words {
+            (word.start, word.end) = self.snap_span(word.start, word.end);
+        }
+    }
+
+    /// Snap segments and their words, for example from [`WhisperState::align`](crate::WhisperState::align).
+    /// Words are kept inside their segment.
+    pub fn snap_segments(&self, segments: &mut [AlignedSegment]) {
+        for segment in segments {
+            (segment.start, segment.end) = self.snap_span(segment.start, segment.end);
+            self.snap_words(&mut segment.words);
+            for word in &mut segment.words {
+                word.start = word.start.clamp(segment.start, segment.end);
+                word.end = word.end.clamp(word.start, segment.end);
+            }
+        }
+    }
+
+    fn snap(&self, time: i64, edge: impl Fn(&WhisperVadSegment) -> f32) -> i64 {
+        let i = self
+            .speech
+            .partition_point(|region| edge(region) < time as f32);
+        let nearest = [i.checked_sub(1), Some(i)]
+            .into_iter()
+            .flatten()
+            .filter_map(|i| self.speech.get(i))
+            .map(edge)
+            .min_by(|a, b| (a - time as f32).abs().total_cmp(&(b - time as f32).abs()));
+        match nearest {
+            Some(edge) if (edge - time as f32).abs() <= self.tolerance => edge.round() as i64,
+            _ => time,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn region(start: f32, end: f32) -> WhisperVadSegment {
+        WhisperVadSegment { start, end }
+    }
+
+    fn word(start: i64, end: i64) -> AlignedWord {
+        AlignedWord {
+            text: String::from(" word"),
+            start,
+            end,
+            probability: 0.9,
+        }
+    }
+
+    #[test]
+    fn test_merges_regions() {
+        let snapper = SpeechSnapper::new(
+            [
+                region(500.0, 600.0),
+                region(100.0, 200.0),
+                region(150.0, 300.0),
+            ],
+            100,
+        );
+        assert_eq!(
+            snapper.speech(),
+            [region(100.0, 300.0), region(500.0, 600.0)]
+        );
+    }
+
+    #[test]
+    fn test_snaps_within_tolerance() {
+        let snapper = SpeechSnapper::new([region(100.0, 300.0), region(500.0, 600.0)], 200);
+        // early and late starts move to the onset
+        assert_eq!(snapper.snap_start(85), 100);
+        assert_eq!(snapper.snap_start(112), 100);
+        assert_eq!(snapper.snap_start(490), 500);
+        // too far from any onset
+        assert_eq!(snapper.snap_start(200), 200);
+        assert_eq!(snapper.snap_end(316), 300);
+        assert_eq!(snapper.snap_end(590), 600);
+        assert_eq!(snapper.snap_end(400), 400);
+        // a span is never turned around
+        assert_eq!(snapper.snap_span(295, 305), (295, 300));
+        assert_eq!(snapper.snap_span(305, 306), (305, 306));
+    }
+
+    #[test]
+    fn test_snap_segments_keeps_words_inside() {
+        let snapper = SpeechSnapper::new([region(100.0, 300.0)], 200);
+        let mut segments = [AlignedSegment {
+            text: String::from(" word word"),
+            start: 90,
+            end: 320,
+            words: vec![word(90, 200), word(200, 320)],
+        }];
+        snapper.snap_segments(&mut segments);
+        assert_eq!((segments[0].start, segments[0].end), (100, 300));
+        assert_eq!(segments[0].words, [word(100, 200), word(200, 300)]);
+    }
+
+    #[test]
+    fn test_from_probabilities() {
+        let probabilities = [0.1, 0.9, 0.8, 0.2, 0.6, 0.7];
+        let snapper = SpeechSnapper::from_probabilities(&probabilities, 0.5, 0);
+        assert_eq!(snapper.speech(), [region(3.2, 9.6), region(12.8, 19.2)]);
+        assert!(SpeechSnapper::from_probabilities(&[], 0.5, 0)
+            .speech()
+            .is_empty());
+    }
+}
@@ -0,0 +1,725 @@
+use crate::{Decoder, DecoderParams, WhisperError, WhisperState, WhisperTokenId};
+
+/// Length in mel frames (10 ms each) of the audio window the encoder sees.
+const WINDOW_FRAMES: i64 = 3000;
+
+/// A word of a transcript aligned with [`WhisperState::align`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlignedWord {
+    pub text: String,
+    /// Start time in centiseconds (10s of milliseconds).
+    pub start: i64,
+    /// End time in centiseconds (10s of milliseconds).
+    pub end: i64,
+    /// Average probability the model gave the tokens of this word.
+    pub probability: f32,
+}
+
+/// A segment of a transcript aligned with [`WhisperState::align`],
+/// split where the model would have put timestamps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlignedSegment {
+    pub text: String,
+    /// Start time in centiseconds (10s of milliseconds).
+    pub start: i64,
+    /// End time in centiseconds (10s of milliseconds).
+    pub end: i64,
+    pub words: Vec<AlignedWord>,
+}
+
+/// Confidence the model must give its most likely timestamp for it to time a token,
+/// whisper.cpp's default `thold_pt`.
+const THOLD_PT: f32 = 0.01;
+/// Confidence the model must give all timestamps together for them to time a token,
+/// whisper.cpp's default `thold_ptsum`.
+const THOLD_PTSUM: f32 = 0.01;
+/// Samples on either side of a sample averaged into its energy.
+const ENERGY_HALF_WINDOW: usize = 32;
+
+/// One token of output, in absolute time.
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    Timestamp(i64),
+    Text {
+        bytes: Vec<u8>,
+        start: i64,
+        end: i64,
+        probability: f32,
+    },
+}
+
+/// A token forced by [`WhisperState::align`] and what the model thought of the time at that step.
+#[derive(Debug, Clone, PartialEq)]
+struct ForcedToken {
+    bytes: Vec<u8>,
+    probability: f32,
+    /// Time of the most likely timestamp.
+    timestamp: i64,
+    /// Probability of the most likely timestamp.
+    pt: f32,
+    /// Probability of all timestamps together.
+    ptsum: f32,
+}
+
+/// A step of [`WhisperState::align`].
+#[derive(Debug, Clone, PartialEq)]
+enum Step {
+    Timestamp(i64),
+    Text(ForcedToken),
+}
+
+impl WhisperState {
+    /// Find out when each word of a known transcript is spoken in `audio`.
+    ///
+    /// `text` is tokenized and forced through the decoder one token at a time,
+    /// so the output always has exactly the words of `text`.
+    /// The model only gets to decide where timestamps go, which splits the text into segments.
+    /// Tokens are timed the way whisper.cpp does with
+    /// [`FullParams::set_token_timestamps`](crate::FullParams::set_token_timestamps):
+    /// by the timestamp the model expects while decoding them if it is confident enough,
+    /// by splitting the time in between by how long each token takes to say otherwise,
+    /// and finally by moving their edges to where the signal energy rises and falls.
+    /// Audio longer than 30 seconds is processed in windows like [`WhisperState::full`] does.
+    ///
+    /// `params` chooses the language and threads. Timestamps are always turned on
+    /// and the text aligned so far is used as the prompt for the next window.
+    ///
+    /// # Arguments
+    /// * audio: raw PCM audio data, 32 bit floating point at a sample rate of 16 kHz, 1 channel.
+    /// * text: the transcript of `audio`.
+    ///
+    /// # Errors
+    /// * [`WhisperError::NullByteInString`] if `text` contains a null byte.
+    /// * [`WhisperError::InvalidText`] if `text` could not be tokenized.
+    /// * Whatever [`WhisperState::pcm_to_mel`], [`WhisperState::encode`] or [`Decoder`] return.
+    pub fn align(
+        &mut self,
+        audio: &[f32],
+        text: &str,
+        params: &DecoderParams,
+    ) -> Result<Vec<AlignedSegment>, WhisperError> {
+        let text = format!(" {}", text.trim());
+        let tokens = self.ctx.tokenize(&text, text.len())?;
+        let token_bytes = tokens
+            .iter()
+            .map(|&token| self.ctx.token_to_bytes(token).map(<[u8]>::to_vec))
+            .collect::<Result<Vec<_>, _>>()?;
+        let beg = self.ctx.token_beg();
+        let eot = self.ctx.token_eot() as usize;
+
+        self.pcm_to_mel(audio, params.threads)?;
+        let n_len = self.n_len() as i64;
+        let mut params = params.clone();
+        params.timestamps(true);
+
+        let mut steps = Vec::new();
+        let mut pos = 0;
+        let mut seek = 0;
+        while pos < tokens.len() && seek < n_len {
+            self.encode(seek as usize, params.threads)?;
+            let last_window = seek + WINDOW_FRAMES >= n_len;
+            params.prompt_tokens(&tokens[..pos]);
+            let mut decoder = Decoder::new(self, &params)?;
+
+            let mut window = Vec::new();
+            let mut window_pos = pos;
+            let mut time = seek;
+            while !decoder.is_done() {
+                let logprobs = decoder.logprobs()?;
+                let (timestamp, timestamp_logprob) = (beg as usize..logprobs.len())
+                    .map(|token| (token as WhisperTokenId, logprobs[token]))
+                    .fold((beg, f32::NEG_INFINITY), |best, next| {
+                        if next.1 > best.1 {
+                            next
+                        } else {
+                            best
+                        }
+                    });
+                let ptsum: f32 = logprobs[beg as usize..]
+                    .iter()
+                    .map(|logprob| logprob.exp())
+                    .sum();
+                let text_logprob = match tokens.get(window_pos) {
+                    Some(&token) => logprobs[token as usize],
+                    None => f32::NEG_INFINITY,
+                };
+                let done = window_pos == tokens.len()
+                    || !last_window && logprobs[eot] > text_logprob.max(timestamp_logprob);
+                let timestamp_time = seek + 2 * (timestamp - beg) as i64;
+                if timestamp_logprob > f32::NEG_INFINITY {
+                    time = timestamp_time;
+                }
+                if done {
+                    // close the last segment if the model allows it
+                    if timestamp_logprob > f32::NEG_INFINITY
+                        && matches!(window.last(), Some(Step::Text(_)))
+                    {
+                        window.push(Step::Timestamp(time));
+                        decoder.push(timestamp);
+                    }
+                    break;
+                }
+                if text_logprob >= timestamp_logprob {
+                    window.push(Step::Text(ForcedToken {
+                        bytes: token_bytes[window_pos].clone(),
+                        probability: text_logprob.exp(),
+                        timestamp: timestamp_time,
+                        pt: timestamp_logprob.exp(),
+                        ptsum,
+                    }));
+                    decoder.push(tokens[window_pos]);
+                    window_pos += 1;
+                } else {
+                    window.push(Step::Timestamp(time));
+                    decoder.push(timestamp);
+                }
+            }
+
+            // like whisper.cpp, keep the text up to the last timestamp and continue from there,
+            // unless the model never timestamped anything
+            let seek_delta = decoder.seek_delta().map(i64::from);
+            let keep = match window
+                .iter()
+                .rposition(|step| matches!(step, Step::Timestamp(_)))
+            {
+                Some(i) if !last_window && seek_delta.is_some_and(|delta| delta > 0) => i + 1,
+                _ => window.len(),
+            };
+            window.truncate(keep);
+            pos += window
+                .iter()
+                .filter(|step| matches!(step, Step::Text(_)))
+                .count();
+            steps.append(&mut window);
+            seek += match seek_delta {
+                Some(delta) if keep > 0 && !last_window => delta,
+                _ => WINDOW_FRAMES,
+            };
+        }
+        // whatever the audio ran out before is squeezed in at the end
+        steps.extend(token_bytes[pos..].iter().map(|bytes| {
+            Step::Text(ForcedToken {
+                bytes: bytes.clone(),
+                probability: 0.0,
+                timestamp: n_len,
+                pt: 0.0,
+                ptsum: 0.0,
+            })
+        }));
+        let items = time_tokens(&steps, n_len, &signal_energy(audio));
+        Ok(segments(&items, n_len))
+    }
+
+    /// The words of the last [`WhisperState::full`] result with their times.
+    ///
+    /// With [`FullParams::set_token_timestamps`](crate::FullParams::set_token_timestamps)
+    /// on, words are timed by their tokens' own times, otherwise the segment's time
+    /// is spread evenly over its tokens.
+    ///
+    /// # Errors
+    /// Whatever [`WhisperToken::to_bytes`](crate::WhisperToken::to_bytes) returns.
+    pub fn words(&self) -> Result<Vec<AlignedWord>, WhisperError> {
+        let eot = self.ctx.token_eot();
+        let mut items = Vec::new();
+        let mut end = 0;
+        for segment in self.as_iter() {
+            let (t0, t1) = (segment.start_timestamp(), segment.end_timestamp());
+            let tokens: Vec<_> = (0..segment.n_tokens())
+                .filter_map(|i| segment.get_token(i))
+                .filter(|token| token.token_id() < eot)
+                .collect();
+            items.push(Item::Timestamp(t0));
+            for (i, token) in tokens.iter().enumerate() {
+                let data = token.token_data();
+                let (start, end) = if data.t0 >= 0 && data.t1 >= 0 {
+                    (data.t0, data.t1)
+                } else {
+                    let n = tokens.len() as i64;
+                    let i = i as i64;
+                    (t0 + (t1 - t0) * i / n, t0 + (t1 - t0) * (i + 1) / n)
+                };
+                items.push(Item::Text {
+                    bytes: token.to_bytes()?.to_vec(),
+                    start,
+                    end,
+                    probability: data.p,
+                });
+            }
+            items.push(Item::Timestamp(t1));
+            end = t1;
+        }
+        Ok(segments(&items, end)
+            .into_iter()
+            .flat_map(|segment| segment.words)
+            .collect())
+    }
+}
+
+/// Time the forced tokens between each pair of timestamps, the way whisper.cpp times tokens
+/// with `token_timestamps` on. Text before the first timestamp starts at 0
+/// and text after the last one runs to `end`.
+fn time_tokens(steps: &[Step], end: i64, energy: &[f32]) -> Vec<Item> {
+    let mut items = Vec::with_capacity(steps.len());
+    let mut start = 0;
+    let mut text: Vec<&ForcedToken> = Vec::new();
+    let flush = |items: &mut Vec<Item>, text: &mut Vec<&ForcedToken>, start, end| {
+        let times = token_times(text, start, end, energy);
+        items.extend(
+            text.drain(..)
+                .zip(times)
+                .map(|(token, (start, end))| Item::Text {
+                    bytes: token.bytes.clone(),
+                    start,
+                    end,
+                    probability: token.probability,
+                }),
+        );
+    };
+    for step in steps {
+        match step {
+            Step::Timestamp(time) => {
+                flush(&mut items, &mut text, start, *time);
+                items.push(Item::Timestamp(*time));
+                start = *time;
+            }
+            Step::Text(token) => text.push(token),
+        }
+    }
+    flush(&mut items, &mut text, start, end.max(start));
+    items
+}
+
+/// Start and end of every token of a segment from `t0` to `t1`,
+/// a port of whisper.cpp's `whisper_exp_compute_token_level_timestamps`.
+fn token_times(tokens: &[&ForcedToken], t0: i64, t1: i64, energy: &[f32]) -> Vec<(i64, i64)> {
+    let n = tokens.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    let mut times = vec![(-1, -1); n];
+    times[0].0 = t0;
+    // times the model was confident about, which must increase
+    let mut last = t0;
+    for (j, token) in tokens.iter().enumerate() {
+        if token.pt > THOLD_PT
+            && token.ptsum > THOLD_PTSUM
+            && token.timestamp > last
+            && token.timestamp <= t1
+        {
+            if j > 0 {
+                times[j - 1].1 = token.timestamp;
+            }
+            times[j].0 = token.timestamp;
+            last = token.timestamp;
+        }
+    }
+    times[n - 1].1 = t1;
+
+    // split the time between known times by how long each token takes to say
+    let lengths: Vec<f64> = tokens
+        .iter()
+        .map(|token| voice_length(&token.bytes))
+        .collect();
+    let mut p0 = 0;
+    while p0 < n {
+        let mut p1 = p0;
+        while p1 < n - 1 && times[p1].1 < 0 {
+            p1 += 1;
+        }
+        let total: f64 = lengths[p0..=p1].iter().sum();
+        if p1 > p0 && total > 0.0 {
+            let dt = (times[p1].1 - times[p0].0) as f64;
+            for j in p0 + 1..=p1 {
+                let time = times[j - 1].0 + (dt * lengths[j - 1] / total) as i64;
+                times[j - 1].1 = time;
+                times[j].0 = time;
+            }
+        }
+        p0 = p1 + 1;
+    }
+    for j in 1..n {
+        if times[j - 1].1 < 0 {
+            times[j - 1].1 = times[j].0;
+        }
+        if times[j - 1].1 > times[j].0 {
+            times[j].0 = times[j - 1].1;
+            times[j].1 = times[j].1.max(times[j].0);
+        }
+    }
+
+    // move the edges to where the signal gets loud or quiet
+    if energy.is_empty() {
+        return times;
+    }
+    let n_samples = energy.len();
+    let sample_rate = whisper_rs_sys::WHISPER_SAMPLE_RATE as i64;
+    let to_sample = |time: i64| (time * sample_rate / 100).clamp(0, n_samples as i64 - 1) as usize;
+    let to_time = |sample: usize| sample as i64 * 100 / sample_rate;
+    let half_window = sample_rate as usize / 8;
+    for j in 0..n {
+        let mut s0 = to_sample(times[j].0);
+        let s1 = to_sample(times[j].1).max(s0);
+        let (ss0, ss1) = (
+            s0.saturating_sub(half_window),
+            (s1 + half_window).min(n_samples),
+        );
+        let threshold = 0.5 * energy[ss0..ss1].iter().sum::<f32>() / (ss1 - ss0) as f32;
+
+        let mut k = s0;
+        if energy[k] > threshold && j > 0 {
+            while k > 0 && energy[k] > threshold {
+                k -= 1;
+            }
+            times[j].0 = to_time(k).max(times[j - 1].1);
+            if times[j].0 == to_time(k) {
+                s0 = k;
+            }
+        } else {
+            while energy[k] < threshold && k < s1 {
+                k += 1;
+            }
+            s0 = k;
+            times[j].0 = to_time(k);
+        }
+
+        let mut k = s1;
+        if energy[k] > threshold {
+            while k < n_samples - 1 && energy[k] > threshold {
+                k += 1;
+            }
+            times[j].1 = to_time(k);
+            if j + 1 < n {
+                times[j].1 = times[j].1.min(times[j + 1].0);
+            }
+        } else {
+            while energy[k] < threshold && k > s0 {
+                k -= 1;
+            }
+            times[j].1 = to_time(k);
+        }
+    }
+    times
+}
+
+/// How long `bytes` take to say, relative to a plain letter, as whisper.cpp estimates it.
+fn voice_length(bytes: &[u8]) -> f64 {
+    bytes
+        .iter()
+        .map(|&byte| match byte {
+            b' ' => 0.01,
+            b',' => 2.0,
+            b'.' | b'!' | b'?' | b'0'..=b'9' => 3.0,
+            _ => 1.0,
+        })
+        .sum()
+}
+
+/// The mean absolute amplitude around each sample.
+fn signal_energy(audio: &[f32]) -> Vec<f32> {
+    let mut sums = vec![0.0f64; audio.len() + 1];
+    for (i, sample) in audio.iter().enumerate() {
+        sums[i + 1] = sums[i] + sample.abs() as f64;
+    }
+    (0..audio.len())
+        .map(|i| {
+            let (start, end) = (
+                i.saturating_sub(ENERGY_HALF_WINDOW),
+                (i + ENERGY_HALF_WINDOW).min(audio.len()),
+            );
+            ((sums[end] - sums[start]) / (2 * ENERGY_HALF_WINDOW) as f64) as f32
+        })
+        .collect()
+}
+
+/// Split `items` into segments at timestamps and the segments into words.
+fn segments(items: &[Item], end: i64) -> Vec<AlignedSegment> {
+    let mut segments = Vec::new();
+    let mut start = None;
+    let mut text: Vec<&Item> = Vec::new();
+    for item in items {
+        match *item {
+            Item::Timestamp(time) => {
+                if !text.is_empty() {
+                    segments.push(segment(&text, start, time));
+                    text.clear();
+                }
+                start = Some(time);
+            }
+            Item::Text { .. } => text.push(item),
+        }
+    }
+    if !text.is_empty() {
+        segments.push(segment(&text, start, end));
+    }
+    segments
+}
+
+fn segment(items: &[&Item], start: Option<i64>, end: i64) -> AlignedSegment {
+    // a token starting with a space starts a new word
+    let mut words: Vec<(Vec<u8>, i64, i64, Vec<f32>)> = Vec::new();
+    for item in items {
+        if let Item::Text {
+            bytes,
+            start,
+            end,
+            probability,
+        } = item
+        {
+            match words.last_mut() {
+                Some(word) if !bytes.starts_with(b" ") => {
+                    word.0.extend_from_slice(bytes);
+                    word.2 = *end;
+                    word.3.push(*probability);
+                }
+                _ => words.push((bytes.clone(), *start, *end, vec![*probability])),
+            }
+        }
+    }
+
+    let start = start.unwrap_or(words[0].1).min(end);
+    // words stay inside the segment, in order and without overlapping
+    let mut previous = start;
+    let words: Vec<AlignedWord> = words
+        .iter()
+        .map(|(bytes, word_start, word_end, probabilities)| {
+            let word_start = (*word_start).clamp(previous, end);
+            previous = (*word_end).clamp(word_start, end);
+            AlignedWord {
+                text: String::from_utf8_lossy(bytes).trim().to_string(),
+                start: word_start,
+                end: previous,
+                probability: probabilities.iter().sum::<f32>() / probabilities.len() as f32,
+            }
+        })
+        .collect();
+    AlignedSegment {
+        text: words
+            .iter()
+            .map(|word| word.text.as_str())
+            .collect::<Vec<_>>()
+            .join(" "),
+        start,
+        end,
+        words,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn text(bytes: &str, start: i64, end: i64) -> Item {
+        Item::Text {
+            bytes: bytes.as_bytes().to_vec(),
+            start,
+            end,
+            probability: 0.5,
+        }
+    }
+
+    fn forced(bytes: &str, timestamp: i64, pt: f32) -> ForcedToken {
+        ForcedToken {
+            bytes: bytes.as_bytes().to_vec(),
+            probability: 0.5,
+            timestamp,
+            pt,
+            ptsum: pt,
+        }
+    }
+
+    #[test]
+    fn test_segments_split_at_timestamps() {
+        let items = [
+            Item::Timestamp(0),
+            text(" And", 10, 30),
+            text(" so", 40, 90),
+            Item::Timestamp(100),
+            Item::Timestamp(100),
+            text(" my", 120, 140),
+            text(" fell", 150, 170),
+            text("ow", 170, 230),
+            Item::Timestamp(250),
+        ];
+        let segments = segments(&items, 3000);
+        assert_eq!(segments.len(), 2);
+        assert_eq!(
+            (
+                segments[0].text.as_str(),
+                segments[0].start,
+                segments[0].end
+            ),
+            ("And so", 0, 100)
+        );
+        assert_eq!(
+            (
+                segments[1].text.as_str(),
+                segments[1].start,
+                segments[1].end
+            ),
+            ("my fellow", 100, 250)
+        );
+        let words: Vec<_> = segments[1]
+            .words
+            .iter()
+            .map(|word| (word.text.as_str(), word.start, word.end))
+            .collect();
+        assert_eq!(words, [("my", 120, 140), ("fellow", 150, 230)]);
+    }
+
+    #[test]
+    fn test_word_times_stay_inside_segment_and_increase() {
+        let items = [
+            Item::Timestamp(100),
+            text(" a", 50, 120),
+            text(" b", 300, 400),
+            text(" c", 200, 220),
+            Item::Timestamp(250),
+        ];
+        let words: Vec<_> = segments(&items, 3000)[0]
+            .words
+            .iter()
+            .map(|word| (word.start, word.end))
+            .collect();
+        assert_eq!(words, [(100, 120), (250, 250), (250, 250)]);
+    }
+
+    #[test]
+    fn test_untimestamped_text_runs_to_the_end() {
+        let items = [text(" hello", 30, 60), text(" world", 80, 120)];
+        let segments = segments(&items, 500);
+        assert_eq!(segments.len(), 1);
+        assert_eq!((segments[0].start, segments[0].end), (30, 500));
+        assert_eq!(segments[0].words[1].end, 120);
+    }
+
+    #[test]
+    fn test_token_times_split_by_voice_length() {
+        // nothing confident, so the segment is split by how long each token takes to say
+        let tokens = [
+            forced(" a", 0, 0.0),
+            forced(" abc", 0, 0.0),
+            forced(".", 0, 0.0),
+        ];
+        let tokens: Vec<_> = tokens.iter().collect();
+        let times = token_times(&tokens, 100, 172, &[]);
+        // 1.01, 3.01 and 3 out of 7.02
+        assert_eq!(times, [(100, 110), (110, 140), (140, 172)]);
+    }
+
+    #[test]
+    fn test_token_times_use_confident_timestamps() {
+        let tokens = [
+            forced(" a", 100, 0.9),
+            forced(" b", 150, 0.9),
+            // not confident enough
+            forced(" c", 160, 0.001),
+            forced(" d", 180, 0.9),
+            // goes back in time
+            forced(" e", 170, 0.9),
+        ];
+        let tokens: Vec<_> = tokens.iter().collect();
+        let times = token_times(&tokens, 100, 200, &[]);
+        assert_eq!(
+            times,
+            [(100, 150), (150, 165), (165, 180), (180, 190), (190, 200)]
+        );
+    }
+
+    #[test]
+    fn test_token_times_follow_the_energy() {
+        // silence, one second of noise from 0.5 s, silence
+        let audio: Vec<f32> = (0..32000)
+            .map(|i| match i {
+                8000..24000 if i % 2 == 0 => 0.5,
+                8000..24000 => -0.5,
+                _ => 0.0,
+            })
+            .collect();
+        let tokens = [forced(" word", 0, 0.0)];
+        let tokens: Vec<_> = tokens.iter().collect();
+        let times = token_times(&tokens, 30, 180, &signal_energy(&audio));
+        let (start, end) = times[0];
+        assert!((48..=52).contains(&start), "{start}");
+        assert!((148..=152).contains(&end), "{end}");
+    }
+
+    #[test]
+    fn test_time_tokens_between_timestamps() {
+        let steps = [
+            Step::Timestamp(0),
+            Step::Text(forced(" a", 0, 0.0)),
+            Step::Text(forced(" b", 0, 0.0)),
+            Step::Timestamp(100),
+            Step::Text(forced(" c", 0, 0.0)),
+        ];
+        let items = time_tokens(&steps, 300, &[]);
+        assert_eq!(
+            items,
+            [
+                Item::Timestamp(0),
+                text(" a", 0, 50),
+                text(" b", 50, 100),
+                Item::Timestamp(100),
+                text(" c", 100, 300),
+            ]
+        );
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "test-with-tiny-model")]
+mod test_with_tiny_model {
+    use super::*;
+    use crate::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+    const MODEL_PATH: &str = "./sys/whisper.cpp/models/ggml-tiny.en.bin";
+    const SAMPLE_PATH: &str = "./sys/whisper.cpp/samples/jfk.wav";
+
+    // These tests expect that the tiny.en model has been downloaded
+    // using the script `sys/whisper.cpp/models/download-ggml-model.sh tiny.en`
+
+    #[test]
+    fn test_align_keeps_text() {
+        let ctx = WhisperContext::new_with_params(MODEL_PATH, WhisperContextParameters::default())
+            .expect("Download the ggml-tiny.en model using 'sys/whisper.cpp/models/download-ggml-model.sh tiny.en'");
+        let samples: Vec<i16> = hound::WavReader::open(SAMPLE_PATH)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(Result::unwrap)
+            .collect();
+        let mut audio = vec![0.0f32; samples.len()];
+        crate::convert_integer_to_float_audio(&samples, &mut audio).unwrap();
+
+        // deliberately not what was said at the end
+        let text = "And so my fellow Americans, ask not what your country can do for you, ask what you can do for the planet.";
+        let mut state = ctx.create_state().unwrap();
+        let segments = state
+            .align(&audio, text, &DecoderParams::default())
+            .unwrap();
+        let words: Vec<_> = segments
+            .iter()
+            .flat_map(|segment| segment.words.iter())
+            .collect();
+        let aligned: Vec<_> = words.iter().map(|word| word.text.as_str()).collect();
+        assert_eq!(aligned.join(" "), text);
+        for pair in words.windows(2) {
+            assert!(pair[0].start <= pair[1].start);
+        }
+        assert!(words.last().unwrap().end <= 1100);
+
+        // the words that were said where they were said, as full times them
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_token_timestamps(true);
+        state.full(params, &audio).unwrap();
+        let transcribed = state.words().unwrap();
+        let mut compared = 0;
+        for (aligned, transcribed) in words.iter().zip(&transcribed) {
+            if aligned.text != transcribed.text {
+                break;
+            }
+            assert!((aligned.start - transcribed.start).abs() <= 50);
+            assert!((aligned.end - transcribed.end).abs() <= 50);
+            compared += 1;
+        }
+        assert!(compared >= 10);
+    }
+}
@@ -0,0 +1,275 @@
+use crate::{Decoder, DecoderParams, Sampler, WhisperError, WhisperState, WhisperTokenId};
+
+/// Length in mel frames (10 ms each) of the audio window the encoder sees.
+const WINDOW_FRAMES: i64 = 3000;
+
+/// A segment of [`WhisperState::full_bilingual`]: what was said and its English translation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BilingualSegment {
+    /// Start time in centiseconds (10s of milliseconds).
+    pub start: i64,
+    /// End time in centiseconds (10s of milliseconds).
+    pub end: i64,
+    /// The transcript in the spoken language.
+    pub text: String,
+    /// The English translation of `text`.
+    pub translation: String,
+}
+
+/// Text between two timestamps, in centiseconds from the start of the window.
+#[derive(Debug, Clone, PartialEq)]
+struct TimedTokens {
+    start: i64,
+    end: i64,
+    tokens: Vec<WhisperTokenId>,
+}
+
+impl WhisperState {
+    /// Transcribe `audio` and translate it to English, running the encoder only once per window.
+    ///
+    /// Calling [`WhisperState::full`] once to transcribe and once to translate encodes all audio twice,
+    /// and the encoder is most of the work. This computes the spectrogram once and encodes each
+    /// 30 second window once, then decodes it twice with [`Decoder`], with the transcribe and
+    /// the translate task token. The transcript decides the segments and where the next window starts,
+    /// and each translated segment is added to the transcript segment it overlaps most in time.
+    /// Each task is prompted with its own text of the previous windows.
+    ///
+    /// `params` chooses the language and threads. The task is set for each decoder
+    /// and timestamps are always turned on.
+    ///
+    /// # Arguments
+    /// * audio: raw PCM audio data, 32 bit floating point at a sample rate of 16 kHz, 1 channel.
+    /// * sampler: picks the tokens of both decoders, [`crate::Greedy`] does what whisper.cpp does at temperature 0.
+    ///
+    /// # Errors
+    /// * [`WhisperError::NoSamples`] if `audio` is empty.
+    /// * Whatever [`WhisperState::pcm_to_mel`], [`WhisperState::encode`] or [`Decoder`] return.
+    pub fn full_bilingual(
+        &mut self,
+        audio: &[f32],
+        params: &DecoderParams,
+        sampler: &mut impl Sampler,
+    ) -> Result<Vec<BilingualSegment>, WhisperError> {
+        if audio.is_empty() {
+            return Err(WhisperError::NoSamples);
+        }
+        let beg = self.ctx.token_beg();
+        let eot = self.ctx.token_eot();
+        let n_prompt = self.ctx.n_text_ctx() as usize / 2;
+
+        self.pcm_to_mel(audio, params.threads)?;
+        let n_len = self.n_len() as i64;
+        let mut transcribe = params.clone();
+        transcribe.translate(false).timestamps(true);
+        let mut translate = params.clone();
+        translate.translate(true).timestamps(true);
+
+        let mut segments = Vec::new();
+        let mut seek = 0;
+        while seek < n_len {
+            self.encode(seek as usize, params.threads)?;
+            let last_window = seek + WINDOW_FRAMES >= n_len;
+            let window_end = WINDOW_FRAMES.min(n_len - seek);
+
+            let mut decoder = Decoder::new(self, &transcribe)?;
+            let text = decoder.run(sampler)?.to_vec();
+            let seek_delta = decoder.seek_delta().map(i64::from);
+            let translation = Decoder::new(self, &translate)?.run(sampler)?.to_vec();
+
+            // like whisper.cpp, continue from the last timestamp unless the model never timestamped anything
+            let consumed = match seek_delta {
+                Some(delta) if delta > 0 && !last_window => delta,
+                _ => window_end,
+            };
+            // what comes after is decoded again in the next window
+            let mut text = timed_tokens(&text, beg, eot, window_end);
+            text.retain(|segment| segment.start < consumed);
+            let mut translation = timed_tokens(&translation, beg, eot, window_end);
+            translation.retain(|segment| segment.start < consumed);
+
+            let mut window: Vec<BilingualSegment> = text
+                .iter()
+                .map(|segment| {
+                    Ok(BilingualSegment {
+                        start: seek + segment.start,
+                        end: seek + segment.end.min(consumed),
+                        text: self.detokenize(&segment.tokens)?,
+                        translation: String::new(),
+                    })
+                })
+                .collect::<Result<_, WhisperError>>()?;
+            let spans: Vec<(i64, i64)> = text.iter().map(|s| (s.start, s.end)).collect();
+            for segment in &translation {
+                let translated = self.detokenize(&segment.tokens)?;
+                match closest_span(&spans, (segment.start, segment.end)) {
+                    Some(i) => window[i].translation.push_str(&translated),
+                    None => window.push(BilingualSegment {
+                        start: seek + segment.start,
+                        end: seek + segment.end.min(consumed),
+                        text: String::new(),
+                        translation: translated,
+                    }),
+                }
+            }
+            segments.append(&mut window);
+
+            for (params, output) in [(&mut transcribe, &text), (&mut translate, &translation)] {
+                let mut context = params.prompt_tokens.clone();
+                context.extend(output.iter().flat_map(|segment| &segment.tokens));
+                params.prompt_tokens(&context[context.len().saturating_sub(n_prompt)..]);
+            }
+            seek += consumed;
+        }
+        Ok(segments)
+    }
+
+    /// The text of `tokens`, with invalid UTF-8 replaced.
+    fn detokenize(&self, tokens: &[WhisperTokenId]) -> Result<String, WhisperError> {
+        let mut bytes = Vec::new();
+        for &token in tokens {
+            bytes.extend_from_slice(self.ctx.token_to_bytes(token)?);
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+/// Split decoder output into the text between timestamps.
+/// Text the model did not close with a timestamp runs to `window_end`.
+fn timed_tokens(
+    tokens: &[WhisperTokenId],
+    beg: WhisperTokenId,
+    eot: WhisperTokenId,
+    window_end: i64,
+) -> Vec<TimedTokens> {
+    let mut segments = Vec::new();
+    let mut start = None;
+    let mut text = Vec::new();
+    for &token in tokens {
+        if token >= beg {
+            let time = 2 * (token - beg) as i64;
+            match start {
+                Some(start) if !text.is_empty() => segments.push(TimedTokens {
+                    start,
+                    end: time,
+                    tokens: std::mem::take(&mut text),
+                }),
+                _ => {
+                    start = Some(time);
+                    continue;
+                }
+            }
+            start = None;
+        } else if token < eot {
+            text.push(token);
+        }
+    }
+    if !text.is_empty() {
+        segments.push(TimedTokens {
+            start: start.unwrap_or(0),
+            end: window_end,
+            tokens: text,
+        });
+    }
+    segments
+}
+
+/// The span `span` overlaps most, or the nearest one if it overlaps none.
+fn closest_span(spans: &[(i64, i64)], span: (i64, i64)) -> Option<usize> {
+    // overlap is positive, a gap negative
+    let score = |&(start, end): &(i64, i64)| end.min(span.1) - start.max(span.0);
+    (0..spans.len()).max_by_key(|&i| (score(&spans[i]), std::cmp::Reverse(i)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 0..5 text, 5 is the end of text and timestamps start at 10
+    const EOT: WhisperTokenId = 5;
+    const BEG: WhisperTokenId = 10;
+
+    fn timed(start: i64, end: i64, tokens: &[WhisperTokenId]) -> TimedTokens {
+        TimedTokens {
+            start,
+            end,
+            tokens: tokens.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_timed_tokens() {
+        let tokens = [10, 0, 1, 14, 14, 2, 20, 5];
+        assert_eq!(
+            timed_tokens(&tokens, BEG, EOT, 3000),
+            [timed(0, 8, &[0, 1]), timed(8, 20, &[2])]
+        );
+        // a timestamp right after another one moves the start, unclosed text runs to the end
+        let tokens = [10, 12, 3, 16, 18, 4, 5];
+        assert_eq!(
+            timed_tokens(&tokens, BEG, EOT, 3000),
+            [timed(4, 12, &[3]), timed(16, 3000, &[4])]
+        );
+        assert!(timed_tokens(&[10, 5], BEG, EOT, 3000).is_empty());
+    }
+
+    #[test]
+    fn test_closest_span() {
+        let spans = [(0, 100), (100, 300), (400, 500)];
+        assert_eq!(closest_span(&spans, (50, 250)), Some(1));
+        assert_eq!(closest_span(&spans, (0, 120)), Some(0));
+        assert_eq!(closest_span(&spans, (320, 360)), Some(1));
+        assert_eq!(closest_span(&spans, (600, 700)), Some(2));
+        assert_eq!(closest_span(&[], (0, 100)), None);
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "test-with-tiny-model")]
+mod test_with_tiny_model {
+    use crate::{
+        DecoderParams, FullParams, Greedy, SamplingStrategy, WhisperContext,
+        WhisperContextParameters,
+    };
+    const MODEL_PATH: &str = "./sys/whisper.cpp/models/ggml-tiny.en.bin";
+    const SAMPLE_PATH: &str = "./sys/whisper.cpp/samples/jfk.wav";
+
+    // These tests expect that the tiny.en model has been downloaded
+    // using the script `sys/whisper.cpp/models/download-ggml-model.sh tiny.en`
+
+    #[test]
+    fn test_transcript_matches_full() {
+        let ctx = WhisperContext::new_with_params(MODEL_PATH, WhisperContextParameters::default())
+            .expect("Download the ggml-tiny.en model using 'sys/whisper.cpp/models/download-ggml-model.sh tiny.en'");
+        let samples: Vec<i16> = hound::WavReader::open(SAMPLE_PATH)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(Result::unwrap)
+            .collect();
+        let mut audio = vec![0.0f32; samples.len()];
+        crate::convert_integer_to_float_audio(&samples, &mut audio).unwrap();
+
+        let mut state = ctx.create_state().unwrap();
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_temperature_inc(0.0);
+        state.full(params, &audio).unwrap();
+        let expected: Vec<(String, i64, i64)> = state
+            .as_iter()
+            .map(|segment| {
+                (
+                    segment.to_str().unwrap().trim().to_string(),
+                    segment.start_timestamp(),
+                    segment.end_timestamp(),
+                )
+            })
+            .collect();
+
+        let segments = state
+            .full_bilingual(&audio, &DecoderParams::default(), &mut Greedy)
+            .unwrap();
+        let transcript: Vec<(String, i64, i64)> = segments
+            .iter()
+            .map(|segment| (segment.text.trim().to_string(), segment.start, segment.end))
+            .collect();
+        assert_eq!(transcript, expected);
+    }
+}
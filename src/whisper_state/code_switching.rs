@@ -0,0 +1,213 @@
+use super::language::rank_languages;
+use crate::{
+    FullParams, Language, LanguageDetectionParams, WhisperError, WhisperState, WhisperVadContext,
+    WhisperVadSegment,
+};
+
+/// Longest part of a region in centiseconds whose language is detected on its own.
+const MAX_REGION_CS: f32 = 3000.0;
+
+/// A segment of [`WhisperState::full_code_switched`], with the language it was transcribed in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanguageSegment {
+    pub text: String,
+    /// Start time in centiseconds (10s of milliseconds).
+    pub start: i64,
+    /// End time in centiseconds (10s of milliseconds).
+    pub end: i64,
+    pub language: Language,
+    /// Probability of `language` in the speech region the segment starts in.
+    pub language_probability: f32,
+}
+
+/// A part of the speech and the language detected in it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct DetectedPart {
+    region: WhisperVadSegment,
+    language: Language,
+    probability: f32,
+}
+
+impl WhisperState {
+    /// Transcribe audio that switches between languages.
+    ///
+    /// [`WhisperState::full`] settles on one language for the whole call. This finds speech with `vad`
+    /// instead and detects the language of every speech region on its own, splitting regions longer
+    /// than 30 seconds for that. Regions shorter than `detection.min_region_ms` are too short
+    /// to tell the language reliably and keep the language of the region before them.
+    /// Consecutive regions of the same language are then transcribed together in one
+    /// [`WhisperState::full`] call in that language, so long regions are not cut mid-sentence
+    /// and context carries over between them. Context is not carried over when the language changes.
+    ///
+    /// The segments in the state only hold the last language, use the returned ones instead.
+    ///
+    /// # Arguments
+    /// * params: [crate::FullParams] struct. The offset and duration are honored, the language is replaced.
+    /// * data: raw PCM audio data, 32 bit floating point at a sample rate of 16 kHz, 1 channel.
+    /// * vad: finds the speech.
+    /// * detection: how to find speech and which languages to choose from. `max_regions` is not used.
+    ///
+    /// # Errors
+    /// * [`WhisperError::NoSamples`] if `data` is empty.
+    /// * Whatever [`WhisperVadContext::segments_from_samples`], [`WhisperState::lang_detect`]
+    ///   or [`WhisperState::full`] return.
+    /// * [`WhisperError::InvalidUtf8`] if a segment is not valid UTF-8.
+    pub fn full_code_switched(
+        &mut self,
+        params: FullParams,
+        data: &[f32],
+        vad: &mut WhisperVadContext,
+        detection: &LanguageDetectionParams,
+    ) -> Result<Vec<LanguageSegment>, WhisperError> {
+        let sample_rate = whisper_rs_sys::WHISPER_SAMPLE_RATE as usize;
+        let offset = (params.fp.offset_ms.max(0) as usize * sample_rate / 1000).min(data.len());
+        let mut end = data.len();
+        if params.fp.duration_ms > 0 {
+            end = end.min(offset + params.fp.duration_ms as usize * sample_rate / 1000);
+        }
+        let data = &data[offset..end];
+        if data.is_empty() {
+            return Err(WhisperError::NoSamples);
+        }
+        let threads = params.fp.n_threads.max(1) as usize;
+        let samples_per_cs = sample_rate as f32 / 100.0;
+        let samples = |region: &WhisperVadSegment| {
+            let start = ((region.start * samples_per_cs) as usize).min(data.len() - 1);
+            let end = ((region.end * samples_per_cs) as usize).clamp(start + 1, data.len());
+            start..end
+        };
+        let speech: Vec<WhisperVadSegment> =
+            vad.segments_from_samples(detection.vad, data)?.collect();
+
+        let mut parts: Vec<DetectedPart> = Vec::new();
+        for region in split_regions(&speech, MAX_REGION_CS) {
+            let short = region.end - region.start < detection.min_region_ms as f32 / 10.0;
+            let (language, probability) = match parts.last() {
+                Some(previous) if short => (previous.language, previous.probability),
+                _ => {
+                    self.pcm_to_mel(&data[samples(&region)], threads)?;
+                    let (_, probs) = self.lang_detect(0, threads)?;
+                    rank_languages(&probs, &detection.allowed, 1)[0]
+                }
+            };
+            parts.push(DetectedPart {
+                region,
+                language,
+                probability,
+            });
+        }
+
+        let mut segments = Vec::new();
+        let mut previous: Option<Language> = None;
+        for run in parts.chunk_by(|a, b| a.language == b.language) {
+            let (first, last) = (run[0], run[run.len() - 1]);
+            let range = samples(&WhisperVadSegment {
+                start: first.region.start,
+                end: last.region.end,
+            });
+            let language = first.language;
+
+            let mut full = params.clone();
+            full.set_offset_ms(0);
+            full.set_duration_ms(0);
+            full.set_detect_language(false);
+            full.set_allowed_languages(None);
+            full.set_language(Some(language.code()));
+            if previous.is_some_and(|previous| previous != language) {
+                full.set_no_context(true);
+            }
+            // from the run to `data`, and from `data` to the audio passed in
+            let run_start = (range.start * 100 / sample_rate) as i64;
+            let shift = ((offset + range.start) * 100 / sample_rate) as i64;
+            self.full(full, &data[range])?;
+            previous = Some(language);
+
+            for segment in self.as_iter() {
+                let part = part_at(run, segment.start_timestamp() + run_start);
+                segments.push(LanguageSegment {
+                    text: segment.to_str()?.to_string(),
+                    start: segment.start_timestamp() + shift,
+                    end: segment.end_timestamp() + shift,
+                    language,
+                    language_probability: part.probability,
+                });
+            }
+        }
+        Ok(segments)
+    }
+}
+
+/// The part of `run` that `time` (in centiseconds) falls in, or the one before it if it falls between two.
+fn part_at(run: &[DetectedPart], time: i64) -> &DetectedPart {
+    let i = run.partition_point(|part| part.region.start <= time as f32);
+    &run[i.saturating_sub(1)]
+}
+
+/// `speech` with every region longer than `max_len` centiseconds split into equal parts no longer than it.
+fn split_regions(speech: &[WhisperVadSegment], max_len: f32) -> Vec<WhisperVadSegment> {
+    let mut regions = Vec::with_capacity(speech.len());
+    for region in speech.iter().filter(|region| region.end > region.start) {
+        let parts = ((region.end - region.start) / max_len).ceil() as usize;
+        let len = (region.end - region.start) / parts as f32;
+        regions.extend((0..parts).map(|i| WhisperVadSegment {
+            start: region.start + i as f32 * len,
+            end: if i + 1 == parts {
+                region.end
+            } else {
+                region.start + (i + 1) as f32 * len
+            },
+        }));
+    }
+    regions
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn region(start: f32, end: f32) -> WhisperVadSegment {
+        WhisperVadSegment { start, end }
+    }
+
+    #[test]
+    fn test_split_regions() {
+        let speech = [
+            region(0.0, 500.0),
+            region(600.0, 600.0),
+            region(700.0, 7700.0),
+        ];
+        let regions = split_regions(&speech, 3000.0);
+        assert_eq!(regions.len(), 4);
+        assert_eq!(regions[0], region(0.0, 500.0));
+        assert_eq!(regions[1].start, 700.0);
+        assert_eq!(regions[3].end, 7700.0);
+        for pair in regions[1..].windows(2) {
+            assert_eq!(pair[0].end, pair[1].start);
+        }
+        for part in &regions[1..] {
+            assert!((part.end - part.start - 7000.0 / 3.0).abs() < 0.01);
+        }
+        assert!(split_regions(&[], 3000.0).is_empty());
+    }
+
+    #[test]
+    fn test_part_at() {
+        let part = |start: f32, end: f32, probability: f32| DetectedPart {
+            region: region(start, end),
+            language: Language::English,
+            probability,
+        };
+        let run = [
+            part(100.0, 500.0, 0.9),
+            part(800.0, 3800.0, 0.7),
+            part(3800.0, 4000.0, 0.5),
+        ];
+        assert_eq!(part_at(&run, 0).probability, 0.9);
+        assert_eq!(part_at(&run, 300).probability, 0.9);
+        // between two regions counts as the one before
+        assert_eq!(part_at(&run, 600).probability, 0.9);
+        assert_eq!(part_at(&run, 800).probability, 0.7);
+        assert_eq!(part_at(&run, 3900).probability, 0.5);
+        assert_eq!(part_at(&run, 9000).probability, 0.5);
+    }
+}
@@ -0,0 +1,1005 @@
+use std::ffi::c_int;
+use std::ops::RangeInclusive;
+
+use crate::{WhisperError, WhisperInnerContext, WhisperState, WhisperTokenId};
+
+/// Length in seconds of the audio window the encoder sees.
+const CHUNK_SECONDS: f32 = 30.0;
+
+/// Parameters for a [`Decoder`].
+///
+/// The defaults match what [`WhisperState::full`] uses: English, transcription, timestamps on,
+/// an initial timestamp of at most one second and blank suppression.
+#[derive(Debug, Clone)]
+pub struct DecoderParams {
+    /// Language id of the audio, see [`crate::get_lang_id`]. Ignored for English-only models.
+    pub language: c_int,
+    /// Translate to English instead of transcribing.
+    pub translate: bool,
+    /// Let the model predict timestamp tokens.
+    pub timestamps: bool,
+    /// The first timestamp can be at most this many seconds. 0 disables the limit.
+    pub max_initial_ts: f32,
+    /// Do not let the first token be a blank or the end of text.
+    pub suppress_blank: bool,
+    /// Tokens of previous text to condition on. Only the most recent `n_text_ctx / 2` are used.
+    pub prompt_tokens: Vec<WhisperTokenId>,
+    /// How many threads to decode with. Must be at least 1.
+    pub threads: usize,
+}
+
+impl Default for DecoderParams {
+    fn default() -> Self {
+        Self {
+            language: 0,
+            translate: false,
+            timestamps: true,
+            max_initial_ts: 1.0,
+            suppress_blank: true,
+            prompt_tokens: Vec::new(),
+            threads: 1,
+        }
+    }
+}
+
+impl DecoderParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn language(&mut self, language: c_int) -> &mut Self {
+        self.language = language;
+        self
+    }
+    pub fn translate(&mut self, translate: bool) -> &mut Self {
+        self.translate = translate;
+        self
+    }
+    pub fn timestamps(&mut self, timestamps: bool) -> &mut Self {
+        self.timestamps = timestamps;
+        self
+    }
+    pub fn max_initial_ts(&mut self, max_initial_ts: f32) -> &mut Self {
+        self.max_initial_ts = max_initial_ts;
+        self
+    }
+    pub fn suppress_blank(&mut self, suppress_blank: bool) -> &mut Self {
+        self.suppress_blank = suppress_blank;
+        self
+    }
+    pub fn prompt_tokens(&mut self, prompt_tokens: &[WhisperTokenId]) -> &mut Self {
+        self.prompt_tokens = prompt_tokens.to_vec();
+        self
+    }
+    pub fn threads(&mut self, threads: usize) -> &mut Self {
+        self.threads = threads;
+        self
+    }
+}
+
+/// Picks the next token from the logits of a decoding step.
+///
+/// Suppressed tokens have a logit of negative infinity and must never be returned.
+/// Any `FnMut(&[f32]) -> WhisperTokenId` closure is a sampler.
+pub trait Sampler {
+    fn sample(&mut self, logits: &[f32]) -> WhisperTokenId;
+}
+
+impl<F: FnMut(&[f32]) -> WhisperTokenId> Sampler for F {
+    fn sample(&mut self, logits: &[f32]) -> WhisperTokenId {
+        self(logits)
+    }
+}
+
+/// Always picks the most likely token.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Greedy;
+
+impl Sampler for Greedy {
+    fn sample(&mut self, logits: &[f32]) -> WhisperTokenId {
+        let mut best = 0;
+        for (i, &logit) in logits.iter().enumerate() {
+            if logit > logits[best] {
+                best = i;
+            }
+        }
+        best as WhisperTokenId
+    }
+}
+
+/// Samples among the `k` most likely tokens, after dividing the logits by `temperature`.
+#[derive(Debug, Clone)]
+pub struct TopK {
+    k: usize,
+    temperature: f32,
+    rng: SplitMix64,
+}
+
+impl TopK {
+    /// `seed` makes the sampled sequence reproducible.
+    pub fn new(k: usize, temperature: f32, seed: u64) -> Self {
+        Self {
+            k: k.max(1),
+            temperature,
+            rng: SplitMix64(seed),
+        }
+    }
+}
+
+impl Sampler for TopK {
+    fn sample(&mut self, logits: &[f32]) -> WhisperTokenId {
+        let mut candidates = sorted_candidates(logits, self.temperature);
+        candidates.truncate(self.k);
+        draw(&candidates, &mut self.rng)
+    }
+}
+
+/// Samples among the smallest set of most likely tokens whose probabilities add up to at least `p`,
+/// after dividing the logits by `temperature`.
+#[derive(Debug, Clone)]
+pub struct Nucleus {
+    p: f32,
+    temperature: f32,
+    rng: SplitMix64,
+}
+
+impl Nucleus {
+    /// `seed` makes the sampled sequence reproducible.
+    pub fn new(p: f32, temperature: f32, seed: u64) -> Self {
+        Self {
+            p,
+            temperature,
+            rng: SplitMix64(seed),
+        }
+    }
+}
+
+impl Sampler for Nucleus {
+    fn sample(&mut self, logits: &[f32]) -> WhisperTokenId {
+        let mut candidates = sorted_candidates(logits, self.temperature);
+        let mut mass = 0.0;
+        let mut keep = candidates.len();
+        for (i, &(_, p)) in candidates.iter().enumerate() {
+            mass += p;
+            if mass >= self.p {
+                keep = i + 1;
+                break;
+            }
+        }
+        candidates.truncate(keep.max(1));
+        draw(&candidates, &mut self.rng)
+    }
+}
+
+/// Tokens that can still be sampled with their probabilities at `temperature`, most likely first.
+fn sorted_candidates(logits: &[f32], temperature: f32) -> Vec<(WhisperTokenId, f32)> {
+    let temperature = if temperature > 0.0 { temperature } else { 1.0 };
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let mut candidates: Vec<_> = logits
+        .iter()
+        .enumerate()
+        .filter(|(_, &logit)| logit > f32::NEG_INFINITY)
+        .map(|(i, &logit)| (i as WhisperTokenId, ((logit - max) / temperature).exp()))
+        .collect();
+    let sum: f32 = candidates.iter().map(|&(_, p)| p).sum();
+    for (_, p) in candidates.iter_mut() {
+        *p /= sum;
+    }
+    candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+    candidates
+}
+
+/// Draw one token from `candidates` in proportion to their (not necessarily normalized) weights.
+fn draw(candidates: &[(WhisperTokenId, f32)], rng: &mut SplitMix64) -> WhisperTokenId {
+    let total: f32 = candidates.iter().map(|&(_, p)| p).sum();
+    let mut target = rng.next_f32() * total;
+    for &(token, p) in candidates {
+        if target < p {
+            return token;
+        }
+        target -= p;
+    }
+    candidates.last().map_or(0, |&(token, _)| token)
+}
+
+/// Small, fast and seedable, which is all sampling needs.
+#[derive(Debug, Clone)]
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next_f32(&mut self) -> f32 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^= z >> 31;
+        (z >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+/// The logit filters whisper.cpp applies at every decoding step.
+#[derive(Debug, Clone)]
+struct LogitRules {
+    eot: WhisperTokenId,
+    sot: WhisperTokenId,
+    solm: WhisperTokenId,
+    prev: WhisperTokenId,
+    nosp: WhisperTokenId,
+    not: WhisperTokenId,
+    beg: WhisperTokenId,
+    translate: WhisperTokenId,
+    transcribe: WhisperTokenId,
+    languages: RangeInclusive<WhisperTokenId>,
+    blank: Option<WhisperTokenId>,
+    timestamps: bool,
+    max_initial_tid: Option<WhisperTokenId>,
+}
+
+impl LogitRules {
+    fn new(ctx: &WhisperInnerContext, params: &DecoderParams) -> Self {
+        let blank = match ctx.tokenize(" ", 2) {
+            Ok(tokens) if tokens.len() == 1 => Some(tokens[0]),
+            _ => None,
+        };
+        let precision = CHUNK_SECONDS / ctx.model_n_audio_ctx() as f32;
+        let max_initial_tid = (params.max_initial_ts > 0.0)
+            .then(|| (params.max_initial_ts / precision).round() as WhisperTokenId);
+        Self {
+            eot: ctx.token_eot(),
+            sot: ctx.token_sot(),
+            solm: ctx.token_solm(),
+            prev: ctx.token_prev(),
+            nosp: ctx.token_nosp(),
+            not: ctx.token_not(),
+            beg: ctx.token_beg(),
+            translate: ctx.token_translate(),
+            transcribe: ctx.token_transcribe(),
+            languages: ctx.token_lang(0)..=ctx.token_lang(crate::get_lang_max_id()),
+            blank: blank.filter(|_| params.suppress_blank),
+            timestamps: params.timestamps,
+            max_initial_tid,
+        }
+    }
+
+    /// The tokens `whisper.cpp` starts decoding with: previous text, then the task.
+    fn prompt(
+        &self,
+        params: &DecoderParams,
+        language: Option<WhisperTokenId>,
+        n_text_ctx: usize,
+    ) -> Vec<WhisperTokenId> {
+        let mut prompt = Vec::new();
+        if !params.prompt_tokens.is_empty() {
+            let n_take = params.prompt_tokens.len().min(n_text_ctx / 2);
+            prompt.push(self.prev);
+            prompt.extend_from_slice(&params.prompt_tokens[params.prompt_tokens.len() - n_take..]);
+        }
+        prompt.push(self.sot);
+        if let Some(language) = language {
+            prompt.push(language);
+            prompt.push(if params.translate {
+                self.translate
+            } else {
+                self.transcribe
+            });
+        }
+        if !self.timestamps {
+            prompt.push(self.not);
+        }
+        prompt
+    }
+
+    /// Mask the raw `logits` of the step after `tokens` and fill `logprobs` with their log-softmax.
+    fn apply(&self, tokens: &[WhisperTokenId], logits: &mut [f32], logprobs: &mut Vec<f32>) {
+        let n_vocab = logits.len();
+        let beg = self.beg as usize;
+        let mut suppress = |token: WhisperTokenId| {
+            if let Some(logit) = logits.get_mut(token as usize) {
+                *logit = f32::NEG_INFINITY;
+            }
+        };
+
+        if tokens.is_empty() {
+            if let Some(blank) = self.blank {
+                suppress(blank);
+                suppress(self.eot);
+            }
+        }
+        for token in [
+            self.not,
+            self.sot,
+            self.nosp,
+            self.solm,
+            self.translate,
+            self.transcribe,
+            self.prev,
+        ] {
+            suppress(token);
+        }
+        for token in self.languages.clone() {
+            suppress(token);
+        }
+
+        let timestamps = beg.min(n_vocab)..n_vocab;
+        if !self.timestamps {
+            logits[timestamps].fill(f32::NEG_INFINITY);
+            log_softmax(logits, logprobs);
+            return;
+        }
+
+        // timestamps have to appear in pairs, except directly before the end of text
+        let is_timestamp = |token: &WhisperTokenId| *token >= self.beg;
+        if tokens.last().is_some_and(is_timestamp) {
+            if tokens.len() < 2 || is_timestamp(&tokens[tokens.len() - 2]) {
+                logits[timestamps.clone()].fill(f32::NEG_INFINITY);
+            } else {
+                logits[..(self.eot as usize).min(n_vocab)].fill(f32::NEG_INFINITY);
+            }
+        }
+
+        if tokens.is_empty() {
+            if let Some(tid0) = self.max_initial_tid {
+                let first = (beg + tid0 as usize + 1).min(n_vocab);
+                logits[first..].fill(f32::NEG_INFINITY);
+            }
+        }
+
+        // timestamps only ever increase
+        if let Some(seek_delta) = seek_delta(tokens, self.beg) {
+            let end = (beg + seek_delta as usize / 2).min(n_vocab);
+            logits[beg.min(end)..end].fill(f32::NEG_INFINITY);
+        }
+
+        log_softmax(logits, logprobs);
+
+        // if a timestamp is more likely than any single text token, sample a timestamp
+        let timestamp_max = logprobs[timestamps.clone()]
+            .iter()
+            .copied()
+            .fold(f32::NEG_INFINITY, f32::max);
+        let timestamp_logprob = if timestamp_max > f32::NEG_INFINITY {
+            let sum: f32 = logprobs[timestamps]
+                .iter()
+                .map(|&logprob| (logprob - timestamp_max).exp())
+                .sum();
+            sum.ln() + timestamp_max
+        } else {
+            f32::NEG_INFINITY
+        };
+        let text_max = logprobs[..beg.min(n_vocab)]
+            .iter()
+            .copied()
+            .fold(f32::NEG_INFINITY, f32::max);
+        if timestamp_logprob > text_max {
+            logits[..beg.min(n_vocab)].fill(f32::NEG_INFINITY);
+            logprobs[..beg.min(n_vocab)].fill(f32::NEG_INFINITY);
+        }
+    }
+}
+
+/// How many mel frames the last timestamp token in `tokens` is into the window,
+/// or `None` if none has been decoded yet (`<|0.00|>` does not count, as in whisper.cpp).
+fn seek_delta(tokens: &[WhisperTokenId], beg: WhisperTokenId) -> Option<c_int> {
+    tokens
+        .iter()
+        .rev()
+        .find(|&&token| token > beg)
+        .map(|&token| 2 * (token - beg))
+}
+
+fn log_softmax(logits: &[f32], logprobs: &mut Vec<f32>) {
+    let max = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+    let sum: f32 = logits.iter().map(|&logit| (logit - max).exp()).sum();
+    let log_sum = sum.ln() + max;
+    logprobs.clear();
+    logprobs.extend(logits.iter().map(|&logit| logit - log_sum));
+}
+
+/// A token-by-token decoder over [`WhisperState::decode`] for writing your own decoding loop.
+///
+/// It builds the same prompt as [`WhisperState::full`] (previous text, start of transcript,
+/// language and task), keeps track of what is already in the KV cache,
+/// and masks the logits of each step with whisper.cpp's rules: special tokens are never sampled,
+/// timestamps come in pairs and only ever increase, and so on.
+/// Which token to pick from the masked logits is up to you, see [`Sampler`].
+///
+/// The audio has to be encoded with [`WhisperState::encode`] first.
+///
+/// # Examples
+/// ```no_run
+/// # use whisper_rs::{Decoder, DecoderParams, TopK, WhisperContext, WhisperContextParameters};
+/// # let ctx = WhisperContext::new_with_params("model.bin", WhisperContextParameters::default()).unwrap();
+/// # let audio = vec![0.0f32; 16000];
+/// let mut state = ctx.create_state().unwrap();
+/// state.pcm_to_mel(&audio, 1).unwrap();
+/// state.encode(0, 1).unwrap();
+///
+/// let mut decoder = Decoder::new(&mut state, &DecoderParams::default()).unwrap();
+/// let tokens = decoder.run(&mut TopK::new(5, 0.7, 42)).unwrap().to_vec();
+/// for token in tokens {
+///     print!("{}", ctx.token_to_str_lossy(token).unwrap());
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Decoder<'a> {
+    state: &'a mut WhisperState,
+    rules: LogitRules,
+    threads: usize,
+    max_tokens: usize,
+    sequence: Vec<WhisperTokenId>,
+    n_prompt: usize,
+    n_past: usize,
+    logits: Vec<f32>,
+    logprobs: Vec<f32>,
+    ready: bool,
+    token_logprobs: Vec<f32>,
+}
+
+impl<'a> Decoder<'a> {
+    /// Start decoding the audio last encoded into `state`.
+    ///
+    /// # Errors
+    /// * [`WhisperError::InvalidThreadCount`] if `params.threads` is 0.
+    /// * [`WhisperError::InvalidLanguageId`] if the model is multilingual and `params.language` is out of range.
+    pub fn new(state: &'a mut WhisperState, params: &DecoderParams) -> Result<Self, WhisperError> {
+        if params.threads < 1 {
+            return Err(WhisperError::InvalidThreadCount);
+        }
+        let ctx = &state.ctx;
+        let language = if ctx.is_multilingual() {
+            if !(0..=crate::get_lang_max_id()).contains(&params.language) {
+                return Err(WhisperError::InvalidLanguageId(params.language));
+            }
+            Some(ctx.token_lang(params.language))
+        } else {
+            None
+        };
+        let n_text_ctx = ctx.n_text_ctx() as usize;
+        let rules = LogitRules::new(ctx, params);
+        let sequence = rules.prompt(params, language, n_text_ctx);
+        Ok(Self {
+            rules,
+            threads: params.threads,
+            // the same limit whisper.cpp puts on a window
+            max_tokens: (n_text_ctx / 2).saturating_sub(4),
+            n_prompt: sequence.len(),
+            sequence,
+            n_past: 0,
+            logits: Vec::new(),
+            logprobs: Vec::new(),
+            ready: false,
+            token_logprobs: Vec::new(),
+            state,
+        })
+    }
+
+    /// The tokens decoding was started with.
+    pub fn prompt(&self) -> &[WhisperTokenId] {
+        &self.sequence[..self.n_prompt]
+    }
+
+    /// The tokens pushed so far.
+    pub fn tokens(&self) -> &[WhisperTokenId] {
+        &self.sequence[self.n_prompt..]
+    }
+
+    /// How many tokens of the prompt and output are in the KV cache.
+    pub fn n_past(&self) -> usize {
+        self.n_past
+    }
+
+    /// Sum of the log probabilities of the sampled tokens.
+    pub fn sum_logprob(&self) -> f32 {
+        self.token_logprobs.iter().sum()
+    }
+
+    /// How many mel frames (10 ms each) into the window the last timestamp token is,
+    /// which is how far `whisper.cpp` would seek before decoding the next window.
+    pub fn seek_delta(&self) -> Option<c_int> {
+        seek_delta(self.tokens(), self.rules.beg)
+    }
+
+    /// Whether the end of text was pushed or the window is full.
+    pub fn is_done(&self) -> bool {
+        self.tokens().last() == Some(&self.rules.eot) || self.tokens().len() >= self.max_tokens
+    }
+
+    /// The masked logits for the next token.
+    ///
+    /// Runs the model on any tokens pushed since the last step.
+    /// Calling this again before [`Decoder::push`] does not run the model again.
+    ///
+    /// # Errors
+    /// Whatever [`WhisperState::decode`] or [`WhisperState::get_logits`] return.
+    pub fn logits(&mut self) -> Result<&[f32], WhisperError> {
+        self.step()?;
+        Ok(&self.logits)
+    }
+
+    /// The log-softmax of [`Decoder::logits`].
+    ///
+    /// # Errors
+    /// Whatever [`WhisperState::decode`] or [`WhisperState::get_logits`] return.
+    pub fn logprobs(&mut self) -> Result<&[f32], WhisperError> {
+        self.step()?;
+        Ok(&self.logprobs)
+    }
+
+    /// Append `token` to the output.
+    ///
+    /// If the logits for this step were computed, the token's log probability is added to
+    /// [`Decoder::sum_logprob`]. Pushing without computing them forces a token.
+    pub fn push(&mut self, token: WhisperTokenId) {
+        let logprob = match self.ready {
+            true => self.logprobs.get(token as usize).copied().unwrap_or(0.0),
+            false => 0.0,
+        };
+        self.sequence.push(token);
+        self.token_logprobs.push(logprob);
+        self.ready = false;
+    }
+
+    /// Drop all but the first `len` output tokens.
+    ///
+    /// What is left of them stays in the KV cache, so only the tokens pushed after this
+    /// have to be run through the model again.
+    pub fn truncate(&mut self, len: usize) {
+        if len >= self.tokens().len() {
+            return;
+        }
+        self.sequence.truncate(self.n_prompt + len);
+        self.token_logprobs.truncate(len);
+        self.n_past = self.n_past.min(self.sequence.len());
+        self.ready = false;
+    }
+
+    /// Sample and push tokens until [`Decoder::is_done`], and return all output tokens.
+    ///
+    /// # Errors
+    /// Whatever [`Decoder::logits`] returns.
+    pub fn run(&mut self, sampler: &mut impl Sampler) -> Result<&[WhisperTokenId], WhisperError> {
+        while !self.is_done() {
+            let token = sampler.sample(self.logits()?);
+            self.push(token);
+        }
+        Ok(self.tokens())
+    }
+
+    /// Run a beam search of width `beam_size` and return up to `n_best` of the hypotheses it finished,
+    /// best first.
+    ///
+    /// This is the search [`SamplingStrategy::BeamSearch`](crate::SamplingStrategy::BeamSearch) runs,
+    /// except that the other hypotheses are kept rather than thrown away,
+    /// for example to rescore them with another language model.
+    /// Beams are ranked by their cumulative log probability while searching
+    /// and hypotheses by their average log probability per token in the end.
+    /// Decoding starts from the tokens pushed so far, and the decoder is left at the best hypothesis.
+    ///
+    /// There is only one KV cache per state, unlike whisper.cpp which keeps one per beam.
+    /// Every time the search switches beams, the cache is cut back to where the two beams agree
+    /// and the rest of the new beam is decoded again in one batch. Beams often differ early on,
+    /// so a step can decode up to `beam_size` times the length of the beams rather than
+    /// `beam_size` tokens, and long outputs get slower per step as they grow.
+    ///
+    /// # Errors
+    /// Whatever [`Decoder::logits`] returns.
+    pub fn beam_search(
+        &mut self,
+        beam_size: usize,
+        n_best: usize,
+    ) -> Result<Vec<Hypothesis>, WhisperError> {
+        let start = Hypothesis {
+            tokens: self.tokens().to_vec(),
+            logprobs: self.token_logprobs.clone(),
+        };
+        if self.is_done() {
+            return Ok(vec![start]);
+        }
+        let (eot, max_tokens) = (self.rules.eot, self.max_tokens);
+        let hypotheses = beam_search(
+            start,
+            eot,
+            max_tokens,
+            beam_size,
+            n_best,
+            |tokens, logprobs| {
+                self.set_tokens(tokens);
+                logprobs.clear();
+                logprobs.extend_from_slice(self.logprobs()?);
+                Ok(())
+            },
+        )?;
+        if let Some(best) = hypotheses.first() {
+            self.set_tokens(&best.tokens);
+            self.token_logprobs.clone_from(&best.logprobs);
+        }
+        Ok(hypotheses)
+    }
+
+    /// Make `tokens` the output, keeping as much of the KV cache as possible.
+    fn set_tokens(&mut self, tokens: &[WhisperTokenId]) {
+        let common = self
+            .tokens()
+            .iter()
+            .zip(tokens)
+            .take_while(|(a, b)| a == b)
+            .count();
+        self.truncate(common);
+        for &token in &tokens[common..] {
+            self.push(token);
+        }
+    }
+
+    fn step(&mut self) -> Result<(), WhisperError> {
+        if self.ready {
+            return Ok(());
+        }
+        self.state
+            .decode(&self.sequence[self.n_past..], self.n_past, self.threads)?;
+        self.n_past = self.sequence.len();
+        self.logits.clear();
+        self.logits.extend_from_slice(self.state.get_logits()?);
+        self.rules.apply(
+            &self.sequence[self.n_prompt..],
+            &mut self.logits,
+            &mut self.logprobs,
+        );
+        self.ready = true;
+        Ok(())
+    }
+}
+
+/// A finished hypothesis of [`Decoder::beam_search`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Hypothesis {
+    /// The output tokens. Ends with the end of text token unless the window filled up first.
+    pub tokens: Vec<WhisperTokenId>,
+    /// The log probability of each token in `tokens`.
+    pub logprobs: Vec<f32>,
+}
+
+impl Hypothesis {
+    pub fn sum_logprob(&self) -> f32 {
+        self.logprobs.iter().sum()
+    }
+
+    /// Average log probability per token, which is what hypotheses of different lengths are compared by.
+    pub fn avg_logprob(&self) -> f32 {
+        self.sum_logprob() / self.logprobs.len().max(1) as f32
+    }
+}
+
+/// Beam search as in OpenAI's Whisper, over a model that gives the log probabilities
+/// of the next token after the given output tokens.
+fn beam_search(
+    start: Hypothesis,
+    eot: WhisperTokenId,
+    max_tokens: usize,
+    beam_size: usize,
+    n_best: usize,
+    mut logprobs_after: impl FnMut(&[WhisperTokenId], &mut Vec<f32>) -> Result<(), WhisperError>,
+) -> Result<Vec<Hypothesis>, WhisperError> {
+    let beam_size = beam_size.max(1);
+    let mut beams = vec![start];
+    let mut finished = Vec::new();
+    let mut logprobs = Vec::new();
+    while !beams.is_empty() && finished.len() < beam_size {
+        let mut candidates = Vec::new();
+        for (i, beam) in beams.iter().enumerate() {
+            logprobs_after(&beam.tokens, &mut logprobs)?;
+            let mut top: Vec<_> = (0..logprobs.len())
+                .filter(|&token| logprobs[token] > f32::NEG_INFINITY)
+                .collect();
+            top.sort_by(|&a, &b| logprobs[b].total_cmp(&logprobs[a]));
+            top.truncate(beam_size + 1);
+            candidates.extend(top.into_iter().map(|token| {
+                let logprob = logprobs[token];
+                (
+                    beam.sum_logprob() + logprob,
+                    i,
+                    token as WhisperTokenId,
+                    logprob,
+                )
+            }));
+        }
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut next = Vec::with_capacity(beam_size);
+        for (_, i, token, logprob) in candidates {
+            let mut hypothesis = beams[i].clone();
+            hypothesis.tokens.push(token);
+            hypothesis.logprobs.push(logprob);
+            if token == eot || hypothesis.tokens.len() >= max_tokens {
+                finished.push(hypothesis);
+            } else {
+                next.push(hypothesis);
+            }
+            if next.len() == beam_size {
+                break;
+            }
+        }
+        beams = next;
+    }
+    finished.sort_by(|a, b| b.avg_logprob().total_cmp(&a.avg_logprob()));
+    finished.truncate(n_best);
+    Ok(finished)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const N_VOCAB: usize = 24;
+    const NEG: f32 = f32::NEG_INFINITY;
+
+    // 0..5 text (1 is the blank), then the specials, then timestamps 15..24
+    fn rules() -> LogitRules {
+        LogitRules {
+            eot: 5,
+            sot: 6,
+            languages: 7..=8,
+            translate: 9,
+            transcribe: 10,
+            solm: 11,
+            prev: 12,
+            nosp: 13,
+            not: 14,
+            beg: 15,
+            blank: Some(1),
+            timestamps: true,
+            max_initial_tid: Some(2),
+        }
+    }
+
+    fn apply(rules: &LogitRules, tokens: &[WhisperTokenId], logits: &mut [f32]) -> Vec<f32> {
+        let mut logprobs = Vec::new();
+        rules.apply(tokens, logits, &mut logprobs);
+        logprobs
+    }
+
+    fn allowed(logits: &[f32]) -> Vec<WhisperTokenId> {
+        (0..logits.len())
+            .filter(|&i| logits[i] > NEG)
+            .map(|i| i as WhisperTokenId)
+            .collect()
+    }
+
+    #[test]
+    fn test_prompt_layout() {
+        let rules = rules();
+        let mut params = DecoderParams::new();
+        assert_eq!(rules.prompt(&params, Some(8), 448), [6, 8, 10]);
+        assert_eq!(rules.prompt(&params, None, 448), [6]);
+
+        params.translate(true).prompt_tokens(&[0, 1, 2, 3]);
+        assert_eq!(rules.prompt(&params, Some(7), 4), [12, 2, 3, 6, 7, 9]);
+
+        let mut rules = rules;
+        rules.timestamps = false;
+        assert_eq!(rules.prompt(&params, None, 448), [12, 0, 1, 2, 3, 6, 14]);
+    }
+
+    #[test]
+    fn test_first_step() {
+        let mut logits = vec![0.0; N_VOCAB];
+        logits[0] = 3.0;
+        apply(&rules(), &[], &mut logits);
+        // no blank, no end of text, no specials, and a first timestamp of at most tid0 = 2
+        assert_eq!(allowed(&logits), [0, 2, 3, 4, 15, 16, 17]);
+    }
+
+    #[test]
+    fn test_no_timestamps() {
+        let mut rules = rules();
+        rules.timestamps = false;
+        let mut logits = vec![0.0; N_VOCAB];
+        let logprobs = apply(&rules, &[0], &mut logits);
+        assert_eq!(allowed(&logits), [0, 1, 2, 3, 4, 5]);
+        let sum: f32 = logprobs.iter().map(|p| p.exp()).sum();
+        assert!((sum - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_timestamps_come_in_pairs() {
+        let rules = rules();
+
+        // a timestamp after text must be followed by another timestamp or the end of text
+        let mut logits = vec![0.0; N_VOCAB];
+        logits[5] = 3.0;
+        apply(&rules, &[16, 0, 17], &mut logits);
+        assert_eq!(allowed(&logits), [5, 17, 18, 19, 20, 21, 22, 23]);
+
+        // a pair of timestamps must be followed by text
+        let mut logits = vec![0.0; N_VOCAB];
+        apply(&rules, &[16, 0, 17, 17], &mut logits);
+        assert_eq!(allowed(&logits), [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_timestamps_increase() {
+        let mut logits = vec![0.0; N_VOCAB];
+        logits[0] = 3.0;
+        apply(&rules(), &[16, 0, 17, 17, 0, 19, 19, 0], &mut logits);
+        assert_eq!(allowed(&logits), [0, 1, 2, 3, 4, 5, 19, 20, 21, 22, 23]);
+        assert_eq!(seek_delta(&[16, 0, 19, 19, 0], 15), Some(8));
+        assert_eq!(seek_delta(&[15, 0], 15), None);
+    }
+
+    #[test]
+    fn test_likely_timestamp_suppresses_text() {
+        let mut logits = vec![0.0; N_VOCAB];
+        logits[0] = 2.0;
+        logits[20..].fill(1.5);
+        let logprobs = apply(&rules(), &[16, 0], &mut logits);
+        // no single timestamp beats the text token, but together they do
+        assert_eq!(allowed(&logits), [16, 17, 18, 19, 20, 21, 22, 23]);
+        assert_eq!(logprobs[0], NEG);
+    }
+
+    // from the start, 0 is more likely than 1, but 1 is almost always followed by the end of text 3
+    fn toy_model(tokens: &[WhisperTokenId], logprobs: &mut Vec<f32>) -> Result<(), WhisperError> {
+        let probs: [f32; 4] = match tokens {
+            [] => [0.6, 0.4, 0.0, 0.0],
+            [1] => [0.05, 0.05, 0.0, 0.9],
+            _ => [0.3, 0.3, 0.3, 0.1],
+        };
+        logprobs.clear();
+        logprobs.extend(probs.iter().map(|p| p.ln()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_beam_search_finds_less_greedy_hypothesis() {
+        let start = Hypothesis {
+            tokens: Vec::new(),
+            logprobs: Vec::new(),
+        };
+        let best = beam_search(start, 3, 4, 2, 2, toy_model).unwrap();
+        assert_eq!(best.len(), 2);
+        assert_eq!(best[0].tokens, [1, 3]);
+        assert!((best[0].sum_logprob() - (0.4f32 * 0.9).ln()).abs() < 1e-5);
+        // the runner up ran out of room
+        assert_eq!(best[1].tokens.len(), 4);
+        assert!(best[0].avg_logprob() > best[1].avg_logprob());
+    }
+
+    #[test]
+    fn test_beam_search_continues_from_start() {
+        let start = Hypothesis {
+            tokens: vec![1],
+            logprobs: vec![-0.5],
+        };
+        let best = beam_search(start, 3, 4, 1, 1, toy_model).unwrap();
+        assert_eq!(best[0].tokens, [1, 3]);
+        assert_eq!(best[0].logprobs[0], -0.5);
+    }
+
+    #[test]
+    fn test_greedy() {
+        assert_eq!(Greedy.sample(&[0.0, 3.0, NEG, 3.0, 1.0]), 1);
+        assert_eq!(Greedy.sample(&[NEG, NEG, 0.5]), 2);
+    }
+
+    #[test]
+    fn test_top_k_stays_in_top_k() {
+        let logits = [0.0, 3.0, NEG, 2.9, 1.0, 2.8];
+        let mut one = TopK::new(1, 1.0, 7);
+        let mut two = TopK::new(2, 1.0, 7);
+        for _ in 0..200 {
+            assert_eq!(one.sample(&logits), 1);
+            assert!(matches!(two.sample(&logits), 1 | 3));
+        }
+    }
+
+    #[test]
+    fn test_nucleus_stays_in_nucleus() {
+        // probabilities of about 0.67, 0.24 and 0.09
+        let logits = [NEG, 2.0, 1.0, 0.0];
+        let mut small = Nucleus::new(0.5, 1.0, 3);
+        let mut large = Nucleus::new(0.8, 1.0, 3);
+        let mut seen = [false; 4];
+        for _ in 0..200 {
+            assert_eq!(small.sample(&logits), 1);
+            seen[large.sample(&logits) as usize] = true;
+        }
+        assert_eq!(seen, [false, true, true, false]);
+    }
+
+    #[test]
+    fn test_samplers_are_reproducible() {
+        let logits: Vec<f32> = (0..32).map(|i| (i % 7) as f32 * 0.3).collect();
+        let mut a = TopK::new(10, 1.2, 99);
+        let mut b = TopK::new(10, 1.2, 99);
+        let a: Vec<_> = (0..50).map(|_| a.sample(&logits)).collect();
+        let b: Vec<_> = (0..50).map(|_| b.sample(&logits)).collect();
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_closure_sampler() {
+        let mut calls = 0;
+        let mut sampler = |logits: &[f32]| {
+            calls += 1;
+            logits.len() as WhisperTokenId - 1
+        };
+        assert_eq!(sampler.sample(&[0.0, 0.0]), 1);
+        assert_eq!(calls, 1);
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "test-with-tiny-model")]
+mod test_with_tiny_model {
+    use super::*;
+    use crate::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+    const MODEL_PATH: &str = "./sys/whisper.cpp/models/ggml-tiny.en.bin";
+    const SAMPLE_PATH: &str = "./sys/whisper.cpp/samples/jfk.wav";
+
+    // These tests expect that the tiny.en model has been downloaded
+    // using the script `sys/whisper.cpp/models/download-ggml-model.sh tiny.en`
+
+    fn jfk() -> (WhisperContext, Vec<f32>) {
+        let ctx = WhisperContext::new_with_params(MODEL_PATH, WhisperContextParameters::default())
+            .expect("Download the ggml-tiny.en model using 'sys/whisper.cpp/models/download-ggml-model.sh tiny.en'");
+        let samples: Vec<i16> = hound::WavReader::open(SAMPLE_PATH)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(Result::unwrap)
+            .collect();
+        let mut audio = vec![0.0f32; samples.len()];
+        crate::convert_integer_to_float_audio(&samples, &mut audio).unwrap();
+        (ctx, audio)
+    }
+
+    fn text(ctx: &WhisperContext, tokens: &[WhisperTokenId]) -> String {
+        let bytes: Vec<u8> = tokens
+            .iter()
+            .filter(|&&token| token < ctx.token_eot())
+            .flat_map(|&token| ctx.token_to_bytes(token).unwrap().to_vec())
+            .collect();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_greedy_run_matches_full() {
+        let (ctx, audio) = jfk();
+        let mut state = ctx.create_state().unwrap();
+
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_temperature_inc(0.0);
+        state.full(params, &audio).unwrap();
+        let expected: String = state
+            .as_iter()
+            .map(|segment| segment.to_str().unwrap().to_string())
+            .collect();
+
+        state.pcm_to_mel(&audio, 1).unwrap();
+        state.encode(0, 1).unwrap();
+        let mut decoder = Decoder::new(&mut state, &DecoderParams::default()).unwrap();
+        let tokens = decoder.run(&mut Greedy).unwrap().to_vec();
+        assert_eq!(text(&ctx, &tokens).trim(), expected.trim());
+    }
+
+    #[test]
+    fn test_beam_scores_match_token_by_token_decoding() {
+        let (ctx, audio) = jfk();
+        let mut state = ctx.create_state().unwrap();
+        state.pcm_to_mel(&audio, 1).unwrap();
+        state.encode(0, 1).unwrap();
+
+        let hypotheses = Decoder::new(&mut state, &DecoderParams::default())
+            .unwrap()
+            .beam_search(3, 3)
+            .unwrap();
+        assert!(!hypotheses.is_empty());
+        // switching beams decodes several tokens at once, which must score the same
+        // as pushing them one at a time
+        for hypothesis in &hypotheses {
+            let mut decoder = Decoder::new(&mut state, &DecoderParams::default()).unwrap();
+            for &token in &hypothesis.tokens {
+                decoder.logprobs().unwrap();
+                decoder.push(token);
+            }
+            assert!((decoder.sum_logprob() - hypothesis.sum_logprob()).abs() < 1e-2);
+        }
+    }
+}
@@ -0,0 +1,478 @@
+use std::ffi::c_int;
+
+use super::metrics::combined_metrics;
+use crate::{
+    FullParams, SamplingStrategy, SegmentMetrics, WhisperError, WhisperSegment, WhisperState,
+    WhisperStateSegmentIterator, WhisperTokenId,
+};
+
+/// Length of a window, in milliseconds.
+const WINDOW_MS: i64 = 30_000;
+
+/// Decides what to do with the transcript of each window, see [`WhisperState::full_with_fallback`].
+///
+/// Implemented for closures taking a [`FallbackCandidate`].
+///
+/// # Examples
+/// ```no_run
+/// # use whisper_rs::{FallbackDecision, FallbackPolicy, FullParams, SamplingStrategy, ThresholdFallback, WhisperContext, WhisperContextParameters};
+/// # let ctx = WhisperContext::new_with_params("model.bin", WhisperContextParameters::default()).unwrap();
+/// # let audio = vec![0.0f32; 16000];
+/// let mut thresholds = ThresholdFallback::default();
+/// let mut policy = |candidate: &whisper_rs::FallbackCandidate| {
+///     let text = candidate.text().unwrap_or_default();
+///     if text.contains("Subtitles by") {
+///         return match candidate.attempt {
+///             0..=2 => FallbackDecision::Retry { temperature: 0.4 * (candidate.attempt + 1) as f32, strategy: None },
+///             _ => FallbackDecision::Discard,
+///         };
+///     }
+///     thresholds.decide(candidate)
+/// };
+///
+/// let mut state = ctx.create_state().unwrap();
+/// let params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+/// for window in state.full_with_fallback(params, &audio, &mut policy).unwrap() {
+///     for segment in window.segments {
+///         println!("{}", segment.text);
+///     }
+/// }
+/// ```
+pub trait FallbackPolicy {
+    fn decide(&mut self, candidate: &FallbackCandidate) -> FallbackDecision;
+}
+
+impl<F: FnMut(&FallbackCandidate) -> FallbackDecision> FallbackPolicy for F {
+    fn decide(&mut self, candidate: &FallbackCandidate) -> FallbackDecision {
+        self(candidate)
+    }
+}
+
+/// What a [`FallbackPolicy`] wants done with a window.
+#[derive(Debug, Clone)]
+pub enum FallbackDecision {
+    /// Keep the transcript and go on to the next window.
+    Accept,
+    /// Drop the transcript and go on to the next window.
+    Discard,
+    /// Decode the window again at another temperature, and with another strategy if given.
+    Retry {
+        temperature: f32,
+        strategy: Option<SamplingStrategy>,
+    },
+    /// Decode the window again with this text as prompt instead of the transcript so far.
+    /// An empty prompt decodes it without context.
+    Reprompt(String),
+}
+
+/// The transcript of one window, before a [`FallbackPolicy`] decided on it.
+pub struct FallbackCandidate<'a> {
+    state: &'a WhisperState,
+    /// Start of the window in centiseconds (10s of milliseconds).
+    pub start: i64,
+    /// End of the window in centiseconds (10s of milliseconds).
+    pub end: i64,
+    /// How often the window was decoded before, 0 the first time.
+    pub attempt: usize,
+    /// The temperature the window was decoded at.
+    pub temperature: f32,
+}
+
+impl<'a> FallbackCandidate<'a> {
+    /// The segments of this window.
+    pub fn segments(&self) -> WhisperStateSegmentIterator<'a> {
+        self.state.as_iter()
+    }
+
+    /// The text of this window.
+    ///
+    /// # Errors
+    /// [`WhisperError::InvalidUtf8`] if the text is not valid UTF-8.
+    pub fn text(&self) -> Result<String, WhisperError> {
+        self.segments()
+            .map(|segment| segment.to_str().map(str::to_string))
+            .collect()
+    }
+
+    /// [`SegmentMetrics`] of all segments of this window together,
+    /// with the no-speech probability of the first one.
+    ///
+    /// # Errors
+    /// Whatever [`WhisperSegment::to_bytes`] returns.
+    pub fn metrics(&self) -> Result<SegmentMetrics, WhisperError> {
+        let segments: Vec<WhisperSegment> = self.segments().collect();
+        combined_metrics(&segments)
+    }
+}
+
+/// The fallback whisper.cpp does on its own, as a [`FallbackPolicy`] to build on.
+///
+/// A window that looks silent is discarded, and one whose text is unlikely or repetitive
+/// is retried at a higher temperature until `max_temperature` is reached.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThresholdFallback {
+    /// Added to the temperature on every retry. Defaults to 0.2.
+    pub temperature_increment: f32,
+    /// Highest temperature to retry at. Defaults to 1.0.
+    pub max_temperature: f32,
+    /// Lowest acceptable average log probability. Defaults to -1.0.
+    pub min_avg_logprob: Option<f32>,
+    /// Highest acceptable compression ratio. Defaults to 2.4.
+    pub max_compression_ratio: Option<f32>,
+    /// A window above this no-speech probability and below `min_avg_logprob` is silent. Defaults to 0.6.
+    pub no_speech_threshold: Option<f32>,
+}
+
+impl Default for ThresholdFallback {
+    fn default() -> Self {
+        Self {
+            temperature_increment: 0.2,
+            max_temperature: 1.0,
+            min_avg_logprob: Some(-1.0),
+            max_compression_ratio: Some(2.4),
+            no_speech_threshold: Some(0.6),
+        }
+    }
+}
+
+impl ThresholdFallback {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn temperature_increment(&mut self, temperature_increment: f32) -> &mut Self {
+        self.temperature_increment = temperature_increment;
+        self
+    }
+    pub fn max_temperature(&mut self, max_temperature: f32) -> &mut Self {
+        self.max_temperature = max_temperature;
+        self
+    }
+    pub fn min_avg_logprob(&mut self, min_avg_logprob: Option<f32>) -> &mut Self {
+        self.min_avg_logprob = min_avg_logprob;
+        self
+    }
+    pub fn max_compression_ratio(&mut self, max_compression_ratio: Option<f32>) -> &mut Self {
+        self.max_compression_ratio = max_compression_ratio;
+        self
+    }
+    pub fn no_speech_threshold(&mut self, no_speech_threshold: Option<f32>) -> &mut Self {
+        self.no_speech_threshold = no_speech_threshold;
+        self
+    }
+
+    /// The decision for a window decoded at `temperature` with `metrics`.
+    pub fn decide_metrics(&self, metrics: &SegmentMetrics, temperature: f32) -> FallbackDecision {
+        let unlikely = self
+            .min_avg_logprob
+            .is_some_and(|min| metrics.avg_logprob < min);
+        if unlikely
+            && self
+                .no_speech_threshold
+                .is_some_and(|threshold| metrics.no_speech_probability > threshold)
+        {
+            return FallbackDecision::Discard;
+        }
+        let repetitive = self
+            .max_compression_ratio
+            .is_some_and(|max| metrics.compression_ratio > max);
+        let temperature = temperature + self.temperature_increment;
+        if (unlikely || repetitive)
+            && self.temperature_increment > 0.0
+            && temperature <= self.max_temperature + 1e-6
+        {
+            FallbackDecision::Retry {
+                temperature,
+                strategy: None,
+            }
+        } else {
+            FallbackDecision::Accept
+        }
+    }
+}
+
+impl FallbackPolicy for ThresholdFallback {
+    fn decide(&mut self, candidate: &FallbackCandidate) -> FallbackDecision {
+        match candidate.metrics() {
+            Ok(metrics) => self.decide_metrics(&metrics, candidate.temperature),
+            Err(_) => FallbackDecision::Accept,
+        }
+    }
+}
+
+/// A window accepted by a [`FallbackPolicy`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscribedWindow {
+    /// Start of the window in centiseconds (10s of milliseconds).
+    pub start: i64,
+    /// End of the window in centiseconds (10s of milliseconds),
+    /// the end of its last segment unless nothing was timestamped.
+    pub end: i64,
+    /// How often the window was decoded before it was accepted.
+    pub retries: usize,
+    /// The temperature of the accepted transcript.
+    pub temperature: f32,
+    pub segments: Vec<TranscribedSegment>,
+}
+
+/// A segment of a [`TranscribedWindow`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranscribedSegment {
+    pub text: String,
+    /// Start time in centiseconds (10s of milliseconds).
+    pub start: i64,
+    /// End time in centiseconds (10s of milliseconds).
+    pub end: i64,
+    pub metrics: SegmentMetrics,
+}
+
+impl WhisperState {
+    /// Like [`WhisperState::full`], but lets `policy` decide on the transcript of every 30 second window.
+    ///
+    /// Each window is decoded by itself with whisper.cpp's own fallback turned off,
+    /// then handed to `policy` as a [`FallbackCandidate`], which can accept or discard it,
+    /// or have it decoded again at another temperature, with another strategy or another prompt.
+    /// The next window starts where the last accepted segment ends, as whisper.cpp does,
+    /// or 30 seconds later if the window was discarded or nothing in it was timestamped.
+    /// Accepted text is the prompt for the next window, unless `params` turns context off.
+    /// The policy is asked until it accepts or discards, so it should give up at some [`FallbackCandidate::attempt`].
+    /// [`ThresholdFallback`] does what whisper.cpp does on its own.
+    ///
+    /// The segments in the state only hold the last decoded window, use the returned ones instead.
+    ///
+    /// # Arguments
+    /// * params: [crate::FullParams] struct. The offset and duration are honored.
+    /// * data: raw PCM audio data, 32 bit floating point at a sample rate of 16 kHz, 1 channel.
+    /// * policy: decides on each window.
+    ///
+    /// # Errors
+    /// * Whatever [`WhisperState::full`] returns.
+    /// * [`WhisperError::InvalidUtf8`] if an accepted segment is not valid UTF-8.
+    /// * Whatever [`WhisperContext::tokenize`](crate::WhisperContext::tokenize) returns for a new prompt.
+    pub fn full_with_fallback(
+        &mut self,
+        params: FullParams,
+        data: &[f32],
+        policy: &mut impl FallbackPolicy,
+    ) -> Result<Vec<TranscribedWindow>, WhisperError> {
+        let mut end_ms = data.len() as i64 * 1000 / whisper_rs_sys::WHISPER_SAMPLE_RATE as i64;
+        if params.fp.duration_ms > 0 {
+            end_ms = end_ms.min(params.fp.offset_ms as i64 + params.fp.duration_ms as i64);
+        }
+        let n_prompt = self.ctx.n_text_ctx() as usize / 2;
+        let mut context: Vec<WhisperTokenId> = Vec::new();
+        let mut windows = Vec::new();
+        let mut offset_ms = params.fp.offset_ms as i64;
+        while offset_ms < end_ms {
+            let duration_ms = WINDOW_MS.min(end_ms - offset_ms);
+            let mut run = params.clone();
+            run.set_offset_ms(offset_ms as c_int);
+            run.set_duration_ms(duration_ms as c_int);
+            run.set_temperature_inc(0.0);
+            if offset_ms > params.fp.offset_ms as i64 && !params.fp.no_context {
+                run.set_tokens(&context[context.len().saturating_sub(n_prompt)..]);
+            }
+
+            let mut temperature = params.fp.temperature;
+            let mut retries = 0;
+            let mut next_ms = offset_ms + duration_ms;
+            loop {
+                run.set_temperature(temperature);
+                self.full(run.clone(), data)?;
+                let candidate = FallbackCandidate {
+                    state: self,
+                    start: offset_ms / 10,
+                    end: (offset_ms + duration_ms) / 10,
+                    attempt: retries,
+                    temperature,
+                };
+                match policy.decide(&candidate) {
+                    FallbackDecision::Accept => {
+                        let mut window = self.transcribed_window(&candidate, &mut context)?;
+                        // like whisper.cpp, go on from the end of the last segment,
+                        // so speech cut off at the end of the window is decoded again
+                        next_ms = match window.segments.last() {
+                            Some(segment) if segment.end * 10 > offset_ms => segment.end * 10,
+                            _ => offset_ms + duration_ms,
+                        };
+                        window.end = next_ms / 10;
+                        windows.push(window);
+                        break;
+                    }
+                    FallbackDecision::Discard => break,
+                    FallbackDecision::Retry {
+                        temperature: new_temperature,
+                        strategy,
+                    } => {
+                        temperature = new_temperature;
+                        if let Some(strategy) = strategy {
+                            run.set_sampling_strategy(strategy);
+                        }
+                    }
+                    FallbackDecision::Reprompt(prompt) => {
+                        let tokens = if prompt.is_empty() {
+                            Vec::new()
+                        } else {
+                            self.ctx.tokenize(&prompt, prompt.len())?
+                        };
+                        run.set_tokens(&tokens[tokens.len().saturating_sub(n_prompt)..]);
+                    }
+                }
+                retries += 1;
+            }
+            offset_ms = next_ms;
+        }
+        Ok(windows)
+    }
+
+    /// Copy out an accepted window, adding its text tokens to `context`.
+    fn transcribed_window(
+        &self,
+        candidate: &FallbackCandidate,
+        context: &mut Vec<WhisperTokenId>,
+    ) -> Result<TranscribedWindow, WhisperError> {
+        let eot = self.ctx.token_eot();
+        let segments = candidate
+            .segments()
+            .map(|segment| {
+                context.extend(
+                    (0..segment.n_tokens())
+                        .filter_map(|i| segment.get_token(i))
+                        .map(|token| token.token_id())
+                        .filter(|&id| id < eot),
+                );
+                Ok(TranscribedSegment {
+                    text: segment.to_str()?.to_string(),
+                    start: segment.start_timestamp(),
+                    end: segment.end_timestamp(),
+                    metrics: segment.metrics()?,
+                })
+            })
+            .collect::<Result<_, WhisperError>>()?;
+        Ok(TranscribedWindow {
+            start: candidate.start,
+            end: candidate.end,
+            retries: candidate.attempt,
+            temperature: candidate.temperature,
+            segments,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metrics(
+        avg_logprob: f32,
+        compression_ratio: f32,
+        no_speech_probability: f32,
+    ) -> SegmentMetrics {
+        SegmentMetrics {
+            n_tokens: 20,
+            avg_logprob,
+            min_logprob: avg_logprob * 2.0,
+            compression_ratio,
+            no_speech_probability,
+            tokens_per_second: 3.0,
+        }
+    }
+
+    fn retry_temperature(decision: FallbackDecision) -> Option<f32> {
+        match decision {
+            FallbackDecision::Retry { temperature, .. } => Some(temperature),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_threshold_fallback_accepts_good_text() {
+        let policy = ThresholdFallback::new();
+        assert!(matches!(
+            policy.decide_metrics(&metrics(-0.3, 1.4, 0.01), 0.0),
+            FallbackDecision::Accept
+        ));
+    }
+
+    #[test]
+    fn test_threshold_fallback_retries_until_max_temperature() {
+        let policy = ThresholdFallback::new();
+        let looping = metrics(-0.3, 3.5, 0.01);
+        let mut temperature = 0.0;
+        let mut retries = 0;
+        while let Some(next) = retry_temperature(policy.decide_metrics(&looping, temperature)) {
+            assert!(next > temperature);
+            temperature = next;
+            retries += 1;
+        }
+        assert_eq!(retries, 5);
+        assert!((temperature - 1.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn test_threshold_fallback_discards_silence() {
+        let policy = ThresholdFallback::new();
+        assert!(matches!(
+            policy.decide_metrics(&metrics(-1.5, 1.2, 0.9), 0.0),
+            FallbackDecision::Discard
+        ));
+        // confident text is kept even if the model thought there was no speech
+        assert!(matches!(
+            policy.decide_metrics(&metrics(-0.2, 1.2, 0.9), 0.0),
+            FallbackDecision::Accept
+        ));
+
+        let mut policy = ThresholdFallback::new();
+        policy.no_speech_threshold(None);
+        assert_eq!(
+            retry_temperature(policy.decide_metrics(&metrics(-1.5, 1.2, 0.9), 0.0)),
+            Some(0.2)
+        );
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "test-with-tiny-model")]
+mod test_with_tiny_model {
+    use crate::{
+        FallbackDecision, FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters,
+    };
+    const MODEL_PATH: &str = "./sys/whisper.cpp/models/ggml-tiny.en.bin";
+    const SAMPLE_PATH: &str = "./sys/whisper.cpp/samples/jfk.wav";
+
+    // These tests expect that the tiny.en model has been downloaded
+    // using the script `sys/whisper.cpp/models/download-ggml-model.sh tiny.en`
+
+    #[test]
+    fn test_windows_continue_after_the_last_segment() {
+        let ctx = WhisperContext::new_with_params(MODEL_PATH, WhisperContextParameters::default())
+            .expect("Download the ggml-tiny.en model using 'sys/whisper.cpp/models/download-ggml-model.sh tiny.en'");
+        let samples: Vec<i16> = hound::WavReader::open(SAMPLE_PATH)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(Result::unwrap)
+            .collect();
+        let mut speech = vec![0.0f32; samples.len()];
+        crate::convert_integer_to_float_audio(&samples, &mut speech).unwrap();
+        // the speech three times over, so some of it crosses the 30 second mark
+        let audio = [speech.clone(), speech.clone(), speech].concat();
+
+        let mut state = ctx.create_state().unwrap();
+        let params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        let windows = state
+            .full_with_fallback(params, &audio, &mut |_: &crate::FallbackCandidate| {
+                FallbackDecision::Accept
+            })
+            .unwrap();
+        assert!(windows.len() >= 2);
+        for pair in windows.windows(2) {
+            assert_eq!(pair[1].start, pair[0].end);
+            assert_eq!(pair[0].end, pair[0].segments.last().unwrap().end);
+        }
+        let text: String = windows
+            .iter()
+            .flat_map(|window| &window.segments)
+            .map(|segment| segment.text.as_str())
+            .collect();
+        assert_eq!(text.matches("country").count(), 6);
+    }
+}
@@ -0,0 +1,431 @@
+use std::ffi::c_int;
+use std::sync::{Arc, Mutex};
+
+use crate::{
+    compression_ratio, FullParams, SegmentCallbackData, WhisperError, WhisperState,
+    WhisperVadSegment,
+};
+
+/// Length of the window re-decoded after a hallucination, in milliseconds.
+const RETRY_WINDOW_MS: c_int = 30_000;
+
+/// Why a segment looks hallucinated, see [`WhisperState::full_checked`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hallucination {
+    /// The same words repeat over and over, like "Thank you. Thank you. Thank you."
+    Repetition,
+    /// The text compresses too well, see [`compression_ratio`].
+    HighCompressionRatio,
+    /// The text lies outside the speech regions that were given.
+    NoSpeech,
+}
+
+/// When to treat decoded text as a hallucination and how to retry, see [`WhisperState::full_checked`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct HallucinationParams {
+    /// Most times in a row any run of words may repeat. Defaults to 4.
+    pub max_repeats: Option<usize>,
+    /// Longest run of words checked for repeats. Defaults to 10.
+    pub max_ngram_len: usize,
+    /// Highest compression ratio of a segment's text. Defaults to 2.4, like OpenAI's Whisper.
+    pub max_compression_ratio: Option<f32>,
+    /// Where there is speech, usually from a [`WhisperVadContext`](crate::WhisperVadContext).
+    /// Not checked if empty, which is the default.
+    pub speech: Vec<WhisperVadSegment>,
+    /// Least part of a segment that has to overlap with `speech`. Defaults to 0.2.
+    pub min_speech_overlap: f32,
+    /// How often to re-decode a window before giving up on it. Defaults to 2.
+    pub max_retries: usize,
+    /// Added to the temperature on every retry. Defaults to 0.2.
+    pub temperature_increment: f32,
+}
+
+impl Default for HallucinationParams {
+    fn default() -> Self {
+        Self {
+            max_repeats: Some(4),
+            max_ngram_len: 10,
+            max_compression_ratio: Some(2.4),
+            speech: Vec::new(),
+            min_speech_overlap: 0.2,
+            max_retries: 2,
+            temperature_increment: 0.2,
+        }
+    }
+}
+
+impl HallucinationParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn max_repeats(&mut self, max_repeats: Option<usize>) -> &mut Self {
+        self.max_repeats = max_repeats;
+        self
+    }
+    pub fn max_ngram_len(&mut self, max_ngram_len: usize) -> &mut Self {
+        self.max_ngram_len = max_ngram_len;
+        self
+    }
+    pub fn max_compression_ratio(&mut self, max_compression_ratio: Option<f32>) -> &mut Self {
+        self.max_compression_ratio = max_compression_ratio;
+        self
+    }
+    pub fn speech(&mut self, speech: impl IntoIterator<Item = WhisperVadSegment>) -> &mut Self {
+        self.speech = speech.into_iter().collect();
+        self
+    }
+    pub fn min_speech_overlap(&mut self, min_speech_overlap: f32) -> &mut Self {
+        self.min_speech_overlap = min_speech_overlap;
+        self
+    }
+    pub fn max_retries(&mut self, max_retries: usize) -> &mut Self {
+        self.max_retries = max_retries;
+        self
+    }
+    pub fn temperature_increment(&mut self, temperature_increment: f32) -> &mut Self {
+        self.temperature_increment = temperature_increment;
+        self
+    }
+}
+
+/// A segment returned by [`WhisperState::full_checked`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct CheckedSegment {
+    pub text: String,
+    /// Start time in centiseconds (10s of milliseconds).
+    pub start: i64,
+    /// End time in centiseconds (10s of milliseconds).
+    pub end: i64,
+    /// Set if the segment still looks hallucinated after every retry.
+    pub hallucination: Option<Hallucination>,
+    /// How often the window this segment is in was re-decoded.
+    pub retries: usize,
+}
+
+/// Follows the segments of one run and spots where they go wrong.
+struct Tracker {
+    params: HallucinationParams,
+    words: Vec<(c_int, String)>,
+}
+
+impl Tracker {
+    fn new(params: HallucinationParams) -> Self {
+        Self {
+            params,
+            words: Vec::new(),
+        }
+    }
+
+    /// Check the next segment, returning the index of the segment the hallucination starts in.
+    fn push(&mut self, segment: &SegmentCallbackData) -> Option<(c_int, Hallucination)> {
+        let text = segment.text.trim();
+        if text.is_empty() {
+            return None;
+        }
+        if self
+            .params
+            .max_compression_ratio
+            .is_some_and(|max| compression_ratio(text.as_bytes()) > max)
+        {
+            return Some((segment.segment, Hallucination::HighCompressionRatio));
+        }
+        if !self.params.speech.is_empty() {
+            let (start, end) = (segment.start_timestamp as f32, segment.end_timestamp as f32);
+            let overlap: f32 = self
+                .params
+                .speech
+                .iter()
+                .map(|speech| (end.min(speech.end) - start.max(speech.start)).max(0.0))
+                .sum();
+            if overlap < self.params.min_speech_overlap * (end - start).max(1.0) {
+                return Some((segment.segment, Hallucination::NoSpeech));
+            }
+        }
+
+        self.words.extend(
+            text.split_whitespace()
+                .map(normalize)
+                .filter(|word| !word.is_empty())
+                .map(|word| (segment.segment, word)),
+        );
+        let max_repeats = self.params.max_repeats?;
+        let words: Vec<&str> = self.words.iter().map(|(_, word)| word.as_str()).collect();
+        repeated_tail(&words, max_repeats, self.params.max_ngram_len)
+            .map(|first| (self.words[first].0, Hallucination::Repetition))
+    }
+}
+
+/// Lowercase `word` without punctuation.
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// If `words` end in a run of up to `max_len` words repeated more than `max_repeats` times in a row,
+/// the index of the first word of the first repeat.
+fn repeated_tail(words: &[&str], max_repeats: usize, max_len: usize) -> Option<usize> {
+    (1..=max_len).find_map(|n| {
+        let last = words.len().checked_sub(n).map(|start| &words[start..])?;
+        let repeats = words
+            .rchunks_exact(n)
+            .take_while(|&chunk| chunk == last)
+            .count();
+        (repeats > max_repeats).then(|| words.len() - repeats * n)
+    })
+}
+
+impl WhisperState {
+    /// Like [`WhisperState::full`], but watches the segments for hallucinations as they come in.
+    ///
+    /// A segment that repeats the words before it, compresses too well or has no speech under it
+    /// aborts the run through the abort callback. Decoding then starts over at the segment
+    /// where the problem began, for one window with the temperature raised and without the previous text
+    /// as prompt, which is what usually gets Whisper out of a loop, and carries on normally after that.
+    /// If the window still looks wrong after [`HallucinationParams::max_retries`] tries, it is kept
+    /// and its segments are flagged with [`CheckedSegment::hallucination`].
+    ///
+    /// The segment and abort callbacks of `params` are replaced. The segments in the state
+    /// only hold the last run, use the returned ones instead.
+    ///
+    /// # Arguments
+    /// * params: [crate::FullParams] struct.
+    /// * data: raw PCM audio data, 32 bit floating point at a sample rate of 16 kHz, 1 channel.
+    /// * check: what counts as a hallucination and how to retry.
+    ///
+    /// # Errors
+    /// * Whatever [`WhisperState::full`] returns, unless the run was aborted here.
+    /// * [`WhisperError::InvalidUtf8`] if a segment is not valid UTF-8.
+    pub fn full_checked(
+        &mut self,
+        params: FullParams,
+        data: &[f32],
+        check: &HallucinationParams,
+    ) -> Result<Vec<CheckedSegment>, WhisperError> {
+        let end = data.len() as i64 * 1000 / whisper_rs_sys::WHISPER_SAMPLE_RATE as i64;
+        let mut segments = Vec::new();
+        let mut offset_ms = params.fp.offset_ms as i64;
+        let mut retries = 0;
+        loop {
+            let mut run = params.clone();
+            run.set_offset_ms(offset_ms as c_int);
+            if retries > 0 {
+                run.set_temperature(
+                    params.fp.temperature + retries as f32 * check.temperature_increment,
+                );
+                run.set_no_context(true);
+                run.set_duration_ms(RETRY_WINDOW_MS);
+            }
+            let found = Arc::new(Mutex::new(None));
+            if retries < check.max_retries {
+                let mut tracker = Tracker::new(check.clone());
+                let callback_found = found.clone();
+                run.set_segment_callback_safe_lossy(move |segment: SegmentCallbackData| {
+                    let mut found = callback_found.lock().unwrap();
+                    if found.is_none() {
+                        *found = tracker.push(&segment);
+                    }
+                });
+                let abort_found = found.clone();
+                run.set_abort_callback_safe(move || abort_found.lock().unwrap().is_some());
+            } else {
+                run.set_segment_callback_safe_lossy::<_, fn(SegmentCallbackData)>(None);
+                run.set_abort_callback_safe::<_, fn() -> bool>(None);
+            }
+
+            let result = self.full(run, data);
+            let found = found.lock().unwrap().take();
+            if found.is_none() {
+                result?;
+            }
+            let mut run_segments = self
+                .as_iter()
+                .map(|segment| {
+                    Ok(SegmentCallbackData {
+                        segment: segment.segment_index(),
+                        start_timestamp: segment.start_timestamp(),
+                        end_timestamp: segment.end_timestamp(),
+                        text: segment.to_str()?.to_string(),
+                    })
+                })
+                .collect::<Result<Vec<_>, WhisperError>>()?;
+
+            if let Some((first, _)) = found {
+                run_segments.truncate(first as usize);
+                segments.extend(checked(&run_segments, check, retries));
+                offset_ms = self
+                    .get_segment(first)
+                    .map_or(offset_ms, |segment| segment.start_timestamp() * 10);
+                retries += 1;
+                continue;
+            }
+            segments.extend(checked(&run_segments, check, retries));
+            if retries == 0 {
+                break;
+            }
+            // the retried window is done, carry on after it with the original parameters
+            offset_ms = match run_segments.last() {
+                Some(segment) if segment.end_timestamp * 10 > offset_ms => {
+                    segment.end_timestamp * 10
+                }
+                _ => offset_ms + RETRY_WINDOW_MS as i64,
+            };
+            retries = 0;
+            if offset_ms >= end {
+                break;
+            }
+        }
+        Ok(segments)
+    }
+}
+
+/// Turn the segments of one run into [`CheckedSegment`]s, flagging hallucinations.
+fn checked(
+    segments: &[SegmentCallbackData],
+    check: &HallucinationParams,
+    retries: usize,
+) -> Vec<CheckedSegment> {
+    let mut tracker = Tracker::new(check.clone());
+    let mut checked: Vec<CheckedSegment> = segments
+        .iter()
+        .map(|segment| CheckedSegment {
+            text: segment.text.clone(),
+            start: segment.start_timestamp,
+            end: segment.end_timestamp,
+            hallucination: None,
+            retries,
+        })
+        .collect();
+    for (i, segment) in segments.iter().enumerate() {
+        if let Some((first, hallucination)) = tracker.push(segment) {
+            for checked in &mut checked[first as usize..=i] {
+                checked.hallucination.get_or_insert(hallucination);
+            }
+        }
+    }
+    checked
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn segment(segment: c_int, start: i64, end: i64, text: &str) -> SegmentCallbackData {
+        SegmentCallbackData {
+            segment,
+            start_timestamp: start,
+            end_timestamp: end,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_repeated_tail() {
+        let words = ["so", "thank", "you", "thank", "you", "thank", "you"];
+        assert_eq!(repeated_tail(&words, 2, 4), Some(1));
+        assert_eq!(repeated_tail(&words, 3, 4), None);
+        assert_eq!(repeated_tail(&words, 2, 1), None);
+        assert_eq!(repeated_tail(&["no", "no", "no"], 2, 4), Some(0));
+        assert_eq!(repeated_tail(&[], 0, 4), None);
+    }
+
+    #[test]
+    fn test_repetition_starts_at_first_repeat() {
+        let mut params = HallucinationParams::new();
+        params.max_repeats(Some(2));
+        let mut tracker = Tracker::new(params);
+        assert_eq!(tracker.push(&segment(0, 0, 100, " Hello there.")), None);
+        assert_eq!(tracker.push(&segment(1, 100, 200, " Thank you.")), None);
+        assert_eq!(tracker.push(&segment(2, 200, 300, " Thank you.")), None);
+        assert_eq!(
+            tracker.push(&segment(3, 300, 400, " Thank you!")),
+            Some((1, Hallucination::Repetition))
+        );
+    }
+
+    #[test]
+    fn test_compression_ratio_and_speech() {
+        let mut params = HallucinationParams::new();
+        params.max_repeats(None).speech([WhisperVadSegment {
+            start: 0.0,
+            end: 500.0,
+        }]);
+        let mut tracker = Tracker::new(params);
+        let looping = " la".repeat(40);
+        assert_eq!(
+            tracker.push(&segment(0, 0, 300, &looping)),
+            Some((0, Hallucination::HighCompressionRatio))
+        );
+        assert_eq!(tracker.push(&segment(1, 400, 600, " Over the edge.")), None);
+        assert_eq!(
+            tracker.push(&segment(2, 480, 900, " In the silence.")),
+            Some((2, Hallucination::NoSpeech))
+        );
+        assert_eq!(tracker.push(&segment(3, 900, 900, "  ")), None);
+    }
+
+    #[test]
+    fn test_checked_flags_whole_loop() {
+        let mut params = HallucinationParams::new();
+        params.max_repeats(Some(1));
+        let segments = [
+            segment(0, 0, 100, " Hello."),
+            segment(1, 100, 200, " Thank you."),
+            segment(2, 200, 300, " Thank you."),
+            segment(3, 300, 400, " Thank you."),
+        ];
+        let checked = checked(&segments, &params, 2);
+        let flags: Vec<_> = checked.iter().map(|s| s.hallucination).collect();
+        assert_eq!(
+            flags,
+            [
+                None,
+                Some(Hallucination::Repetition),
+                Some(Hallucination::Repetition),
+                Some(Hallucination::Repetition)
+            ]
+        );
+        assert!(checked.iter().all(|s| s.retries == 2));
+        assert_eq!(checked[1].text, " Thank you.");
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "test-with-tiny-model")]
+mod test_with_tiny_model {
+    use super::*;
+    use crate::{SamplingStrategy, WhisperContext, WhisperContextParameters};
+    const MODEL_PATH: &str = "./sys/whisper.cpp/models/ggml-tiny.en.bin";
+    const SAMPLE_PATH: &str = "./sys/whisper.cpp/samples/jfk.wav";
+
+    // These tests expect that the tiny.en model has been downloaded
+    // using the script `sys/whisper.cpp/models/download-ggml-model.sh tiny.en`
+
+    #[test]
+    fn test_clean_speech_is_not_retried() {
+        let ctx = WhisperContext::new_with_params(MODEL_PATH, WhisperContextParameters::default())
+            .expect("Download the ggml-tiny.en model using 'sys/whisper.cpp/models/download-ggml-model.sh tiny.en'");
+        let samples: Vec<i16> = hound::WavReader::open(SAMPLE_PATH)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(Result::unwrap)
+            .collect();
+        let mut audio = vec![0.0f32; samples.len()];
+        crate::convert_integer_to_float_audio(&samples, &mut audio).unwrap();
+
+        let mut state = ctx.create_state().unwrap();
+        let params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        let segments = state
+            .full_checked(params, &audio, &HallucinationParams::default())
+            .unwrap();
+        assert!(segments
+            .iter()
+            .all(|segment| segment.hallucination.is_none() && segment.retries == 0));
+        let text: String = segments
+            .iter()
+            .map(|segment| segment.text.as_str())
+            .collect();
+        assert!(text.contains("ask not what your country can do for you"));
+    }
+}
@@ -0,0 +1,379 @@
+use crate::{
+    FullParams, Language, WhisperError, WhisperState, WhisperVadContext, WhisperVadParams,
+    WhisperVadSegment,
+};
+
+/// Length in centiseconds of the audio the encoder sees at once.
+const WINDOW_CS: f32 = 3000.0;
+
+/// How [`WhisperState::lang_detect_speech`] picks the speech to detect the language of.
+#[derive(Clone)]
+pub struct LanguageDetectionParams {
+    /// How to find speech.
+    pub vad: WhisperVadParams,
+    /// Most speech regions to use, longest first. Defaults to 5.
+    pub max_regions: usize,
+    /// Shortest speech region to use, in milliseconds.
+    /// Shorter ones are only used if there is nothing longer. Defaults to 1000.
+    pub min_region_ms: u32,
+    /// The languages to choose from. Empty allows every language, which is the default.
+    pub allowed: Vec<Language>,
+    /// Threads to compute the spectrogram and run the encoder with. Defaults to 1.
+    pub threads: usize,
+}
+
+impl Default for LanguageDetectionParams {
+    fn default() -> Self {
+        Self {
+            vad: WhisperVadParams::default(),
+            max_regions: 5,
+            min_region_ms: 1000,
+            allowed: Vec::new(),
+            threads: 1,
+        }
+    }
+}
+
+impl LanguageDetectionParams {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn vad(&mut self, vad: WhisperVadParams) -> &mut Self {
+        self.vad = vad;
+        self
+    }
+    pub fn max_regions(&mut self, max_regions: usize) -> &mut Self {
+        self.max_regions = max_regions;
+        self
+    }
+    pub fn min_region_ms(&mut self, min_region_ms: u32) -> &mut Self {
+        self.min_region_ms = min_region_ms;
+        self
+    }
+    pub fn allowed(&mut self, allowed: &[Language]) -> &mut Self {
+        self.allowed = allowed.to_vec();
+        self
+    }
+    pub fn threads(&mut self, threads: usize) -> &mut Self {
+        self.threads = threads;
+        self
+    }
+}
+
+/// The language detected by [`WhisperState::lang_detect_speech`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanguageDetection {
+    /// The most likely language.
+    pub language: Language,
+    /// Probability of `language`, averaged over the regions weighted by their length.
+    pub probability: f32,
+    /// Part of the speech, by length, in regions that detected `language` on their own.
+    /// Low when the regions disagree, which makes the detection less trustworthy.
+    pub agreement: f32,
+    /// Every allowed language with its averaged probability, most likely first.
+    pub languages: Vec<(Language, f32)>,
+    /// The speech regions used, at most 30 seconds each.
+    pub regions: Vec<WhisperVadSegment>,
+}
+
+impl WhisperState {
+    /// Like [`WhisperState::lang_detect`], but only among `allowed` languages.
+    ///
+    /// The probabilities are renormalized to add up to 1 over the allowed languages.
+    /// Make sure to call [`Self::pcm_to_mel`] or [`Self::set_mel`] first.
+    ///
+    /// # Arguments
+    /// * `offset_ms`: The offset in milliseconds to use for the language detection.
+    /// * `threads`: How many threads to use. Must be at least 1, returns an error otherwise.
+    /// * `allowed`: The languages to choose from. Empty allows every language, [`Language::Auto`] is ignored.
+    /// * `top_k`: How many languages to return at most.
+    ///
+    /// # Returns
+    /// The `top_k` most likely languages with their probabilities, most likely first.
+    ///
+    /// # Errors
+    /// Whatever [`WhisperState::lang_detect`] returns.
+    pub fn lang_detect_among(
+        &self,
+        offset_ms: usize,
+        threads: usize,
+        allowed: &[Language],
+        top_k: usize,
+    ) -> Result<Vec<(Language, f32)>, WhisperError> {
+        let (_, probs) = self.lang_detect(offset_ms, threads)?;
+        Ok(rank_languages(&probs, allowed, top_k))
+    }
+
+    /// Detect the spoken language from several regions of speech in `audio`.
+    ///
+    /// [`WhisperState::lang_detect`] only listens to one 30 second window, which goes wrong
+    /// when that holds music, silence or a single word. This finds speech with `vad`,
+    /// detects the language of each of the longest regions on its own and averages the results,
+    /// weighing each region by its length. Audio without speech is detected from its start.
+    ///
+    /// The spectrogram in the state is replaced.
+    ///
+    /// # Arguments
+    /// * audio: raw PCM audio data, 32 bit floating point at a sample rate of 16 kHz, 1 channel.
+    /// * vad: finds the speech.
+    /// * params: which speech to use and which languages to choose from.
+    ///
+    /// # Errors
+    /// * [`WhisperError::NoSamples`] if `audio` is empty.
+    /// * Whatever [`WhisperVadContext::segments_from_samples`], [`WhisperState::pcm_to_mel`]
+    ///   or [`WhisperState::lang_detect`] return.
+    pub fn lang_detect_speech(
+        &mut self,
+        audio: &[f32],
+        vad: &mut WhisperVadContext,
+        params: &LanguageDetectionParams,
+    ) -> Result<LanguageDetection, WhisperError> {
+        if audio.is_empty() {
+            return Err(WhisperError::NoSamples);
+        }
+        let samples_per_cs = whisper_rs_sys::WHISPER_SAMPLE_RATE as f32 / 100.0;
+        let speech: Vec<WhisperVadSegment> =
+            vad.segments_from_samples(params.vad, audio)?.collect();
+        let mut regions = pick_regions(
+            &speech,
+            params.max_regions,
+            params.min_region_ms as f32 / 10.0,
+        );
+        if regions.is_empty() {
+            regions.push(WhisperVadSegment {
+                start: 0.0,
+                end: (audio.len() as f32 / samples_per_cs).min(WINDOW_CS),
+            });
+        }
+
+        let mut detections = Vec::with_capacity(regions.len());
+        for region in &regions {
+            let start = ((region.start * samples_per_cs) as usize).min(audio.len() - 1);
+            let end = ((region.end * samples_per_cs) as usize).clamp(start + 1, audio.len());
+            self.pcm_to_mel(&audio[start..end], params.threads)?;
+            let (_, probs) = self.lang_detect(0, params.threads)?;
+            detections.push((
+                region.end - region.start,
+                rank_languages(&probs, &params.allowed, usize::MAX),
+            ));
+        }
+        Ok(combine_detections(regions, &detections))
+    }
+
+    /// Settle on the most likely of the allowed languages of `params` before whisper.cpp runs,
+    /// see [`FullParams::set_allowed_languages`].
+    pub(super) fn force_allowed_language(
+        &mut self,
+        mut params: FullParams,
+        data: &[f32],
+    ) -> Result<FullParams, WhisperError> {
+        let Some(allowed) = params.restricted_detection() else {
+            return Ok(params);
+        };
+        let threads = params.fp.n_threads.max(1) as usize;
+        self.pcm_to_mel(data, threads)?;
+        let best =
+            self.lang_detect_among(params.fp.offset_ms.max(0) as usize, threads, allowed, 1)?;
+        if let Some((language, _)) = best.first() {
+            params.set_language(Some(language.code()));
+        }
+        Ok(params)
+    }
+}
+
+/// Sort the `allowed` languages by their probability in `probs`, renormalized over them, and keep `top_k`.
+pub(super) fn rank_languages(
+    probs: &[f32],
+    allowed: &[Language],
+    top_k: usize,
+) -> Vec<(Language, f32)> {
+    let mut languages: Vec<Language> = allowed
+        .iter()
+        .copied()
+        .filter(|&language| language != Language::Auto)
+        .collect();
+    if languages.is_empty() {
+        languages = Language::ALL.to_vec();
+    }
+    languages.sort();
+    languages.dedup();
+    let mut ranked: Vec<(Language, f32)> = languages
+        .into_iter()
+        .filter_map(|language| Some((language, *probs.get(language.id()? as usize)?)))
+        .collect();
+    let total: f32 = ranked.iter().map(|&(_, prob)| prob).sum();
+    if total > 0.0 {
+        for (_, prob) in &mut ranked {
+            *prob /= total;
+        }
+    }
+    ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+    ranked.truncate(top_k);
+    ranked
+}
+
+/// The longest `max_regions` regions of `speech` that last at least `min_len` centiseconds,
+/// or any length if none do, cut to one window and in order.
+fn pick_regions(
+    speech: &[WhisperVadSegment],
+    max_regions: usize,
+    min_len: f32,
+) -> Vec<WhisperVadSegment> {
+    let len = |region: &WhisperVadSegment| region.end - region.start;
+    let mut regions: Vec<WhisperVadSegment> = speech
+        .iter()
+        .copied()
+        .filter(|region| len(region) >= min_len)
+        .collect();
+    if regions.is_empty() {
+        regions = speech
+            .iter()
+            .copied()
+            .filter(|region| len(region) > 0.0)
+            .collect();
+    }
+    regions.sort_by(|a, b| len(b).total_cmp(&len(a)));
+    regions.truncate(max_regions);
+    for region in &mut regions {
+        region.end = region.end.min(region.start + WINDOW_CS);
+    }
+    regions.sort_by(|a, b| a.start.total_cmp(&b.start));
+    regions
+}
+
+/// Average the ranked languages detected in regions of the given lengths.
+fn combine_detections(
+    regions: Vec<WhisperVadSegment>,
+    detections: &[(f32, Vec<(Language, f32)>)],
+) -> LanguageDetection {
+    let total: f32 = detections.iter().map(|&(len, _)| len).sum::<f32>();
+    let weight = |len: f32| {
+        if total > 0.0 {
+            len / total
+        } else {
+            1.0 / detections.len() as f32
+        }
+    };
+    let mut probs = vec![None; Language::ALL.len()];
+    for (len, ranked) in detections {
+        for &(language, prob) in ranked {
+            if let Some(id) = language.id() {
+                *probs[id as usize].get_or_insert(0.0) += weight(*len) * prob;
+            }
+        }
+    }
+    let mut languages: Vec<(Language, f32)> = Language::ALL
+        .iter()
+        .zip(probs)
+        .filter_map(|(&language, prob)| Some((language, prob?)))
+        .collect();
+    languages.sort_by(|a, b| b.1.total_cmp(&a.1));
+    let (language, probability) = languages.first().copied().unwrap_or((Language::Auto, 0.0));
+    let agreement = detections
+        .iter()
+        .filter(|(_, ranked)| ranked.first().map(|&(best, _)| best) == Some(language))
+        .map(|&(len, _)| weight(len))
+        .sum();
+    LanguageDetection {
+        language,
+        probability,
+        agreement,
+        languages,
+        regions,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn probs(pairs: &[(Language, f32)]) -> Vec<f32> {
+        let mut probs = vec![0.0; Language::ALL.len()];
+        for &(language, prob) in pairs {
+            probs[language.id().unwrap() as usize] = prob;
+        }
+        probs
+    }
+
+    #[test]
+    fn test_rank_renormalizes_over_allowed() {
+        let probs = probs(&[
+            (Language::English, 0.1),
+            (Language::German, 0.3),
+            (Language::Dutch, 0.5),
+            (Language::French, 0.1),
+        ]);
+        let ranked = rank_languages(
+            &probs,
+            &[Language::English, Language::French, Language::German],
+            5,
+        );
+        let languages: Vec<_> = ranked.iter().map(|&(language, _)| language).collect();
+        assert_eq!(
+            languages,
+            [Language::German, Language::English, Language::French]
+        );
+        assert!((ranked[0].1 - 0.6).abs() < 1e-6);
+        assert!((ranked[1].1 - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_rank_top_k_and_everything_allowed() {
+        let probs = probs(&[(Language::Dutch, 0.7), (Language::German, 0.2)]);
+        let ranked = rank_languages(&probs, &[], 2);
+        assert_eq!(ranked.len(), 2);
+        assert_eq!(ranked[0].0, Language::Dutch);
+        assert_eq!(ranked[1].0, Language::German);
+
+        // auto and duplicates are ignored
+        let ranked = rank_languages(
+            &probs,
+            &[Language::Auto, Language::German, Language::German],
+            3,
+        );
+        assert_eq!(ranked, [(Language::German, 1.0)]);
+    }
+
+    fn region(start: f32, end: f32) -> WhisperVadSegment {
+        WhisperVadSegment { start, end }
+    }
+
+    #[test]
+    fn test_pick_regions() {
+        let speech = [
+            region(0.0, 50.0),
+            region(100.0, 400.0),
+            region(500.0, 4000.0),
+            region(4100.0, 4300.0),
+        ];
+        let regions = pick_regions(&speech, 2, 100.0);
+        assert_eq!(regions, [region(100.0, 400.0), region(500.0, 3500.0)]);
+
+        // only short regions, so they are used anyway
+        let regions = pick_regions(&[region(0.0, 50.0), region(60.0, 70.0)], 5, 100.0);
+        assert_eq!(regions, [region(0.0, 50.0), region(60.0, 70.0)]);
+        assert!(pick_regions(&[], 5, 100.0).is_empty());
+    }
+
+    #[test]
+    fn test_combine_weighs_by_length() {
+        let detections = [
+            (
+                300.0,
+                vec![(Language::German, 0.8), (Language::English, 0.2)],
+            ),
+            (
+                100.0,
+                vec![(Language::English, 0.9), (Language::German, 0.1)],
+            ),
+        ];
+        let regions = vec![region(0.0, 300.0), region(400.0, 500.0)];
+        let detection = combine_detections(regions.clone(), &detections);
+        assert_eq!(detection.language, Language::German);
+        assert!((detection.probability - 0.625).abs() < 1e-6);
+        assert!((detection.agreement - 0.75).abs() < 1e-6);
+        assert_eq!(detection.languages.len(), 2);
+        assert_eq!(detection.languages[1].0, Language::English);
+        assert_eq!(detection.regions, regions);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::{compression_ratio, WhisperError, WhisperSegment, WhisperState};
+
+/// Quality metrics of a segment, see [`WhisperSegment::metrics`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SegmentMetrics {
+    /// Number of text tokens in the segment.
+    pub n_tokens: usize,
+    /// Average log probability of the text tokens. 0 for a segment without text tokens.
+    pub avg_logprob: f32,
+    /// Lowest log probability of a text token. 0 for a segment without text tokens.
+    pub min_logprob: f32,
+    /// How well the text compresses, see [`compression_ratio`].
+    pub compression_ratio: f32,
+    /// See [`WhisperSegment::no_speech_probability`].
+    pub no_speech_probability: f32,
+    /// Text tokens per second of audio.
+    pub tokens_per_second: f32,
+}
+
+impl WhisperSegment<'_> {
+    /// Compute quality metrics for this segment.
+    ///
+    /// Only text tokens count, timestamps and other special tokens are left out.
+    /// A low average log probability means the model was unsure of the text,
+    /// and a high compression ratio that the text repeats itself,
+    /// which OpenAI's Whisper treats as signs of a failed decode (below -1.0 and above 2.4).
+    ///
+    /// # Errors
+    /// Whatever [`WhisperSegment::to_bytes`] returns.
+    pub fn metrics(&self) -> Result<SegmentMetrics, WhisperError> {
+        combined_metrics(std::slice::from_ref(self))
+    }
+}
+
+/// [`SegmentMetrics`] of consecutive segments taken together,
+/// with the no-speech probability of the first one.
+pub(super) fn combined_metrics(
+    segments: &[WhisperSegment],
+) -> Result<SegmentMetrics, WhisperError> {
+    let mut logprobs = Vec::new();
+    let mut text = Vec::new();
+    for segment in segments {
+        let eot = segment.get_state().ctx.token_eot();
+        logprobs.extend(
+            (0..segment.n_tokens())
+                .filter_map(|i| segment.get_token(i))
+                .filter(|token| token.token_id() < eot)
+                .map(|token| token.token_data().plog),
+        );
+        text.extend_from_slice(segment.to_bytes()?);
+    }
+    let seconds = match (segments.first(), segments.last()) {
+        (Some(first), Some(last)) => {
+            (last.end_timestamp() - first.start_timestamp()) as f32 / 100.0
+        }
+        _ => 0.0,
+    };
+    Ok(SegmentMetrics {
+        n_tokens: logprobs.len(),
+        avg_logprob: logprobs.iter().sum::<f32>() / logprobs.len().max(1) as f32,
+        min_logprob: logprobs.iter().copied().reduce(f32::min).unwrap_or(0.0),
+        compression_ratio: compression_ratio(&text),
+        no_speech_probability: segments
+            .first()
+            .map_or(0.0, |segment| segment.no_speech_probability()),
+        tokens_per_second: logprobs.len() as f32 / seconds.max(0.01),
+    })
+}
+
+/// Thresholds for dropping low quality segments, see [`WhisperState::filtered_segments`].
+///
+/// Every threshold is off by default.
+/// OpenAI's Whisper uses an average log probability of -1.0 and a compression ratio of 2.4
+/// to decide a decode failed, and a no-speech probability of 0.6 to decide a window is silent.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SegmentFilter {
+    pub min_avg_logprob: Option<f32>,
+    pub min_token_logprob: Option<f32>,
+    pub max_compression_ratio: Option<f32>,
+    pub max_no_speech_probability: Option<f32>,
+    pub min_tokens_per_second: Option<f32>,
+    pub max_tokens_per_second: Option<f32>,
+}
+
+impl SegmentFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn min_avg_logprob(&mut self, min_avg_logprob: f32) -> &mut Self {
+        self.min_avg_logprob = Some(min_avg_logprob);
+        self
+    }
+    pub fn min_token_logprob(&mut self, min_token_logprob: f32) -> &mut Self {
+        self.min_token_logprob = Some(min_token_logprob);
+        self
+    }
+    pub fn max_compression_ratio(&mut self, max_compression_ratio: f32) -> &mut Self {
+        self.max_compression_ratio = Some(max_compression_ratio);
+        self
+    }
+    pub fn max_no_speech_probability(&mut self, max_no_speech_probability: f32) -> &mut Self {
+        self.max_no_speech_probability = Some(max_no_speech_probability);
+        self
+    }
+    pub fn min_tokens_per_second(&mut self, min_tokens_per_second: f32) -> &mut Self {
+        self.min_tokens_per_second = Some(min_tokens_per_second);
+        self
+    }
+    pub fn max_tokens_per_second(&mut self, max_tokens_per_second: f32) -> &mut Self {
+        self.max_tokens_per_second = Some(max_tokens_per_second);
+        self
+    }
+
+    /// Whether a segment with `metrics` meets every threshold that is set.
+    pub fn accepts(&self, metrics: &SegmentMetrics) -> bool {
+        let at_least = |threshold: Option<f32>, value: f32| threshold.is_none_or(|t| value >= t);
+        let at_most = |threshold: Option<f32>, value: f32| threshold.is_none_or(|t| value <= t);
+        at_least(self.min_avg_logprob, metrics.avg_logprob)
+            && at_least(self.min_token_logprob, metrics.min_logprob)
+            && at_most(self.max_compression_ratio, metrics.compression_ratio)
+            && at_most(
+                self.max_no_speech_probability,
+                metrics.no_speech_probability,
+            )
+            && at_least(self.min_tokens_per_second, metrics.tokens_per_second)
+            && at_most(self.max_tokens_per_second, metrics.tokens_per_second)
+    }
+}
+
+impl WhisperState {
+    /// Iterate over the segments whose [`WhisperSegment::metrics`] pass `filter`.
+    ///
+    /// Segments whose metrics cannot be computed are dropped as well.
+    pub fn filtered_segments<'a>(
+        &'a self,
+        filter: &'a SegmentFilter,
+    ) -> impl Iterator<Item = WhisperSegment<'a>> + 'a {
+        self.as_iter().filter(|segment| {
+            segment
+                .metrics()
+                .is_ok_and(|metrics| filter.accepts(&metrics))
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn metrics(avg_logprob: f32, compression_ratio: f32) -> SegmentMetrics {
+        SegmentMetrics {
+            n_tokens: 10,
+            avg_logprob,
+            min_logprob: avg_logprob * 2.0,
+            compression_ratio,
+            no_speech_probability: 0.1,
+            tokens_per_second: 3.0,
+        }
+    }
+
+    #[test]
+    fn test_filter_defaults_accept_everything() {
+        assert!(SegmentFilter::new().accepts(&metrics(-5.0, 10.0)));
+    }
+
+    #[test]
+    fn test_filter_thresholds() {
+        let mut filter = SegmentFilter::new();
+        filter.min_avg_logprob(-1.0).max_compression_ratio(2.4);
+        assert!(filter.accepts(&metrics(-0.5, 1.2)));
+        assert!(!filter.accepts(&metrics(-1.5, 1.2)));
+        assert!(!filter.accepts(&metrics(-0.5, 3.0)));
+
+        filter.min_token_logprob(-0.8);
+        assert!(!filter.accepts(&metrics(-0.5, 1.2)));
+
+        let mut filter = SegmentFilter::new();
+        filter.max_tokens_per_second(2.0);
+        assert!(!filter.accepts(&metrics(-0.5, 1.2)));
+        filter
+            .max_tokens_per_second(5.0)
+            .max_no_speech_probability(0.05);
+        assert!(!filter.accepts(&metrics(-0.5, 1.2)));
+    }
+}
@@ -1,21 +1,50 @@
 use std::ffi::c_int;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use crate::{FullParams, WhisperError, WhisperInnerContext, WhisperTokenId};
 
+mod align;
+mod bilingual;
+mod code_switching;
+mod decoder;
+mod fallback;
+mod hallucination;
 mod iterator;
+mod language;
+mod metrics;
 mod segment;
 mod token;
+mod vad_map;
 
+pub use align::{AlignedSegment, AlignedWord};
+pub use bilingual::BilingualSegment;
+pub use code_switching::LanguageSegment;
+pub use decoder::{Decoder, DecoderParams, Greedy, Hypothesis, Nucleus, Sampler, TopK};
+pub use fallback::{
+    FallbackCandidate, FallbackDecision, FallbackPolicy, ThresholdFallback, TranscribedSegment,
+    TranscribedWindow,
+};
+pub use hallucination::{CheckedSegment, Hallucination, HallucinationParams};
 pub use iterator::WhisperStateSegmentIterator;
+pub use language::{LanguageDetection, LanguageDetectionParams};
+pub use metrics::{SegmentFilter, SegmentMetrics};
 pub use segment::WhisperSegment;
-pub use token::WhisperToken;
+pub use token::{TokenTimeSource, WhisperToken};
+use vad_map::VadCache;
+pub use vad_map::{VadMappedSegment, VadSegmentMap};
 
 /// Rustified pointer to a Whisper state.
 #[derive(Debug)]
 pub struct WhisperState {
     ctx: Arc<WhisperInnerContext>,
     ptr: *mut whisper_rs_sys::whisper_state,
+    vad_map: Option<VadSegmentMap>,
+    /// The VAD model [`WhisperState::full`] last loaded to compute `vad_map`.
+    vad: Option<VadCache>,
+    /// Tokens in the last call to [`WhisperState::decode`], 0 if anything else used the decoder since.
+    /// whisper.cpp only computes the logits of the last one, at that row.
+    n_decoded: AtomicUsize,
 }
 
 unsafe impl Send for WhisperState {}
@@ -38,7 +67,13 @@ impl WhisperState {
         ctx: Arc<WhisperInnerContext>,
         ptr: *mut whisper_rs_sys::whisper_state,
     ) -> Self {
-        Self { ctx, ptr }
+        Self {
+            ctx,
+            ptr,
+            vad_map: None,
+            vad: None,
+            n_decoded: AtomicUsize::new(0),
+        }
     }
 
     /// Convert raw PCM audio (floating point 32 bit) to log mel spectrogram.
@@ -179,6 +214,8 @@ impl WhisperState {
                 threads as c_int,
             )
         };
+        self.n_decoded
+            .store(if ret == 0 { tokens.len() } else { 0 }, Ordering::Relaxed);
         if ret == -1 {
             Err(WhisperError::UnableToCalculateEvaluation)
         } else if ret == 0 {
@@ -212,6 +249,8 @@ impl WhisperState {
         }
 
         let mut lang_probs: Vec<f32> = vec![0.0; crate::standalone::get_lang_max_id() as usize + 1];
+        // this decodes a single token of its own
+        self.n_decoded.store(0, Ordering::Relaxed);
         let ret = unsafe {
             whisper_rs_sys::whisper_lang_auto_detect_with_state(
                 self.ctx.ctx,
@@ -229,8 +268,11 @@ impl WhisperState {
     }
 
     // logit functions
-    /// Gets logits obtained from the last call to [WhisperState::decode].
-    /// As of whisper.cpp 1.4.1, only a single row of logits is available, corresponding to the last token in the input.
+    /// Gets the logits for the token after the last call to [WhisperState::decode].
+    ///
+    /// whisper.cpp keeps a row of logits per decoded token, but only computes the row of the last one.
+    /// When several tokens are decoded at once, that is row `tokens.len() - 1`,
+    /// and the rows before it hold whatever an earlier call left there. This returns the computed row.
     ///
     /// # Returns
     /// A slice of logits with length equal to n_vocab.
@@ -242,8 +284,9 @@ impl WhisperState {
         if ret.is_null() {
             return Err(WhisperError::NullPointer);
         }
-        let n_vocab = self.n_vocab();
-        Ok(unsafe { std::slice::from_raw_parts(ret, n_vocab as usize) })
+        let n_vocab = self.n_vocab() as usize;
+        let row = self.n_decoded.load(Ordering::Relaxed).saturating_sub(1);
+        Ok(unsafe { std::slice::from_raw_parts(ret.add(row * n_vocab), n_vocab) })
     }
 
     // model attributes
@@ -274,6 +317,13 @@ impl WhisperState {
     ///
     /// This is usually the only function you need to call as an end user.
     ///
+    /// If [`FullParams::set_allowed_languages`] restricts language detection,
+    /// the language is detected among those first and the audio transcribed in the most likely one.
+    ///
+    /// With [`FullParams::enable_vad`], whisper.cpp only transcribes the speech.
+    /// The speech is found a second time to record where it came from, see [`WhisperState::vad_map`],
+    /// and segment and token times are reported in the time of `data` either way.
+    ///
     /// # Arguments
     /// * params: [crate::FullParams] struct.
     /// * pcm: raw PCM audio data, 32 bit floating point at a sample rate of 16 kHz, 1 channel.
@@ -294,6 +344,13 @@ impl WhisperState {
             // can randomly trigger segmentation faults if we don't check this
             return Err(WhisperError::NoSamples);
         }
+        let params = self.force_allowed_language(params, data)?;
+        self.n_decoded.store(0, Ordering::Relaxed);
+        self.vad_map = if params.fp.vad {
+            Some(self.compute_vad_map(&params, data)?)
+        } else {
+            None
+        };
 
         let ret = unsafe {
             whisper_rs_sys::whisper_full_with_state(
@@ -2,6 +2,19 @@ use crate::{WhisperError, WhisperSegment, WhisperTokenData, WhisperTokenId};
 use std::borrow::Cow;
 use std::ffi::{c_int, CStr};
 use std::fmt;
+use std::time::Duration;
+
+/// Where the time returned by [`WhisperToken::time`] comes from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenTimeSource {
+    /// Dynamic time warping over the cross-attention of the alignment heads,
+    /// see [`WhisperContextParameters::dtw_parameters`](crate::WhisperContextParameters::dtw_parameters).
+    /// The more accurate of the two.
+    Dtw,
+    /// whisper.cpp's heuristic based on the timestamp token probabilities,
+    /// see [`FullParams::set_token_timestamps`](crate::FullParams::set_token_timestamps).
+    TokenTimestamps,
+}
 
 pub struct WhisperToken<'a, 'b: 'a> {
     segment: &'a WhisperSegment<'b>,
@@ -34,19 +47,27 @@ impl<'a, 'b> WhisperToken<'a, 'b> {
 
     /// Get token data for this token in its segment.
     ///
+    /// `t0`, `t1` and `t_dtw` are in the time of the audio passed to
+    /// [`WhisperState::full`](crate::WhisperState::full), even if VAD cut out silence before transcribing,
+    /// like [`WhisperToken::start_time`], [`WhisperToken::end_time`] and [`WhisperToken::dtw_time`].
+    ///
     /// # Returns
     /// [`WhisperTokenData`]
     ///
     /// # C++ equivalent
     /// `whisper_token_data whisper_full_get_token_data(struct whisper_context * ctx, int i_segment, int i_token)`
     pub fn token_data(&self) -> WhisperTokenData {
-        unsafe {
+        let mut data = unsafe {
             whisper_rs_sys::whisper_full_get_token_data_from_state(
                 self.segment.get_state().ptr,
                 self.segment.segment_index(),
                 self.token_idx,
             )
-        }
+        };
+        data.t0 = self.original_time(data.t0);
+        data.t1 = self.original_time(data.t1);
+        data.t_dtw = self.original_time(data.t_dtw);
+        data
     }
 
     /// Get the probability of this token in its segment.
@@ -66,6 +87,58 @@ impl<'a, 'b> WhisperToken<'a, 'b> {
         }
     }
 
+    /// Get when this token starts, from the token-level timestamps.
+    ///
+    /// # Returns
+    /// `None` unless [`FullParams::set_token_timestamps`](crate::FullParams::set_token_timestamps) was on.
+    pub fn start_time(&self) -> Option<Duration> {
+        centiseconds(self.token_data().t0)
+    }
+
+    /// Get when this token ends, from the token-level timestamps.
+    ///
+    /// # Returns
+    /// `None` unless [`FullParams::set_token_timestamps`](crate::FullParams::set_token_timestamps) was on.
+    pub fn end_time(&self) -> Option<Duration> {
+        centiseconds(self.token_data().t1)
+    }
+
+    /// Get when this token is spoken according to dynamic time warping.
+    ///
+    /// This is a single point in time rather than a span, roughly where the token starts.
+    ///
+    /// # Returns
+    /// `None` unless the context was created with
+    /// [`WhisperContextParameters::dtw_parameters`](crate::WhisperContextParameters::dtw_parameters)
+    /// set to something other than [`DtwMode::None`](crate::DtwMode::None).
+    pub fn dtw_time(&self) -> Option<Duration> {
+        centiseconds(self.token_data().t_dtw)
+    }
+
+    /// Get the best available time for this token and where it comes from.
+    ///
+    /// Like all token times, it is in the time of the audio passed to [`WhisperState::full`](crate::WhisperState::full),
+    /// even if VAD cut out silence before transcribing.
+    ///
+    /// Prefers [`WhisperToken::dtw_time`] and falls back to [`WhisperToken::start_time`].
+    ///
+    /// # Returns
+    /// `None` if neither was computed.
+    pub fn time(&self) -> Option<(Duration, TokenTimeSource)> {
+        let data = self.token_data();
+        centiseconds(data.t_dtw)
+            .map(|time| (time, TokenTimeSource::Dtw))
+            .or_else(|| centiseconds(data.t0).map(|time| (time, TokenTimeSource::TokenTimestamps)))
+    }
+
+    /// Map a time from whisper.cpp into the original audio if VAD was on, keeping -1 for none.
+    fn original_time(&self, time: i64) -> i64 {
+        match time {
+            0.. => self.segment.get_state().to_original_time(time),
+            _ => time,
+        }
+    }
+
     fn to_raw_cstr(&self) -> Result<&'b CStr, WhisperError> {
         let ret = unsafe {
             whisper_rs_sys::whisper_full_get_token_text_from_state(
@@ -151,3 +224,63 @@ impl fmt::Debug for WhisperToken<'_, '_> {
             .finish()
     }
 }
+
+/// A time in centiseconds, where whisper.cpp uses -1 for times it did not compute.
+fn centiseconds(time: i64) -> Option<Duration> {
+    (time >= 0).then(|| Duration::from_millis(time as u64 * 10))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_centiseconds() {
+        assert_eq!(centiseconds(-1), None);
+        assert_eq!(centiseconds(0), Some(Duration::ZERO));
+        assert_eq!(centiseconds(1234), Some(Duration::from_millis(12_340)));
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "test-with-tiny-model")]
+mod test_with_tiny_model {
+    use super::*;
+    use crate::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+    const MODEL_PATH: &str = "./sys/whisper.cpp/models/ggml-tiny.en.bin";
+    const SAMPLE_PATH: &str = "./sys/whisper.cpp/samples/jfk.wav";
+
+    // These tests expect that the tiny.en model has been downloaded
+    // using the script `sys/whisper.cpp/models/download-ggml-model.sh tiny.en`
+
+    #[test]
+    fn test_token_times_need_token_timestamps() {
+        let ctx = WhisperContext::new_with_params(MODEL_PATH, WhisperContextParameters::default())
+            .expect("Download the ggml-tiny.en model using 'sys/whisper.cpp/models/download-ggml-model.sh tiny.en'");
+        let samples: Vec<i16> = hound::WavReader::open(SAMPLE_PATH)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(Result::unwrap)
+            .collect();
+        let mut audio = vec![0.0f32; samples.len()];
+        crate::convert_integer_to_float_audio(&samples, &mut audio).unwrap();
+        let mut state = ctx.create_state().unwrap();
+
+        for token_timestamps in [false, true] {
+            let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+            params.set_token_timestamps(token_timestamps);
+            state.full(params, &audio).unwrap();
+            let segment = state.get_segment(0).unwrap();
+            let token = segment.get_token(1).unwrap();
+            assert_eq!(token.dtw_time(), None);
+            assert_eq!(token.start_time().is_some(), token_timestamps);
+            assert_eq!(token.end_time().is_some(), token_timestamps);
+            match token.time() {
+                Some((time, TokenTimeSource::TokenTimestamps)) => {
+                    assert_eq!(Some(time), token.start_time())
+                }
+                time => assert!(!token_timestamps && time.is_none()),
+            }
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::ffi::CString;
+
+use crate::{
+    FullParams, WhisperError, WhisperState, WhisperVadContext, WhisperVadContextParams,
+    WhisperVadParams, WhisperVadSegment,
+};
+
+/// Silence whisper.cpp puts between speech segments, in seconds.
+const SILENCE_SECONDS: f64 = 0.1;
+
+/// A speech segment that VAD kept in [`WhisperState::full`], and where it ended up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VadMappedSegment {
+    /// Start in the original audio, in centiseconds (10s of milliseconds).
+    pub original_start: i64,
+    /// End in the original audio, in centiseconds (10s of milliseconds).
+    pub original_end: i64,
+    /// Start in the audio whisper.cpp transcribed, in centiseconds (10s of milliseconds).
+    pub processed_start: i64,
+    /// End in the audio whisper.cpp transcribed, in centiseconds (10s of milliseconds).
+    /// Includes the overlap into the next segment.
+    pub processed_end: i64,
+}
+
+/// How the audio whisper.cpp transcribed with VAD on maps back to the original audio.
+///
+/// With [`FullParams::enable_vad`], whisper.cpp cuts the speech out of the audio,
+/// joins it with a short silence in between and only transcribes that.
+/// Times inside a segment are mapped linearly onto the original segment,
+/// and times in the silence between two segments onto the silence between them,
+/// the same way whisper.cpp maps segment timestamps.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VadSegmentMap {
+    segments: Vec<VadMappedSegment>,
+    /// (processed, original) pairs to interpolate between, in order.
+    points: Vec<(i64, i64)>,
+}
+
+/// A loaded VAD model and the path it was loaded from.
+pub(super) struct VadCache {
+    model_path: CString,
+    vad: WhisperVadContext,
+}
+
+impl std::fmt::Debug for VadCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("VadCache")
+            .field("model_path", &self.model_path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl VadSegmentMap {
+    /// Lay out `speech` the way whisper.cpp does for `n_samples` of audio.
+    fn new(speech: &[WhisperVadSegment], samples_overlap: f32, n_samples: usize) -> Self {
+        let sample_rate = whisper_rs_sys::WHISPER_SAMPLE_RATE as f64;
+        let to_samples = |cs: f32| (cs as f64 / 100.0 * sample_rate + 0.5) as usize;
+        let to_cs = |samples: usize| (samples as f64 / sample_rate * 100.0) as i64;
+        let overlap = (samples_overlap as f64 * sample_rate) as usize;
+        let silence = (SILENCE_SECONDS * sample_rate) as usize;
+
+        let mut map = Self::default();
+        let mut offset = 0;
+        for (i, region) in speech.iter().enumerate() {
+            let last = i + 1 == speech.len();
+            let start = to_samples(region.start);
+            let mut end = to_samples(region.end);
+            if !last {
+                end += overlap;
+            }
+            let len = end.min(n_samples.saturating_sub(1)).saturating_sub(start);
+            // whisper.cpp leaves out empty segments and the silence after them
+            if len == 0 {
+                continue;
+            }
+            let segment = VadMappedSegment {
+                original_start: region.start as i64,
+                original_end: region.end as i64,
+                processed_start: to_cs(offset),
+                processed_end: to_cs(offset + len),
+            };
+            map.points
+                .push((segment.processed_start, segment.original_start));
+            map.points
+                .push((segment.processed_end, segment.original_end));
+            map.segments.push(segment);
+            offset += len;
+            if !last {
+                offset += silence;
+            }
+        }
+        map.points.dedup_by_key(|&mut (processed, _)| processed);
+        map
+    }
+
+    /// The speech segments, in order.
+    pub fn segments(&self) -> &[VadMappedSegment] {
+        &self.segments
+    }
+
+    /// Map a time in the transcribed audio to the original audio, both in centiseconds.
+    ///
+    /// Times before the first segment map to its start and times after the last one to its end.
+    /// Without segments, times are returned as they are.
+    pub fn to_original(&self, processed: i64) -> i64 {
+        let (Some(&first), Some(&last)) = (self.points.first(), self.points.last()) else {
+            return processed;
+        };
+        if processed <= first.0 {
+            return first.1;
+        }
+        if processed >= last.0 {
+            return last.1;
+        }
+        let i = self.points.partition_point(|&(time, _)| time <= processed);
+        let ((p0, o0), (p1, o1)) = (self.points[i - 1], self.points[i]);
+        o0 + (processed - p0) * (o1 - o0) / (p1 - p0)
+    }
+}
+
+impl WhisperState {
+    /// The map from the audio the last [`WhisperState::full`] transcribed to the original audio,
+    /// or `None` if VAD was off.
+    ///
+    /// Segment and token times are already reported in original time, this is for checking
+    /// what was cut out and mapping times of your own.
+    pub fn vad_map(&self) -> Option<&VadSegmentMap> {
+        self.vad_map.as_ref()
+    }
+
+    /// Map a time reported by whisper.cpp to the original audio, if VAD moved it.
+    pub(crate) fn to_original_time(&self, processed: i64) -> i64 {
+        self.vad_map
+            .as_ref()
+            .map_or(processed, |map| map.to_original(processed))
+    }
+
+    /// Find the speech whisper.cpp is about to cut out of `data`, by running the same VAD model
+    /// with the same parameters.
+    ///
+    /// The model is loaded once and kept on the state until another model is asked for.
+    pub(super) fn compute_vad_map(
+        &mut self,
+        params: &FullParams,
+        data: &[f32],
+    ) -> Result<VadSegmentMap, WhisperError> {
+        let model_path = params.vad_model_path().ok_or(WhisperError::NullPointer)?;
+        let cached = match self.vad.take() {
+            Some(cached) if cached.model_path.as_c_str() == model_path => cached,
+            _ => {
+                let mut context_params = WhisperVadContextParams::new();
+                context_params.set_n_threads(params.fp.n_threads.max(1));
+                VadCache {
+                    model_path: model_path.to_owned(),
+                    vad: WhisperVadContext::new(model_path.to_str()?, context_params)?,
+                }
+            }
+        };
+        let vad = &mut self.vad.insert(cached).vad;
+        let vad_params = WhisperVadParams::from_inner(params.fp.vad_params);
+        let speech: Vec<WhisperVadSegment> = vad.segments_from_samples(vad_params, data)?.collect();
+        Ok(VadSegmentMap::new(
+            &speech,
+            params.fp.vad_params.samples_overlap,
+            data.len(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn region(start: f32, end: f32) -> WhisperVadSegment {
+        WhisperVadSegment { start, end }
+    }
+
+    // three seconds of speech in five minutes, separated by long silences
+    fn map() -> VadSegmentMap {
+        let speech = [
+            region(100.0, 300.0),
+            region(6000.0, 6200.0),
+            region(30000.0, 30100.0),
+        ];
+        VadSegmentMap::new(&speech, 0.1, 302 * 16000)
+    }
+
+    #[test]
+    fn test_layout_matches_whisper_cpp() {
+        let map = map();
+        let processed: Vec<_> = map
+            .segments()
+            .iter()
+            .map(|segment| (segment.processed_start, segment.processed_end))
+            .collect();
+        // 10 cs of overlap after all but the last segment, then 10 cs of silence
+        assert_eq!(processed, [(0, 210), (220, 430), (440, 540)]);
+        assert_eq!(map.segments()[1].original_start, 6000);
+        assert_eq!(map.segments()[1].original_end, 6200);
+    }
+
+    #[test]
+    fn test_to_original_across_long_silences() {
+        let map = map();
+        assert_eq!(map.to_original(0), 100);
+        assert_eq!(map.to_original(210), 300);
+        assert_eq!(map.to_original(220), 6000);
+        assert_eq!(map.to_original(325), 6100);
+        assert_eq!(map.to_original(440), 30000);
+        assert_eq!(map.to_original(490), 30050);
+        // the silence in between maps onto the silence in between
+        let gap = map.to_original(435);
+        assert!(6200 < gap && gap < 30000);
+        // clamped at the ends
+        assert_eq!(map.to_original(-5), 100);
+        assert_eq!(map.to_original(10_000), 30100);
+        // times never go backwards
+        let times: Vec<_> = (0..600).map(|time| map.to_original(time)).collect();
+        assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn test_skips_empty_segments() {
+        // the last region starts where the audio ends, so nothing of it is left;
+        // an empty region before another one still gets its overlap
+        let speech = [
+            region(100.0, 300.0),
+            region(400.0, 400.0),
+            region(500.0, 600.0),
+            region(1000.0, 1100.0),
+        ];
+        let map = VadSegmentMap::new(&speech, 0.1, 10 * 16000);
+        let processed: Vec<_> = map
+            .segments()
+            .iter()
+            .map(|segment| (segment.original_start, segment.processed_start))
+            .collect();
+        assert_eq!(processed, [(100, 0), (400, 220), (500, 240)]);
+        assert_eq!(map.to_original(10_000), 600);
+    }
+
+    #[test]
+    fn test_empty_map_is_identity() {
+        let map = VadSegmentMap::new(&[], 0.1, 16000);
+        assert!(map.segments().is_empty());
+        assert_eq!(map.to_original(1234), 1234);
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "test-with-tiny-model")]
+mod test_with_tiny_model {
+    use crate::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+    const MODEL_PATH: &str = "./sys/whisper.cpp/models/ggml-tiny.en.bin";
+    const VAD_MODEL_PATH: &str = "./sys/whisper.cpp/models/for-tests-silero-v5.1.2-ggml.bin";
+    const SAMPLE_PATH: &str = "./sys/whisper.cpp/samples/jfk.wav";
+
+    // These tests expect that the tiny.en model has been downloaded
+    // using the script `sys/whisper.cpp/models/download-ggml-model.sh tiny.en`
+
+    #[test]
+    fn test_times_are_in_original_audio() {
+        let ctx = WhisperContext::new_with_params(MODEL_PATH, WhisperContextParameters::default())
+            .expect("Download the ggml-tiny.en model using 'sys/whisper.cpp/models/download-ggml-model.sh tiny.en'");
+        let samples: Vec<i16> = hound::WavReader::open(SAMPLE_PATH)
+            .unwrap()
+            .into_samples::<i16>()
+            .map(Result::unwrap)
+            .collect();
+        let mut speech = vec![0.0f32; samples.len()];
+        crate::convert_integer_to_float_audio(&samples, &mut speech).unwrap();
+        // 20 seconds of silence, the speech, another 40 seconds of silence and the speech again
+        let silence = |seconds: usize| vec![0.0f32; seconds * 16000];
+        let audio = [silence(20), speech.clone(), silence(40), speech].concat();
+        let second_start = (60 * 100 + samples.len() * 100 / 16000) as i64;
+
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_token_timestamps(true);
+        params.set_vad_model_path(Some(VAD_MODEL_PATH));
+        params.enable_vad(true);
+        let mut state = ctx.create_state().unwrap();
+        state.full(params, &audio).unwrap();
+
+        let map = state.vad_map().unwrap();
+        assert!(map.segments().len() >= 2);
+        assert!(map.segments()[0].original_start >= 1900);
+        for segment in state.as_iter() {
+            // nothing was said in the silences
+            let start = segment.start_timestamp();
+            assert!(start >= 1900);
+            assert!(start < 3100 || start >= second_start - 100);
+            for token in (0..segment.n_tokens()).filter_map(|i| segment.get_token(i)) {
+                let time = token.start_time().unwrap().as_millis() as i64 / 10;
+                assert_eq!(token.token_data().t0, time);
+                assert!(time >= start - 100 && time <= segment.end_timestamp() + 100);
+            }
+        }
+        assert!(state
+            .as_iter()
+            .any(|segment| segment.start_timestamp() >= second_start - 100));
+
+        let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+        params.set_token_timestamps(true);
+        state.full(params, &audio).unwrap();
+        assert!(state.vad_map().is_none());
+    }
+}
@@ -0,0 +1,492 @@
+use crate::AlignedWord;
+use std::fmt;
+use std::time::Duration;
+
+/// How many words of the transcript past the last matched cue are searched for the next one.
+const SEARCH_WORDS: usize = 400;
+/// The share of a cue's words that have to be found in the transcript for it to count as matched.
+const MIN_MATCH: f32 = 0.5;
+
+/// One cue of a subtitle file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubtitleCue {
+    pub start: Duration,
+    pub end: Duration,
+    /// The text of the cue, lines separated by `\n`.
+    pub text: String,
+}
+
+/// A syntax error in an SRT file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubtitleParseError {
+    /// The line the error occurred on, starting at 1.
+    pub line: usize,
+    /// What went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for SubtitleParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "SRT syntax error at line {}: {}",
+            self.line, self.message
+        )
+    }
+}
+
+impl std::error::Error for SubtitleParseError {}
+
+/// Parse the cues of an SRT file.
+///
+/// Cue numbers are optional and ignored, as are positions after the end time.
+/// Both `,` and `.` are accepted before the milliseconds.
+///
+/// # Errors
+/// A [`SubtitleParseError`] with the line of the first cue that could not be read.
+pub fn parse_srt(src: &str) -> Result<Vec<SubtitleCue>, SubtitleParseError> {
+    let src = src.strip_prefix('\u{feff}').unwrap_or(src);
+    let mut lines = src.lines().enumerate().peekable();
+    let mut cues = Vec::new();
+    while let Some((i, line)) = lines.next() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (i, timing) = if line.bytes().all(|b| b.is_ascii_digit()) {
+            match lines.next() {
+                Some((i, timing)) => (i, timing),
+                None => return Err(error(i, "expected the cue times after the cue number")),
+            }
+        } else {
+            (i, line)
+        };
+        let (start, end) = parse_timing(timing).ok_or_else(|| {
+            error(
+                i,
+                "expected the cue times, like `00:00:01,000 --> 00:00:02,500`",
+            )
+        })?;
+
+        let mut text = Vec::new();
+        while let Some((_, line)) = lines.next_if(|(_, line)| !line.trim().is_empty()) {
+            text.push(line.trim_end());
+        }
+        cues.push(SubtitleCue {
+            start,
+            end,
+            text: text.join("\n"),
+        });
+    }
+    Ok(cues)
+}
+
+/// Write `cues` as an SRT file, numbering them from 1.
+pub fn write_srt(cues: &[SubtitleCue]) -> String {
+    let mut out = String::new();
+    for (i, cue) in cues.iter().enumerate() {
+        out.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            i + 1,
+            format_time(cue.start),
+            format_time(cue.end),
+            cue.text
+        ));
+    }
+    out
+}
+
+fn error(line: usize, message: &str) -> SubtitleParseError {
+    SubtitleParseError {
+        line: line + 1,
+        message: message.to_string(),
+    }
+}
+
+fn parse_timing(line: &str) -> Option<(Duration, Duration)> {
+    let (start, end) = line.split_once("-->")?;
+    let end = end.split_whitespace().next()?;
+    Some((parse_time(start.trim())?, parse_time(end)?))
+}
+
+fn parse_time(time: &str) -> Option<Duration> {
+    let (hms, millis) = time.split_once([',', '.'])?;
+    let mut parts = hms.split(':');
+    let (hours, minutes, seconds) = (parts.next()?, parts.next()?, parts.next()?);
+    if parts.next().is_some() || millis.len() != 3 {
+        return None;
+    }
+    let field = |s: &str| {
+        s.bytes()
+            .all(|b| b.is_ascii_digit())
+            .then(|| s.parse::<u64>().ok())
+            .flatten()
+    };
+    let (minutes, seconds) = (field(minutes)?, field(seconds)?);
+    if minutes >= 60 || seconds >= 60 {
+        return None;
+    }
+    let seconds = field(hours)? * 3600 + minutes * 60 + seconds;
+    Some(Duration::from_millis(seconds * 1000 + field(millis)?))
+}
+
+fn format_time(time: Duration) -> String {
+    let millis = time.as_millis();
+    format!(
+        "{:02}:{:02}:{:02},{:03}",
+        millis / 3_600_000,
+        millis / 60_000 % 60,
+        millis / 1000 % 60,
+        millis % 1000
+    )
+}
+
+/// The result of [`resync_subtitles`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResyncedSubtitles {
+    /// The cues with corrected times, in the same order as given.
+    pub cues: Vec<SubtitleCue>,
+    /// Indices of the cues whose text was not found in the transcript.
+    /// They are shifted by as much as the closest matched cue before them,
+    /// or after them if there is none before.
+    pub unmatched: Vec<usize>,
+}
+
+/// Correct the times of subtitle cues that drifted out of sync with the audio.
+///
+/// The text of each cue is looked up in `words`, a fresh transcription of the audio,
+/// usually from [`WhisperState::words`](crate::WhisperState::words).
+/// Matching ignores case, punctuation and formatting tags, and tolerates small spelling
+/// differences and words missing on either side, since subtitles are rarely verbatim.
+/// A cue is matched if at least half of its words are found in order,
+/// and its new times are those of the first and last of them.
+///
+/// Cues are searched for in order, each no further than a few hundred words
+/// after the previous match, so cues are expected to be in the order they are spoken.
+///
+/// # Examples
+/// ```no_run
+/// # use whisper_rs::{FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters};
+/// # let ctx = WhisperContext::new_with_params("model.bin", WhisperContextParameters::default()).unwrap();
+/// # let audio = vec![0.0f32; 16000];
+/// let cues = whisper_rs::parse_srt(&std::fs::read_to_string("movie.srt").unwrap()).unwrap();
+///
+/// let mut params = FullParams::new(SamplingStrategy::Greedy { best_of: 1 });
+/// params.set_token_timestamps(true);
+/// let mut state = ctx.create_state().unwrap();
+/// state.full(params, &audio).unwrap();
+///
+/// let resynced = whisper_rs::resync_subtitles(&cues, &state.words().unwrap());
+/// for &i in &resynced.unmatched {
+///     eprintln!("could not find {:?}", cues[i].text);
+/// }
+/// std::fs::write("movie.resynced.srt", whisper_rs::write_srt(&resynced.cues)).unwrap();
+/// ```
+pub fn resync_subtitles(cues: &[SubtitleCue], words: &[AlignedWord]) -> ResyncedSubtitles {
+    let transcript: Vec<String> = words.iter().map(|word| normalize(&word.text)).collect();
+    let mut times: Vec<Option<(Duration, Duration)>> = Vec::with_capacity(cues.len());
+    let mut cursor = 0;
+    for cue in cues {
+        let cue_words: Vec<String> = strip_tags(&cue.text)
+            .split_whitespace()
+            .map(normalize)
+            .filter(|word| !word.is_empty())
+            .collect();
+        let window_end = transcript
+            .len()
+            .min(cursor + SEARCH_WORDS + cue_words.len());
+        let found = match_words(&cue_words, &transcript[cursor..window_end])
+            .filter(|m| m.matches as f32 >= (cue_words.len() as f32 * MIN_MATCH).max(1.0));
+        times.push(found.map(|m| {
+            let (first, last) = (&words[cursor + m.first], &words[cursor + m.last]);
+            cursor += m.last + 1;
+            let start = centiseconds(first.start);
+            (start, centiseconds(last.end).max(start))
+        }));
+    }
+
+    let offsets: Vec<Option<i128>> = cues
+        .iter()
+        .zip(&times)
+        .map(|(cue, time)| {
+            time.map(|(start, _)| start.as_millis() as i128 - cue.start.as_millis() as i128)
+        })
+        .collect();
+    let mut resynced = ResyncedSubtitles {
+        cues: Vec::with_capacity(cues.len()),
+        unmatched: Vec::new(),
+    };
+    for (i, cue) in cues.iter().enumerate() {
+        let (start, end) = match times[i] {
+            Some(time) => time,
+            None => {
+                resynced.unmatched.push(i);
+                let offset = offsets[..i]
+                    .iter()
+                    .rev()
+                    .chain(&offsets[i..])
+                    .find_map(|&offset| offset)
+                    .unwrap_or(0);
+                (shift(cue.start, offset), shift(cue.end, offset))
+            }
+        };
+        resynced.cues.push(SubtitleCue {
+            start,
+            end,
+            text: cue.text.clone(),
+        });
+    }
+    resynced
+}
+
+fn centiseconds(time: i64) -> Duration {
+    Duration::from_millis(time.max(0) as u64 * 10)
+}
+
+fn shift(time: Duration, offset_millis: i128) -> Duration {
+    Duration::from_millis((time.as_millis() as i128 + offset_millis).max(0) as u64)
+}
+
+/// Remove `<i>`-style HTML tags and `{\an8}`-style ASS overrides.
+fn strip_tags(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut closing = None;
+    for c in text.chars() {
+        match (closing, c) {
+            (None, '<') => closing = Some('>'),
+            (None, '{') => closing = Some('}'),
+            (None, c) => out.push(c),
+            (Some(end), c) if c == end => closing = None,
+            _ => {}
+        }
+    }
+    out
+}
+
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+fn similar(a: &str, b: &str) -> bool {
+    if a.is_empty() || b.is_empty() {
+        return false;
+    }
+    if a == b {
+        return true;
+    }
+    let len = a.chars().count().min(b.chars().count());
+    let allowed = match len {
+        0..=3 => 0,
+        4..=7 => 1,
+        _ => 2,
+    };
+    allowed > 0 && edit_distance(a, b) <= allowed
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Where the words of a cue were found in a stretch of the transcript.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct WordMatch {
+    first: usize,
+    last: usize,
+    matches: usize,
+}
+
+/// Align all of `cue` against the best-fitting part of `transcript`, allowing words to be
+/// missing on either side, and return where its matched words were found.
+fn match_words(cue: &[String], transcript: &[String]) -> Option<WordMatch> {
+    const MATCH: i32 = 2;
+    const GAP: i32 = -1;
+
+    #[derive(Clone, Copy)]
+    struct Cell {
+        score: i32,
+        matched: Option<WordMatch>,
+    }
+    let start = Cell {
+        score: 0,
+        matched: None,
+    };
+    // row i holds the best alignments of the first i cue words ending at each transcript word,
+    // where row 0 is free so the cue can start anywhere
+    let mut previous = vec![start; transcript.len() + 1];
+    for word in cue {
+        let mut row = Vec::with_capacity(transcript.len() + 1);
+        row.push(Cell {
+            score: previous[0].score + GAP,
+            matched: previous[0].matched,
+        });
+        for (j, spoken) in transcript.iter().enumerate() {
+            let diagonal = previous[j];
+            let mut best = if similar(word, spoken) {
+                let matched = match diagonal.matched {
+                    Some(m) => WordMatch {
+                        last: j,
+                        matches: m.matches + 1,
+                        ..m
+                    },
+                    None => WordMatch {
+                        first: j,
+                        last: j,
+                        matches: 1,
+                    },
+                };
+                Cell {
+                    score: diagonal.score + MATCH,
+                    matched: Some(matched),
+                }
+            } else {
+                Cell {
+                    score: diagonal.score + GAP,
+                    ..diagonal
+                }
+            };
+            // skip the cue word, or the spoken word
+            for other in [previous[j + 1], row[j]] {
+                if other.score + GAP > best.score {
+                    best = Cell {
+                        score: other.score + GAP,
+                        ..other
+                    };
+                }
+            }
+            row.push(best);
+        }
+        previous = row;
+    }
+    previous
+        .iter()
+        .filter_map(|cell| cell.matched.map(|m| (cell.score, m)))
+        .max_by(|a, b| a.0.cmp(&b.0).then(b.1.first.cmp(&a.1.first)))
+        .map(|(_, m)| m)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SRT: &str = "\u{feff}1\r\n00:00:01,000 --> 00:00:02,500\r\n<i>Hello there.</i>\r\n\r\n2\r\n00:00:03,000 --> 00:00:05,250 X1:10\r\nGeneral Kenobi!\r\nYou are a bold one.\r\n\r\n";
+
+    fn words(text: &str, start: i64) -> Vec<AlignedWord> {
+        text.split_whitespace()
+            .enumerate()
+            .map(|(i, word)| AlignedWord {
+                text: word.to_string(),
+                start: start + 50 * i as i64,
+                end: start + 50 * i as i64 + 40,
+                probability: 1.0,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_srt() {
+        let cues = parse_srt(SRT).unwrap();
+        assert_eq!(cues.len(), 2);
+        assert_eq!(cues[0].start, Duration::from_millis(1000));
+        assert_eq!(cues[0].end, Duration::from_millis(2500));
+        assert_eq!(cues[0].text, "<i>Hello there.</i>");
+        assert_eq!(cues[1].end, Duration::from_millis(5250));
+        assert_eq!(cues[1].text, "General Kenobi!\nYou are a bold one.");
+    }
+
+    #[test]
+    fn test_write_srt_round_trip() {
+        let cues = parse_srt(SRT).unwrap();
+        let written = write_srt(&cues);
+        assert!(written.starts_with("1\n00:00:01,000 --> 00:00:02,500\n<i>Hello there.</i>\n\n2\n"));
+        assert_eq!(parse_srt(&written).unwrap(), cues);
+        assert_eq!(
+            format_time(Duration::from_millis(3_725_042)),
+            "01:02:05,042"
+        );
+    }
+
+    #[test]
+    fn test_parse_srt_errors() {
+        let err = parse_srt("1\n00:00:01,000 -> 00:00:02,000\nHi\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        let err = parse_srt("1\n00:00:01,000 --> 00:00:02,000\nHi\n\n2\n").unwrap_err();
+        assert_eq!(err.line, 5);
+        assert!(parse_srt("00:61:00,000 --> 01:00:00,000\nHi").is_err());
+    }
+
+    #[test]
+    fn test_resync_shifts_cues_to_speech() {
+        let cues = parse_srt(SRT).unwrap();
+        let words = words("um hello there general kenobi you are a bold one", 1000);
+        let resynced = resync_subtitles(&cues, &words);
+        assert!(resynced.unmatched.is_empty());
+        // "hello" is the second word and "there" the third
+        assert_eq!(resynced.cues[0].start, Duration::from_millis(10_500));
+        assert_eq!(resynced.cues[0].end, Duration::from_millis(11_400));
+        assert_eq!(resynced.cues[1].start, Duration::from_millis(11_500));
+        assert_eq!(resynced.cues[1].end, Duration::from_millis(14_900));
+        assert_eq!(resynced.cues[1].text, cues[1].text);
+    }
+
+    #[test]
+    fn test_resync_tolerates_differences() {
+        let cues =
+            parse_srt("00:00:01,000 --> 00:00:02,000\nI love the colour of the sea").unwrap();
+        let words = words("so I really love the color of this sea", 0);
+        let resynced = resync_subtitles(&cues, &words);
+        assert!(resynced.unmatched.is_empty());
+        assert_eq!(resynced.cues[0].start, Duration::from_millis(500));
+        assert_eq!(resynced.cues[0].end, Duration::from_millis(4400));
+    }
+
+    #[test]
+    fn test_resync_reports_unmatched_cues() {
+        let cues = parse_srt(
+            "00:00:01,000 --> 00:00:02,000\nGood morning\n\n\
+             00:00:03,000 --> 00:00:04,000\n[door slams]\n\n\
+             00:00:05,000 --> 00:00:06,000\nWho is there",
+        )
+        .unwrap();
+        let words = words("good morning who is there", 300);
+        let resynced = resync_subtitles(&cues, &words);
+        assert_eq!(resynced.unmatched, [1]);
+        // shifted like the cue before it, which moved two seconds later
+        assert_eq!(resynced.cues[0].start, Duration::from_millis(3000));
+        assert_eq!(resynced.cues[1].start, Duration::from_millis(5000));
+        assert_eq!(resynced.cues[1].end, Duration::from_millis(6000));
+        assert_eq!(resynced.cues[2].start, Duration::from_millis(4000));
+    }
+
+    #[test]
+    fn test_match_words_prefers_compact_match() {
+        let transcript: Vec<String> = "a b x x x x c a b c"
+            .split(' ')
+            .map(str::to_string)
+            .collect();
+        let cue: Vec<String> = ["a", "b", "c"].iter().map(|w| w.to_string()).collect();
+        let found = match_words(&cue, &transcript).unwrap();
+        assert_eq!((found.first, found.last, found.matches), (7, 9, 3));
+    }
+
+    #[test]
+    fn test_edit_distance() {
+        assert_eq!(edit_distance("colour", "color"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert!(similar("colour", "color"));
+        assert!(!similar("cat", "cut"));
+    }
+}
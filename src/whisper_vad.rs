@@ -87,6 +87,10 @@ impl WhisperVadParams {
         self.params.samples_overlap = samples_overlap;
     }
 
+    pub(crate) fn from_inner(params: whisper_vad_params) -> Self {
+        Self { params }
+    }
+
     pub(crate) fn into_inner(self) -> whisper_vad_params {
         self.params
     }
@@ -292,7 +296,7 @@ impl Iterator for WhisperVadSegments {
     }
 }
 
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct WhisperVadSegment {
     /// Start timestamp of this segment in centiseconds.
     pub start: f32,